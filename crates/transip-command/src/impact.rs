@@ -0,0 +1,524 @@
+use crate::{
+    ACME_CHALLENGE, AuditCommand, DnsCommand, DnssecCommand, DomainCommand, EmailBoxCommand,
+    EmailForwardCommand, InvoiceCommand, ProductCommand, REDACTED, SubCommand, VpsCommand,
+};
+
+/// Effect of a command on the resources of the account
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Impact {
+    /// Reads resources or changes settings of the interpreter only
+    ReadOnly,
+    /// Adds or changes resources
+    Mutating,
+    /// Removes resources, interrupts a running vps or moves a domain to other nameservers
+    Destructive,
+}
+
+/// Resource a command reads or changes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Resource<'a> {
+    /// Settings of the interpreter, no api call is made
+    Settings,
+    /// Resources of the account that do not belong to a domain or vps
+    Account,
+    Domain(&'a str),
+    DnsRecord {
+        domain: &'a str,
+        name: &'a str,
+    },
+    Mailbox {
+        domain: &'a str,
+        id: &'a str,
+    },
+    MailForward {
+        domain: &'a str,
+        id: &'a str,
+    },
+    Vps(&'a str),
+}
+
+impl Resource<'_> {
+    pub fn domain(&self) -> Option<&str> {
+        match self {
+            Resource::Domain(domain)
+            | Resource::DnsRecord { domain, .. }
+            | Resource::Mailbox { domain, .. }
+            | Resource::MailForward { domain, .. } => Some(domain),
+            _ => None,
+        }
+    }
+}
+
+impl SubCommand {
+    /// True if the command adds, changes or removes resources
+    pub fn is_mutating(&self) -> bool {
+        self.impact() != Impact::ReadOnly
+    }
+
+    /// Name of the command as typed, without arguments, like `dns acme-validation-set`
+    pub fn name(&self) -> &'static str {
+        match self {
+            SubCommand::Audit(AuditCommand::Show { .. }) => "audit show",
+            SubCommand::AvailibilityZones => "availibility-zones",
+            SubCommand::Comment { .. } => "comment",
+            SubCommand::Confirm { .. } => "confirm",
+            SubCommand::Dns(command) => match command {
+                DnsCommand::AcmeValidationDelete { .. } => "dns acme-validation-delete",
+                DnsCommand::AcmeValidationSet { .. } => "dns acme-validation-set",
+                #[cfg(feature = "propagation")]
+                DnsCommand::AcmeValidationCheck { .. } => "dns acme-validation-check",
+                DnsCommand::Delete(_) => "dns delete",
+                DnsCommand::Insert(_) => "dns insert",
+                DnsCommand::List { .. } => "dns list",
+            },
+            SubCommand::Domain(command) => match command {
+                DomainCommand::List => "domain list",
+                DomainCommand::Item { .. } => "domain item",
+                DomainCommand::Dnssec(command) => match command {
+                    DnssecCommand::List { .. } => "domain dnssec list",
+                    DnssecCommand::Set { .. } => "domain dnssec set",
+                    DnssecCommand::Delete { .. } => "domain dnssec delete",
+                },
+                DomainCommand::Nameservers { .. } => "domain nameservers",
+                DomainCommand::NameserversSet { .. } => "domain nameservers-set",
+            },
+            SubCommand::Dryrun { .. } => "dryrun",
+            SubCommand::EmailBox(command) => match command {
+                EmailBoxCommand::List { .. } => "email-box list",
+                EmailBoxCommand::Item { .. } => "email-box item",
+                EmailBoxCommand::Delete { .. } => "email-box delete",
+                EmailBoxCommand::Insert { .. } => "email-box insert",
+            },
+            SubCommand::EmailForward(command) => match command {
+                EmailForwardCommand::List { .. } => "email-forward list",
+                EmailForwardCommand::Item { .. } => "email-forward item",
+                EmailForwardCommand::Delete { .. } => "email-forward delete",
+                EmailForwardCommand::Insert { .. } => "email-forward insert",
+            },
+            SubCommand::End => "end",
+            SubCommand::Invoice(command) => match command {
+                InvoiceCommand::List => "invoice list",
+                InvoiceCommand::Item { .. } => "invoice item",
+                InvoiceCommand::Pdf { .. } => "invoice pdf",
+            },
+            SubCommand::Onerror { .. } => "onerror",
+            SubCommand::Parallel { .. } => "parallel",
+            SubCommand::Ping => "ping",
+            SubCommand::Product(command) => match command {
+                ProductCommand::List => "product list",
+                ProductCommand::Elements { .. } => "product elements",
+            },
+            SubCommand::Ratelimit => "ratelimit",
+            SubCommand::Retry { .. } => "retry",
+            SubCommand::Sleep { .. } => "sleep",
+            SubCommand::Vps(command) => match command {
+                VpsCommand::List => "vps list",
+                VpsCommand::Item { .. } => "vps item",
+                VpsCommand::Start { .. } => "vps start",
+                VpsCommand::Stop { .. } => "vps stop",
+                VpsCommand::Reset { .. } => "vps reset",
+                VpsCommand::Lock { .. } => "vps lock",
+                VpsCommand::Unlock { .. } => "vps unlock",
+            },
+        }
+    }
+
+    /// Resource the command reads or changes
+    pub fn resource(&self) -> Resource<'_> {
+        match self {
+            SubCommand::Dns(command) => match command {
+                DnsCommand::AcmeValidationDelete { domain, .. }
+                | DnsCommand::AcmeValidationSet { domain, .. } => Resource::DnsRecord {
+                    domain,
+                    name: ACME_CHALLENGE,
+                },
+                #[cfg(feature = "propagation")]
+                DnsCommand::AcmeValidationCheck { domain, .. } => Resource::DnsRecord {
+                    domain,
+                    name: ACME_CHALLENGE,
+                },
+                DnsCommand::Delete(entry) | DnsCommand::Insert(entry) => Resource::DnsRecord {
+                    domain: &entry.domain,
+                    name: &entry.name,
+                },
+                DnsCommand::List { domain } => Resource::Domain(domain),
+            },
+            SubCommand::Domain(
+                DomainCommand::Item { domain }
+                | DomainCommand::Nameservers { domain }
+                | DomainCommand::NameserversSet { domain, .. }
+                | DomainCommand::Dnssec(
+                    DnssecCommand::List { domain }
+                    | DnssecCommand::Set { domain, .. }
+                    | DnssecCommand::Delete { domain },
+                ),
+            ) => Resource::Domain(domain),
+            SubCommand::EmailBox(command) => match command {
+                EmailBoxCommand::List { domain } => Resource::Domain(domain),
+                EmailBoxCommand::Item { domain, id } | EmailBoxCommand::Delete { domain, id } => {
+                    Resource::Mailbox { domain, id }
+                }
+                EmailBoxCommand::Insert {
+                    domain, username, ..
+                } => Resource::Mailbox {
+                    domain,
+                    id: username,
+                },
+            },
+            SubCommand::EmailForward(command) => match command {
+                EmailForwardCommand::List { domain } => Resource::Domain(domain),
+                EmailForwardCommand::Item { domain, id }
+                | EmailForwardCommand::Delete { domain, id } => {
+                    Resource::MailForward { domain, id }
+                }
+                EmailForwardCommand::Insert {
+                    domain, local_part, ..
+                } => Resource::MailForward {
+                    domain,
+                    id: local_part,
+                },
+            },
+            SubCommand::Vps(command) => match command {
+                VpsCommand::List => Resource::Account,
+                VpsCommand::Item { name }
+                | VpsCommand::Start { name }
+                | VpsCommand::Stop { name }
+                | VpsCommand::Reset { name }
+                | VpsCommand::Lock { name }
+                | VpsCommand::Unlock { name } => Resource::Vps(name),
+            },
+            SubCommand::AvailibilityZones
+            | SubCommand::Domain(DomainCommand::List)
+            | SubCommand::Invoice(_)
+            | SubCommand::Ping
+            | SubCommand::Product(_) => Resource::Account,
+            SubCommand::Audit(_)
+            | SubCommand::Comment { .. }
+            | SubCommand::Confirm { .. }
+            | SubCommand::Dryrun { .. }
+            | SubCommand::End
+            | SubCommand::Onerror { .. }
+            | SubCommand::Parallel { .. }
+            | SubCommand::Ratelimit
+            | SubCommand::Retry { .. }
+            | SubCommand::Sleep { .. } => Resource::Settings,
+        }
+    }
+
+    pub fn impact(&self) -> Impact {
+        match self {
+            SubCommand::Dns(command) => match command {
+                // without append the challenges of the record are replaced
+                DnsCommand::Delete(_)
+                | DnsCommand::AcmeValidationDelete { .. }
+                | DnsCommand::AcmeValidationSet { append: false, .. } => Impact::Destructive,
+                DnsCommand::AcmeValidationSet { append: true, .. } | DnsCommand::Insert(_) => {
+                    Impact::Mutating
+                }
+                _ => Impact::ReadOnly,
+            },
+            SubCommand::Domain(
+                DomainCommand::NameserversSet { .. }
+                | DomainCommand::Dnssec(DnssecCommand::Set { .. } | DnssecCommand::Delete { .. }),
+            ) => Impact::Destructive,
+            SubCommand::EmailBox(command) => match command {
+                EmailBoxCommand::Delete { .. } => Impact::Destructive,
+                EmailBoxCommand::Insert { .. } => Impact::Mutating,
+                _ => Impact::ReadOnly,
+            },
+            SubCommand::EmailForward(command) => match command {
+                EmailForwardCommand::Delete { .. } => Impact::Destructive,
+                EmailForwardCommand::Insert { .. } => Impact::Mutating,
+                _ => Impact::ReadOnly,
+            },
+            SubCommand::Vps(command) => match command {
+                VpsCommand::Reset { .. } | VpsCommand::Stop { .. } => Impact::Destructive,
+                VpsCommand::Lock { .. } | VpsCommand::Start { .. } | VpsCommand::Unlock { .. } => {
+                    Impact::Mutating
+                }
+                _ => Impact::ReadOnly,
+            },
+            _ => Impact::ReadOnly,
+        }
+    }
+
+    /// Mutating or destructive command as it would be typed, with the mailbox password redacted
+    pub fn canonical(&self) -> Option<String> {
+        let args = match self {
+            SubCommand::Dns(command) => match command {
+                DnsCommand::AcmeValidationDelete {
+                    domain,
+                    challenge,
+                    follow_cname,
+                } => {
+                    let mut args = std::iter::once(domain)
+                        .chain(challenge)
+                        .cloned()
+                        .collect::<Vec<_>>();
+                    if *follow_cname {
+                        args.push("--follow-cname".to_owned());
+                    }
+                    args
+                }
+                DnsCommand::AcmeValidationSet {
+                    domain,
+                    challenge,
+                    append,
+                    follow_cname,
+                } => {
+                    let mut args = vec![domain.clone(), challenge.clone()];
+                    if *append {
+                        args.push("--append".to_owned());
+                    }
+                    if *follow_cname {
+                        args.push("--follow-cname".to_owned());
+                    }
+                    args
+                }
+                DnsCommand::Delete(entry) | DnsCommand::Insert(entry) => vec![
+                    entry.domain.clone(),
+                    entry.name.clone(),
+                    entry.ttl.to_string(),
+                    format!("{:?}", entry.r#type),
+                    entry.content.clone(),
+                ],
+                _ => return None,
+            },
+            SubCommand::Domain(DomainCommand::NameserversSet {
+                domain,
+                nameservers,
+            }) => std::iter::once(domain.clone())
+                .chain(nameservers.iter().map(ToString::to_string))
+                .collect(),
+            SubCommand::Domain(DomainCommand::Dnssec(command)) => match command {
+                DnssecCommand::Set { domain, files } => std::iter::once(domain.clone())
+                    .chain(files.iter().map(|file| file.display().to_string()))
+                    .collect(),
+                DnssecCommand::Delete { domain } => vec![domain.clone()],
+                _ => return None,
+            },
+            SubCommand::EmailBox(command) => match command {
+                EmailBoxCommand::Delete { domain, id } => vec![domain.clone(), id.clone()],
+                EmailBoxCommand::Insert {
+                    domain,
+                    username,
+                    mb_size,
+                    ..
+                } => vec![
+                    domain.clone(),
+                    username.clone(),
+                    REDACTED.to_owned(),
+                    mb_size.to_string(),
+                ],
+                _ => return None,
+            },
+            SubCommand::EmailForward(command) => match command {
+                EmailForwardCommand::Delete { domain, id } => vec![domain.clone(), id.clone()],
+                EmailForwardCommand::Insert {
+                    domain,
+                    local_part,
+                    forward_to,
+                } => vec![domain.clone(), local_part.clone(), forward_to.clone()],
+                _ => return None,
+            },
+            SubCommand::Vps(command) => match command {
+                VpsCommand::Lock { name }
+                | VpsCommand::Reset { name }
+                | VpsCommand::Start { name }
+                | VpsCommand::Stop { name }
+                | VpsCommand::Unlock { name } => vec![name.clone()],
+                _ => return None,
+            },
+            _ => return None,
+        };
+        shlex::try_join(args.iter().map(String::as_str))
+            .ok()
+            .map(|args| format!("{} {args}", self.name()))
+    }
+
+    /// Resource changed by a mutating or destructive command, like `vps vps1`
+    pub fn target(&self) -> Option<String> {
+        match self {
+            SubCommand::Dns(command) => match command {
+                DnsCommand::AcmeValidationDelete {
+                    domain,
+                    challenge: Some(challenge),
+                    ..
+                } => Some(format!("acme challenge {challenge} of {domain}")),
+                DnsCommand::AcmeValidationDelete { domain, .. }
+                | DnsCommand::AcmeValidationSet { domain, .. } => {
+                    Some(format!("acme challenge of {domain}"))
+                }
+                DnsCommand::Delete(entry) | DnsCommand::Insert(entry) => Some(format!(
+                    "dns entry {} {} {:?} {} of {}",
+                    entry.name, entry.ttl, entry.r#type, entry.content, entry.domain
+                )),
+                _ => None,
+            },
+            SubCommand::Domain(DomainCommand::NameserversSet { domain, .. }) => {
+                Some(format!("nameservers of {domain}"))
+            }
+            SubCommand::Domain(DomainCommand::Dnssec(
+                DnssecCommand::Set { domain, .. } | DnssecCommand::Delete { domain },
+            )) => Some(format!("dnssec entries of {domain}")),
+            SubCommand::EmailBox(command) => match command {
+                EmailBoxCommand::Delete { domain, id } => Some(format!("mailbox {id} of {domain}")),
+                EmailBoxCommand::Insert {
+                    domain, username, ..
+                } => Some(format!("mailbox {username}@{domain}")),
+                _ => None,
+            },
+            SubCommand::EmailForward(command) => match command {
+                EmailForwardCommand::Delete { domain, id } => {
+                    Some(format!("mail forward {id} of {domain}"))
+                }
+                EmailForwardCommand::Insert {
+                    domain, local_part, ..
+                } => Some(format!("mail forward {local_part}@{domain}")),
+                _ => None,
+            },
+            SubCommand::Vps(command) => match command {
+                VpsCommand::Lock { name }
+                | VpsCommand::Reset { name }
+                | VpsCommand::Start { name }
+                | VpsCommand::Stop { name }
+                | VpsCommand::Unlock { name } => Some(format!("vps {name}")),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Impact, Resource};
+    use crate::TransipCommand;
+
+    fn impact(line: &str) -> (Impact, Option<String>) {
+        let command = line.parse::<TransipCommand>().unwrap().command;
+        (command.impact(), command.target())
+    }
+
+    #[test]
+    fn classification() {
+        assert_eq!(impact("dns list example.nl"), (Impact::ReadOnly, None));
+        assert_eq!(impact("confirm off"), (Impact::ReadOnly, None));
+        assert_eq!(
+            impact("vps start vps1"),
+            (Impact::Mutating, Some("vps vps1".to_owned()))
+        );
+        assert_eq!(
+            impact("vps reset vps1"),
+            (Impact::Destructive, Some("vps vps1".to_owned()))
+        );
+        assert_eq!(
+            impact("dns delete example.nl www 300 A 1.2.3.4"),
+            (
+                Impact::Destructive,
+                Some("dns entry www 300 A 1.2.3.4 of example.nl".to_owned())
+            )
+        );
+        assert_eq!(
+            impact("dns acme-validation-delete example.nl"),
+            (
+                Impact::Destructive,
+                Some("acme challenge of example.nl".to_owned())
+            )
+        );
+        assert_eq!(
+            impact("dns acme-validation-set example.nl abc").0,
+            Impact::Destructive
+        );
+        assert_eq!(
+            impact("dns acme-validation-set example.nl abc --append").0,
+            Impact::Mutating
+        );
+        assert_eq!(
+            impact("email-box delete example.nl info@example.nl"),
+            (
+                Impact::Destructive,
+                Some("mailbox info@example.nl of example.nl".to_owned())
+            )
+        );
+        assert_eq!(
+            impact("domain nameservers example.nl"),
+            (Impact::ReadOnly, None)
+        );
+        assert_eq!(
+            impact("domain dnssec list example.nl"),
+            (Impact::ReadOnly, None)
+        );
+        assert_eq!(
+            impact("domain dnssec delete example.nl"),
+            (
+                Impact::Destructive,
+                Some("dnssec entries of example.nl".to_owned())
+            )
+        );
+        assert_eq!(
+            impact("domain nameservers-set example.nl ns0.transip.net ns1.transip.nl"),
+            (
+                Impact::Destructive,
+                Some("nameservers of example.nl".to_owned())
+            )
+        );
+    }
+
+    #[test]
+    fn name_and_resource() {
+        let command = "dns acme-validation-set example.nl abc"
+            .parse::<TransipCommand>()
+            .unwrap()
+            .command;
+        assert_eq!(command.name(), "dns acme-validation-set");
+        assert!(command.is_mutating());
+        assert_eq!(
+            command.resource(),
+            Resource::DnsRecord {
+                domain: "example.nl",
+                name: "_acme-challenge"
+            }
+        );
+        assert_eq!(command.resource().domain(), Some("example.nl"));
+
+        let command = "vps list".parse::<TransipCommand>().unwrap().command;
+        assert_eq!(command.canonical(), None);
+        assert_eq!(command.name(), "vps list");
+        assert!(!command.is_mutating());
+        assert_eq!(command.resource(), Resource::Account);
+    }
+
+    #[test]
+    fn canonical() {
+        let canonical = |line: &str| line.parse::<TransipCommand>().unwrap().command.canonical();
+        assert_eq!(
+            canonical(r#"dns insert example.nl  www 300 TXT "a  b""#).as_deref(),
+            Some("dns insert example.nl www 300 TXT 'a  b'")
+        );
+        assert_eq!(
+            canonical("email-box insert example.nl info secret 100").as_deref(),
+            Some("email-box insert example.nl info '********' 100")
+        );
+        assert_eq!(
+            canonical("dns acme-validation-set example.nl abc --append").as_deref(),
+            Some("dns acme-validation-set example.nl abc --append")
+        );
+        assert_eq!(
+            canonical("dns acme-validation-delete example.nl abc").as_deref(),
+            Some("dns acme-validation-delete example.nl abc")
+        );
+        assert_eq!(
+            canonical("dns acme-validation-delete example.nl --follow-cname").as_deref(),
+            Some("dns acme-validation-delete example.nl --follow-cname")
+        );
+        assert_eq!(
+            canonical(
+                "domain nameservers-set example.nl NS1.example.nl.=192.0.2.1 ns0.transip.net"
+            )
+            .as_deref(),
+            Some("domain nameservers-set example.nl 'ns1.example.nl=192.0.2.1' ns0.transip.net")
+        );
+        assert_eq!(canonical("audit show --since 2024-01-31"), None);
+    }
+}
@@ -1,9 +1,20 @@
-use std::str::FromStr;
+#[cfg(feature = "propagation")]
+use std::net::SocketAddr;
+use std::{
+    fmt::Display,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::PathBuf,
+    str::FromStr,
+};
 
 pub use clap::error::ErrorKind;
 use clap::{Error, Parser, Subcommand, ValueEnum};
 
-#[derive(Clone, Debug, ValueEnum)]
+pub use impact::{Impact, Resource};
+
+mod impact;
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
 #[value(rename_all = "UPPER")]
 pub enum RecordType {
     A,
@@ -21,6 +32,12 @@ pub enum OnError {
     Exit,
 }
 
+#[derive(Clone, Debug, ValueEnum, PartialEq)]
+pub enum Switch {
+    On,
+    Off,
+}
+
 #[derive(Clone, Debug, Parser)]
 pub struct DnsEntry {
     pub domain: String,
@@ -30,31 +47,246 @@ pub struct DnsEntry {
     pub content: String,
 }
 
+/// Nameserver of a domain, with the glue addresses a nameserver inside the domain needs
+///
+/// Written as `ns1.example.nl=192.0.2.1,2001:db8::1`, or as the hostname alone without glue.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Nameserver {
+    pub hostname: String,
+    pub ipv4: Option<Ipv4Addr>,
+    pub ipv6: Option<Ipv6Addr>,
+}
+
+impl Nameserver {
+    /// True if the hostname is domain or a name below it, so resolvers can only find it with glue
+    pub fn in_zone(&self, domain: &str) -> bool {
+        let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+        self.hostname == domain || self.hostname.ends_with(&format!(".{domain}"))
+    }
+
+    pub fn has_glue(&self) -> bool {
+        self.ipv4.is_some() || self.ipv6.is_some()
+    }
+}
+
+fn is_hostname(s: &str) -> bool {
+    s.len() <= 253
+        && s.split('.').count() > 1
+        && s.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+impl FromStr for Nameserver {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (hostname, glue) = s.split_once('=').unwrap_or((s, ""));
+        let hostname = hostname.trim_end_matches('.').to_ascii_lowercase();
+        if !is_hostname(&hostname) {
+            return Err(format!("{hostname} is not a hostname"));
+        }
+        let mut nameserver = Nameserver {
+            hostname,
+            ipv4: None,
+            ipv6: None,
+        };
+        for address in glue.split(',').filter(|address| !address.is_empty()) {
+            match address.parse::<IpAddr>() {
+                Ok(IpAddr::V4(ipv4)) if nameserver.ipv4.is_none() => nameserver.ipv4 = Some(ipv4),
+                Ok(IpAddr::V6(ipv6)) if nameserver.ipv6.is_none() => nameserver.ipv6 = Some(ipv6),
+                Ok(_) => {
+                    return Err(format!(
+                        "{} takes one ipv4 and one ipv6 address",
+                        nameserver.hostname
+                    ));
+                }
+                Err(_) => return Err(format!("{address} is not an ip address")),
+            }
+        }
+        Ok(nameserver)
+    }
+}
+
+impl Display for Nameserver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let glue = self
+            .ipv4
+            .map(IpAddr::V4)
+            .into_iter()
+            .chain(self.ipv6.map(IpAddr::V6))
+            .map(|address| address.to_string())
+            .collect::<Vec<_>>();
+        if glue.is_empty() {
+            write!(f, "{}", self.hostname)
+        } else {
+            write!(f, "{}={}", self.hostname, glue.join(","))
+        }
+    }
+}
+
+/// Nameservers queried by `dns acme-validation-check`
+#[cfg(feature = "propagation")]
+#[derive(Clone, Debug, PartialEq)]
+pub enum Nameservers {
+    /// Authoritative nameservers of the domain, found with a public resolver
+    Authoritative,
+    /// Public resolvers of Google, Cloudflare and Quad9
+    Public,
+    /// Comma separated addresses, with port 53 if no port is given
+    Custom(Vec<SocketAddr>),
+}
+
+/// Address of a nameserver, with port 53 if no port is given
+#[cfg(feature = "propagation")]
+fn nameserver_address(address: &str) -> Result<SocketAddr, String> {
+    address
+        .parse::<SocketAddr>()
+        .or_else(|_| address.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 53)))
+        .map_err(|_| format!("{address} is not an ip address"))
+}
+
+#[cfg(feature = "propagation")]
+impl FromStr for Nameservers {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "authoritative" => Ok(Nameservers::Authoritative),
+            "public" => Ok(Nameservers::Public),
+            _ => s
+                .split(',')
+                .map(|address| {
+                    nameserver_address(address).map_err(|_| {
+                        format!("{address} is not authoritative, public or an ip address")
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()
+                .map(Nameservers::Custom),
+        }
+    }
+}
+
 #[cfg(feature = "propagation")]
 #[derive(Debug, Subcommand)]
 pub enum DnsCommand {
-    AcmeValidationDelete { domain: String },
-    AcmeValidationSet { domain: String, challenge: String },
-    AcmeValidationCheck { domain: String, challenge: String },
+    /// Delete the acme challenges, or only the one with value challenge
+    AcmeValidationDelete {
+        domain: String,
+        challenge: Option<String>,
+        /// Delete in the zone a cname on the challenge record points to
+        #[arg(long)]
+        follow_cname: bool,
+    },
+    /// Replace the acme challenges by challenge, or add it to them with append
+    AcmeValidationSet {
+        domain: String,
+        challenge: String,
+        #[arg(long)]
+        append: bool,
+        /// Set in the zone a cname on the challenge record points to
+        #[arg(long)]
+        follow_cname: bool,
+    },
+    /// Wait until the nameservers serve the challenge, checking every interval seconds
+    AcmeValidationCheck {
+        domain: String,
+        challenge: String,
+        #[arg(long, default_value = "authoritative")]
+        nameservers: Nameservers,
+        #[arg(long, default_value_t = 5)]
+        interval: u64,
+        #[arg(long, default_value_t = 3600)]
+        timeout: u64,
+        /// Check the record a cname on the challenge record points to
+        #[arg(long)]
+        follow_cname: bool,
+        /// Resolver that finds the authoritative nameservers, Google over ipv4 or ipv6 if not given
+        #[arg(long, value_parser = nameserver_address)]
+        resolver: Option<SocketAddr>,
+    },
     Delete(DnsEntry),
     Insert(DnsEntry),
-    List { domain: String },
+    List {
+        domain: String,
+    },
 }
 
 #[cfg(not(feature = "propagation"))]
 #[derive(Debug, Subcommand)]
 pub enum DnsCommand {
-    AcmeValidationDelete { domain: String },
-    AcmeValidationSet { domain: String, challenge: String },
+    /// Delete the acme challenges, or only the one with value challenge
+    AcmeValidationDelete {
+        domain: String,
+        challenge: Option<String>,
+        /// Delete in the zone a cname on the challenge record points to
+        #[arg(long)]
+        follow_cname: bool,
+    },
+    /// Replace the acme challenges by challenge, or add it to them with append
+    AcmeValidationSet {
+        domain: String,
+        challenge: String,
+        #[arg(long)]
+        append: bool,
+        /// Set in the zone a cname on the challenge record points to
+        #[arg(long)]
+        follow_cname: bool,
+    },
     Delete(DnsEntry),
     Insert(DnsEntry),
+    List {
+        domain: String,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum AuditCommand {
+    /// Show audit records written at or after since, a date like 2024-01-31 or an rfc 3339 time
+    Show {
+        #[arg(long)]
+        since: Option<String>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum DnssecCommand {
+    /// Show the dnssec entries the registry publishes as ds records of domain
     List { domain: String },
+    /// Replace the dnssec entries of domain by the key signing keys in the output files of a zone signer
+    Set {
+        domain: String,
+        /// Files with dnskey records, like the .key files of dnssec-keygen or a signed zone,
+        /// ds records in them select the keys
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Delete all dnssec entries of domain, making it unsigned at the registry
+    Delete { domain: String },
 }
 
 #[derive(Debug, Subcommand)]
 pub enum DomainCommand {
     List,
-    Item { domain: String },
+    Item {
+        domain: String,
+    },
+    #[command(subcommand)]
+    Dnssec(DnssecCommand),
+    /// Show the nameservers of domain with their glue addresses
+    Nameservers {
+        domain: String,
+    },
+    /// Replace the nameservers of domain, a nameserver inside domain needs glue like ns1.example.nl=192.0.2.1
+    NameserversSet {
+        domain: String,
+        #[arg(required = true)]
+        nameservers: Vec<Nameserver>,
+    },
 }
 
 #[derive(Debug, Subcommand)]
@@ -124,16 +356,27 @@ pub enum VpsCommand {
 
 #[derive(Debug, Subcommand)]
 pub enum SubCommand {
+    #[command(subcommand)]
+    Audit(AuditCommand),
     AvailibilityZones,
     Comment {
         text: String,
     },
+    /// Ask for confirmation before executing destructive commands
+    Confirm {
+        confirm: Switch,
+    },
     #[command(subcommand)]
     Dns(DnsCommand),
     #[command(subcommand)]
     Domain(DomainCommand),
+    Dryrun {
+        dry_run: Switch,
+    },
     #[command(subcommand)]
     EmailBox(EmailBoxCommand),
+    /// Ends a parallel block
+    End,
     #[command(subcommand)]
     EmailForward(EmailForwardCommand),
     #[command(subcommand)]
@@ -141,9 +384,20 @@ pub enum SubCommand {
     Onerror {
         on_error: OnError,
     },
+    /// Executes the commands up to end on at most jobs threads
+    Parallel {
+        jobs: usize,
+    },
     Ping,
     #[command(subcommand)]
     Product(ProductCommand),
+    /// Show the remaining api call budget
+    Ratelimit,
+    /// Retry api calls failing with a transient error, waiting at most max_delay seconds between
+    Retry {
+        retries: u32,
+        max_delay: Option<u64>,
+    },
     Sleep {
         number_of_seconds: u64,
     },
@@ -151,18 +405,158 @@ pub enum SubCommand {
     Vps(VpsCommand),
 }
 
+/// Format used to print the result of a command, set with `--output`
+#[derive(Clone, Debug, Default, PartialEq, ValueEnum)]
+pub enum OutputFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+/// File the output of a command is written to, set with `> file` or `>> file`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Redirect {
+    pub path: PathBuf,
+    pub append: bool,
+}
+
 #[derive(Debug, Parser)]
 #[command(multicall = true)]
 pub struct TransipCommand {
     #[command(subcommand)]
     pub command: SubCommand,
+    #[arg(skip)]
+    pub query: Option<String>,
+    #[arg(skip)]
+    pub redirect: Option<Redirect>,
+    #[arg(skip)]
+    pub output: OutputFormat,
+}
+
+/// Name of the txt record with the token of an acme dns-01 challenge
+pub const ACME_CHALLENGE: &str = "_acme-challenge";
+/// Shown instead of a secret, like a password, in planned requests and recordings
+pub const REDACTED: &str = "********";
+
+const OUTPUT: &str = "--output";
+const QUERY: &str = "--query";
+const REDIRECT_APPEND: &str = ">>";
+const REDIRECT_TRUNCATE: &str = ">";
+
+/// Byte offset of the first redirection operator outside quotes, at the start of a word
+fn redirect_position(line: &str) -> Option<usize> {
+    let mut quote = None;
+    let mut escaped = false;
+    let mut word_start = true;
+    for (index, c) in line.char_indices() {
+        match (quote, c) {
+            _ if escaped => escaped = false,
+            (Some('\''), '\'') | (Some('"'), '"') => quote = None,
+            (Some('"'), '\\') | (None, '\\') => escaped = true,
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '>') if word_start => return Some(index),
+            _ => {}
+        }
+        word_start = quote.is_none() && !escaped && c.is_whitespace();
+    }
+    None
+}
+
+/// Line without its redirection and the redirection, a quoted `>` is an argument
+fn redirect(line: &str) -> Result<(&str, Option<Redirect>), clap::Error> {
+    let missing_file = || {
+        clap::Error::raw(
+            clap::error::ErrorKind::InvalidValue,
+            "file name missing after redirection\n",
+        )
+    };
+    let Some(index) = redirect_position(line) else {
+        return Ok((line, None));
+    };
+    let (command, operator) = line.split_at(index);
+    let (append, rest) = match operator.strip_prefix(REDIRECT_APPEND) {
+        Some(rest) => (true, rest),
+        None => (false, &operator[REDIRECT_TRUNCATE.len()..]),
+    };
+    if redirect_position(rest).is_some() || rest.trim_start().starts_with(REDIRECT_TRUNCATE) {
+        return Err(missing_file());
+    }
+    let mut tail = shlex::split(rest)
+        .ok_or(clap::Error::new(clap::error::ErrorKind::Format))?
+        .into_iter();
+    let path = tail.next().ok_or_else(missing_file)?;
+    match tail.next() {
+        Some(arg) => Err(clap::Error::raw(
+            clap::error::ErrorKind::UnknownArgument,
+            format!("unexpected argument '{arg}' after redirection\n"),
+        )),
+        None => Ok((
+            command,
+            Some(Redirect {
+                path: PathBuf::from(path),
+                append,
+            }),
+        )),
+    }
+}
+
+fn named_value(args: &mut Vec<String>, name: &str) -> Result<Option<String>, clap::Error> {
+    let prefix = format!("{name}=");
+    let Some(index) = args
+        .iter()
+        .position(|arg| arg == name || arg.starts_with(&prefix))
+    else {
+        return Ok(None);
+    };
+    let arg = args.remove(index);
+    match arg.strip_prefix(&prefix) {
+        Some(value) => Ok(Some(value.to_owned())),
+        None if index < args.len() => Ok(Some(args.remove(index))),
+        None => Err(clap::Error::raw(
+            clap::error::ErrorKind::InvalidValue,
+            format!("value missing after {name}\n"),
+        )),
+    }
+}
+
+fn output(args: &mut Vec<String>) -> Result<OutputFormat, clap::Error> {
+    named_value(args, OUTPUT)?.map_or(Ok(OutputFormat::default()), |value| {
+        <OutputFormat as ValueEnum>::from_str(&value, true).map_err(|_| {
+            clap::Error::raw(
+                clap::error::ErrorKind::InvalidValue,
+                format!("invalid value '{value}' for {OUTPUT}, expected json or csv\n"),
+            )
+        })
+    })
+}
+
+struct CommandLine {
+    args: Vec<String>,
+    query: Option<String>,
+    redirect: Option<Redirect>,
+    output: OutputFormat,
 }
 
-fn command_line<S: AsRef<str>>(line: S) -> Result<Vec<String>, clap::Error> {
+fn command_line<S: AsRef<str>>(line: S) -> Result<CommandLine, clap::Error> {
     if line.as_ref().trim_start().starts_with("#") {
-        Ok(vec!["comment".to_owned(), line.as_ref().to_owned()])
+        Ok(CommandLine {
+            args: vec!["comment".to_owned(), line.as_ref().to_owned()],
+            query: None,
+            redirect: None,
+            output: OutputFormat::default(),
+        })
     } else {
-        shlex::split(line.as_ref()).ok_or(clap::Error::new(clap::error::ErrorKind::Format))
+        let (line, redirect) = redirect(line.as_ref())?;
+        let mut args =
+            shlex::split(line).ok_or(clap::Error::new(clap::error::ErrorKind::Format))?;
+        let query = named_value(&mut args, QUERY)?;
+        let output = output(&mut args)?;
+        Ok(CommandLine {
+            args,
+            query,
+            redirect,
+            output,
+        })
     }
 }
 
@@ -170,15 +564,25 @@ impl FromStr for TransipCommand {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        command_line(s).and_then(TransipCommand::try_parse_from)
+        command_line(s).and_then(|line| {
+            TransipCommand::try_parse_from(line.args).map(|command| TransipCommand {
+                query: line.query,
+                redirect: line.redirect,
+                output: line.output,
+                ..command
+            })
+        })
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::io::{BufRead, BufReader};
+    use std::{
+        io::{BufRead, BufReader},
+        path::PathBuf,
+    };
 
-    use super::{TransipCommand, command_line};
+    use super::{Nameserver, OutputFormat, Redirect, TransipCommand, command_line};
     use clap::Parser;
 
     const COMMANDS: &[u8] = include_bytes!("commands.txt");
@@ -186,14 +590,134 @@ mod tests {
     #[test]
     fn try_command_lines() {
         let lines = BufReader::new(COMMANDS).lines();
-        for args_option in lines.map_while(Result::ok).map(command_line) {
-            match args_option {
-                Ok(args) => {
-                    let result = TransipCommand::try_parse_from(args).unwrap();
-                    println!("{:?}", &result.command);
-                }
-                Err(error) => eprintln!("Error parsing line: {error}"),
-            }
+        for line in lines.map_while(Result::ok) {
+            let args = command_line(&line)
+                .unwrap_or_else(|error| panic!("Error parsing line {line}: {error}"))
+                .args;
+            TransipCommand::try_parse_from(args).unwrap();
+        }
+    }
+
+    #[test]
+    fn redirect() {
+        let command = "dns list example.nl > backup/example.nl.json"
+            .parse::<TransipCommand>()
+            .unwrap();
+        assert_eq!(
+            command.redirect,
+            Some(Redirect {
+                path: PathBuf::from("backup/example.nl.json"),
+                append: false,
+            })
+        );
+
+        let command = "dns list example.nl >>backup.json"
+            .parse::<TransipCommand>()
+            .unwrap();
+        assert_eq!(
+            command.redirect,
+            Some(Redirect {
+                path: PathBuf::from("backup.json"),
+                append: true,
+            })
+        );
+
+        let command = "dns list example.nl".parse::<TransipCommand>().unwrap();
+        assert_eq!(command.redirect, None);
+
+        for line in [
+            "dns insert example.nl @ 300 TXT '>quoted'",
+            "dns list \\>escaped",
+        ] {
+            assert_eq!(line.parse::<TransipCommand>().unwrap().redirect, None);
         }
+
+        let command = "dns list example.nl > 'backup dir/>a.json'"
+            .parse::<TransipCommand>()
+            .unwrap();
+        assert_eq!(
+            command.redirect.map(|redirect| redirect.path),
+            Some(PathBuf::from("backup dir/>a.json"))
+        );
+
+        assert!("dns list example.nl >".parse::<TransipCommand>().is_err());
+        assert!(
+            "dns list example.nl > >a"
+                .parse::<TransipCommand>()
+                .is_err()
+        );
+        assert!(
+            "dns list example.nl > a b"
+                .parse::<TransipCommand>()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn query() {
+        let command = "vps list --query '[].name'"
+            .parse::<TransipCommand>()
+            .unwrap();
+        assert_eq!(command.query.as_deref(), Some("[].name"));
+
+        let command = "domain item x.nl --query=renewalDate > renewal.json"
+            .parse::<TransipCommand>()
+            .unwrap();
+        assert_eq!(command.query.as_deref(), Some("renewalDate"));
+        assert!(command.redirect.is_some());
+
+        assert!("vps list --query".parse::<TransipCommand>().is_err());
+    }
+
+    #[test]
+    fn output() {
+        let command = "invoice list --output csv > invoices.csv"
+            .parse::<TransipCommand>()
+            .unwrap();
+        assert_eq!(command.output, OutputFormat::Csv);
+        assert!(command.redirect.is_some());
+
+        let command = "dns list example.nl --output=json"
+            .parse::<TransipCommand>()
+            .unwrap();
+        assert_eq!(command.output, OutputFormat::Json);
+
+        let command = "vps list".parse::<TransipCommand>().unwrap();
+        assert_eq!(command.output, OutputFormat::Json);
+
+        assert!("vps list --output xml".parse::<TransipCommand>().is_err());
+        assert!("vps list --output".parse::<TransipCommand>().is_err());
+    }
+
+    #[test]
+    fn nameserver() {
+        let nameserver = "NS1.example.nl.=2001:db8::1,192.0.2.1"
+            .parse::<Nameserver>()
+            .unwrap();
+        assert_eq!(nameserver.hostname, "ns1.example.nl");
+        assert_eq!(
+            nameserver.to_string(),
+            "ns1.example.nl=192.0.2.1,2001:db8::1"
+        );
+        assert!(nameserver.in_zone("example.nl"));
+        assert!(!nameserver.in_zone("ample.nl"));
+
+        let nameserver = "ns0.transip.net".parse::<Nameserver>().unwrap();
+        assert!(!nameserver.has_glue());
+        assert!(!nameserver.in_zone("example.nl"));
+
+        assert!("ns1".parse::<Nameserver>().is_err());
+        assert!("-ns1.example.nl".parse::<Nameserver>().is_err());
+        assert!(
+            "ns1.example.nl=192.0.2.1,192.0.2.2"
+                .parse::<Nameserver>()
+                .is_err()
+        );
+        assert!("ns1.example.nl=ns0".parse::<Nameserver>().is_err());
+        assert!(
+            "domain nameservers-set example.nl"
+                .parse::<TransipCommand>()
+                .is_err()
+        );
     }
 }
@@ -0,0 +1,260 @@
+use transip::api::dns::DnsEntry;
+use transip_command::{ACME_CHALLENGE, DnsCommand, SubCommand};
+
+use crate::{Client, Error, Result};
+
+const TTL: u32 = 60;
+
+/// Challenge record of a domain, in the zone of the account that contains it
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct AcmeRecord {
+    pub zone: String,
+    /// Name relative to the zone, like `_acme-challenge.sub.app`
+    pub name: String,
+    /// Reached by following a cname, so it may hold challenges of other domains
+    pub delegated: bool,
+}
+
+impl AcmeRecord {
+    /// Record named fqdn in the longest zone that contains it
+    fn in_zones(fqdn: &str, zones: &[String]) -> Option<Self> {
+        let fqdn = fqdn.trim_end_matches('.').to_lowercase();
+        let zone = zones
+            .iter()
+            .filter(|zone| fqdn == **zone || fqdn.ends_with(&format!(".{zone}")))
+            .max_by_key(|zone| zone.len())?;
+        let name = match fqdn[..fqdn.len() - zone.len()].trim_end_matches('.') {
+            "" => "@",
+            name => name,
+        };
+        Some(Self {
+            zone: zone.clone(),
+            name: name.to_owned(),
+            delegated: false,
+        })
+    }
+
+    /// Challenge record of domain in the longest zone that contains it, in domain itself if no zone does
+    fn new(domain: &str, zones: &[String]) -> Self {
+        let domain = domain.trim_end_matches('.').to_lowercase();
+        Self::in_zones(&format!("{ACME_CHALLENGE}.{domain}"), zones).unwrap_or(Self {
+            zone: domain,
+            name: ACME_CHALLENGE.to_owned(),
+            delegated: false,
+        })
+    }
+
+    /// Fully qualified name of the record, as looked up in dns
+    pub fn fqdn(&self) -> String {
+        match self.name.as_str() {
+            "@" => self.zone.clone(),
+            name => format!("{name}.{}", self.zone),
+        }
+    }
+
+    /// Challenges of the record, or only the one with value challenge
+    ///
+    /// A delegated record is shared with other domains, so only the one with value challenge.
+    pub fn matches(&self, challenge: Option<&str>) -> impl Fn(&DnsEntry) -> bool {
+        move |entry| {
+            entry.is_acme_challenge_named(&self.name)
+                && challenge.map_or(!self.delegated, |challenge| entry.content == challenge)
+        }
+    }
+
+    /// Challenges deleted by acme-validation-delete, a delegated record needs the challenge
+    pub fn deleted(&self, challenge: Option<&str>) -> Result<impl Fn(&DnsEntry) -> bool> {
+        if self.delegated && challenge.is_none() {
+            return Err(Error::Rejected(format!(
+                "acme challenge record {} is delegated, give the challenge to delete",
+                self.fqdn()
+            )));
+        }
+        Ok(self.matches(challenge))
+    }
+
+    pub fn entry(&self, challenge: &str) -> DnsEntry {
+        DnsEntry::new_acme_challenge_named(&self.name, TTL, challenge)
+    }
+}
+
+/// Acme challenges below the apex of a zone
+pub(crate) trait AcmeChallenge {
+    fn new_acme_challenge_named(name: &str, expire: u32, content: &str) -> Self;
+    fn is_acme_challenge_named(&self, name: &str) -> bool;
+}
+
+impl AcmeChallenge for DnsEntry {
+    fn new_acme_challenge_named(name: &str, expire: u32, content: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            ..DnsEntry::new_acme_challenge(expire, content)
+        }
+    }
+
+    fn is_acme_challenge_named(&self, name: &str) -> bool {
+        self.entry_type == "TXT" && self.name == name
+    }
+}
+
+/// Fully qualified target of a cname in zone, relative targets are names in that zone
+fn cname_target(cname: &DnsEntry, zone: &str) -> String {
+    match cname.content.strip_suffix('.') {
+        Some(target) => target.to_owned(),
+        None => format!("{}.{zone}", cname.content),
+    }
+}
+
+impl Client {
+    /// Names of the domains of the account, listed once per client
+    fn zones(&mut self) -> Result<&[String]> {
+        if self.zones.is_none() {
+            let domains = self.inner.domain_list()?;
+            self.zones = Some(domains.into_iter().map(|domain| domain.name).collect());
+        }
+        Ok(self.zones.as_deref().unwrap_or_default())
+    }
+
+    /// Challenge record of domain
+    ///
+    /// With follow_cname a cname on the challenge record is followed to the record it points to,
+    /// which must be in a domain of the account as well.
+    pub(crate) fn acme_record(&mut self, domain: &str, follow_cname: bool) -> Result<AcmeRecord> {
+        let record = AcmeRecord::new(domain, self.zones()?);
+        if !follow_cname {
+            return Ok(record);
+        }
+        let Some(cname) = self
+            .inner
+            .dns_entry_list(&record.zone)?
+            .into_iter()
+            .find(|entry| entry.entry_type == "CNAME" && entry.name == record.name)
+        else {
+            return Ok(record);
+        };
+        let target = cname_target(&cname, &record.zone);
+        tracing::info!("Acme challenge of {domain} is delegated to {target}");
+        if target.split('.').next() != Some(ACME_CHALLENGE) {
+            return Err(Error::Rejected(format!(
+                "acme challenge of {domain} is delegated to {target}, which is not an {ACME_CHALLENGE} name"
+            )));
+        }
+        AcmeRecord::in_zones(&target, self.zones()?)
+            .map(|record| AcmeRecord {
+                delegated: true,
+                ..record
+            })
+            .ok_or_else(|| {
+                Error::Rejected(format!(
+                    "acme challenge of {domain} is delegated to {target}, which is not in a domain of the account"
+                ))
+            })
+    }
+
+    /// Zone and name of the challenge record command changes or checks, if it follows a cname to it
+    ///
+    /// Lets a policy check the record that is changed instead of the one of the domain.
+    pub fn delegated_record(&mut self, command: &SubCommand) -> Result<Option<(String, String)>> {
+        let (domain, follow_cname) = match command {
+            SubCommand::Dns(
+                DnsCommand::AcmeValidationDelete {
+                    domain,
+                    follow_cname,
+                    ..
+                }
+                | DnsCommand::AcmeValidationSet {
+                    domain,
+                    follow_cname,
+                    ..
+                },
+            ) => (domain, *follow_cname),
+            #[cfg(feature = "propagation")]
+            SubCommand::Dns(DnsCommand::AcmeValidationCheck {
+                domain,
+                follow_cname,
+                ..
+            }) => (domain, *follow_cname),
+            _ => return Ok(None),
+        };
+        if !follow_cname {
+            return Ok(None);
+        }
+        self.acme_record(domain, true)
+            .map(|record| record.delegated.then_some((record.zone, record.name)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use transip::api::dns::DnsEntry;
+
+    use super::{AcmeChallenge, AcmeRecord, cname_target};
+
+    fn record(domain: &str) -> (String, String) {
+        let zones = ["example.nl".to_owned(), "app.example.nl".to_owned()];
+        let record = AcmeRecord::new(domain, &zones);
+        (record.zone, record.name)
+    }
+
+    #[test]
+    fn zones() {
+        let expected = |zone: &str, name: &str| (zone.to_owned(), name.to_owned());
+        assert_eq!(
+            record("example.nl"),
+            expected("example.nl", "_acme-challenge")
+        );
+        assert_eq!(
+            record("www.example.nl"),
+            expected("example.nl", "_acme-challenge.www")
+        );
+        assert_eq!(
+            record("sub.app.example.nl"),
+            expected("app.example.nl", "_acme-challenge.sub")
+        );
+        assert_eq!(
+            record("Deep.Sub.Example.nl."),
+            expected("example.nl", "_acme-challenge.deep.sub")
+        );
+        assert_eq!(
+            record("badexample.nl"),
+            expected("badexample.nl", "_acme-challenge")
+        );
+    }
+
+    #[test]
+    fn delegation() {
+        let zones = ["example.nl".to_owned(), "acme.example.net".to_owned()];
+        let cname = |content: &str| DnsEntry {
+            name: "_acme-challenge.www".to_owned(),
+            expire: 300,
+            entry_type: "CNAME".to_owned(),
+            content: content.to_owned(),
+        };
+        let target = cname_target(&cname("www.acme.example.net."), "example.nl");
+        let record = AcmeRecord::in_zones(&target, &zones).unwrap();
+        assert_eq!(record.zone, "acme.example.net");
+        assert_eq!(record.name, "www");
+        #[cfg(feature = "propagation")]
+        assert_eq!(record.fqdn(), "www.acme.example.net");
+        assert_eq!(
+            cname_target(&cname("_acme-challenge.web"), "example.nl"),
+            "_acme-challenge.web.example.nl"
+        );
+        assert_eq!(
+            AcmeRecord::in_zones("acme.example.net", &zones)
+                .unwrap()
+                .name,
+            "@"
+        );
+        assert_eq!(AcmeRecord::in_zones("www.example.com", &zones), None);
+    }
+
+    #[test]
+    fn named_challenge() {
+        let entry = DnsEntry::new_acme_challenge_named("_acme-challenge.sub", 60, "abc");
+        assert_eq!(entry.entry_type, "TXT");
+        assert!(entry.is_acme_challenge_named("_acme-challenge.sub"));
+        assert!(!entry.is_acme_challenge_named("_acme-challenge"));
+        assert!(!entry.is_acme_challenge());
+    }
+}
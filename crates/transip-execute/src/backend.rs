@@ -0,0 +1,59 @@
+use transip::api::{
+    account::Invoice,
+    dns::DnsEntry,
+    domain::{Domain, NameServer},
+    email::{MailForward, MailForwardInsert, Mailbox, MailboxInsert},
+    general::{AvailabilityZone, ProductElement, Products},
+    vps::Vps,
+};
+
+use crate::{DnsSecEntry, RateLimitHeaders, Result};
+
+/// Api calls used to execute commands
+///
+/// Implemented by [`crate::Api`] for the real api endpoint
+/// and by [`crate::Memory`] for testing without credentials.
+pub trait Backend {
+    /// Prefix of the urls of the api endpoint
+    fn endpoint(&self) -> String;
+    /// Rate limit reported by the last http response, None for backends without http calls
+    fn rate_limit(&self) -> Option<RateLimitHeaders>;
+    fn api_test(&mut self) -> Result<String>;
+    fn availability_zones(&mut self) -> Result<Vec<AvailabilityZone>>;
+    fn dns_entry_delete(&mut self, domain_name: &str, entry: DnsEntry) -> Result<()>;
+    fn dns_entry_insert(&mut self, domain_name: &str, entry: DnsEntry) -> Result<()>;
+    fn dns_entry_list(&mut self, domain_name: &str) -> Result<Vec<DnsEntry>>;
+    fn domain_item(&mut self, name: &str) -> Result<Domain>;
+    fn domain_dnssec_list(&mut self, domain_name: &str) -> Result<Vec<DnsSecEntry>>;
+    fn domain_dnssec_set(&mut self, domain_name: &str, entries: Vec<DnsSecEntry>) -> Result<()>;
+    fn domain_list(&mut self) -> Result<Vec<Domain>>;
+    fn domain_nameservers(&mut self, domain_name: &str) -> Result<Vec<NameServer>>;
+    fn domain_nameservers_set(
+        &mut self,
+        domain_name: &str,
+        nameservers: Vec<NameServer>,
+    ) -> Result<()>;
+    fn invoice(&mut self, invoice_number: &str) -> Result<Invoice>;
+    fn invoice_list(&mut self) -> Result<Vec<Invoice>>;
+    fn invoice_pdf(&mut self, invoice_number: &str) -> Result<String>;
+    fn mailbox_delete(&mut self, domain_name: &str, id: &str) -> Result<()>;
+    fn mailbox_insert(&mut self, domain_name: &str, mailbox: MailboxInsert) -> Result<()>;
+    fn mailbox_item(&mut self, domain_name: &str, id: &str) -> Result<Mailbox>;
+    fn mailbox_list(&mut self, domain_name: &str) -> Result<Vec<Mailbox>>;
+    fn mailforward_delete(&mut self, domain_name: &str, id: &str) -> Result<()>;
+    fn mailforward_insert(
+        &mut self,
+        domain_name: &str,
+        mail_forward: MailForwardInsert,
+    ) -> Result<()>;
+    fn mailforward_item(&mut self, domain_name: &str, id: &str) -> Result<MailForward>;
+    fn mailforward_list(&mut self, domain_name: &str) -> Result<Vec<MailForward>>;
+    fn product_elements(&mut self, name: &str) -> Result<Vec<ProductElement>>;
+    fn products(&mut self) -> Result<Products>;
+    fn vps(&mut self, name: &str) -> Result<Vps>;
+    fn vps_list(&mut self) -> Result<Vec<Vps>>;
+    fn vps_reset(&mut self, name: &str) -> Result<()>;
+    fn vps_set_is_locked(&mut self, name: &str, locked: bool) -> Result<()>;
+    fn vps_start(&mut self, name: &str) -> Result<()>;
+    fn vps_stop(&mut self, name: &str) -> Result<()>;
+}
@@ -0,0 +1,477 @@
+use std::{
+    collections::VecDeque,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::{Value, json};
+use transip::api::{
+    account::Invoice,
+    dns::DnsEntry,
+    domain::{Domain, NameServer},
+    email::{MailForward, MailForwardInsert, Mailbox, MailboxInsert},
+    general::{AvailabilityZone, ProductElement, Products},
+    vps::Vps,
+};
+use transip_command::REDACTED;
+
+use crate::{Backend, DnsSecEntry, Error, RateLimitHeaders, Result};
+
+/// Fields that never end up in a recording
+const SECRETS: &[&str] = &["password", "privateKey", "token"];
+
+/// Result of an api call as saved in a recording
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Response {
+    Ok(Value),
+    Error(String),
+}
+
+/// One api request and its response, saved as a numbered json file
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct Interaction {
+    pub method: String,
+    pub path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<Value>,
+    pub response: Response,
+}
+
+fn json_error(error: serde_json::Error) -> Error {
+    Error::IO(error.into())
+}
+
+fn redact(value: &mut Value) {
+    match value {
+        Value::Object(map) => map.iter_mut().for_each(|(key, value)| {
+            if SECRETS.contains(&key.as_str()) {
+                *value = json!(REDACTED);
+            } else {
+                redact(value);
+            }
+        }),
+        Value::Array(items) => items.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+fn redacted<T: Serialize>(value: &T) -> Result<Value> {
+    let mut value = serde_json::to_value(value).map_err(json_error)?;
+    redact(&mut value);
+    Ok(value)
+}
+
+/// Maps every api call onto a method and path of the api, so recording and replaying share it
+trait Cassette {
+    fn endpoint(&self) -> String;
+    fn rate_limit(&self) -> Option<RateLimitHeaders>;
+    fn call<T: Serialize + DeserializeOwned>(
+        &mut self,
+        method: &str,
+        path: String,
+        body: Option<Value>,
+        f: impl FnOnce(&mut dyn Backend) -> Result<T>,
+    ) -> Result<T>;
+}
+
+/// Backend that saves every api call of the wrapped backend in a directory
+pub struct Recorder {
+    inner: Box<dyn Backend>,
+    dir: PathBuf,
+    count: usize,
+}
+
+impl Recorder {
+    /// Fails if dir already holds files, to avoid mixing recordings
+    pub fn new<P: AsRef<Path>>(inner: Box<dyn Backend>, dir: P) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        if fs::read_dir(&dir)?.next().is_some() {
+            return Err(Error::Rejected(format!(
+                "recording directory {} is not empty",
+                dir.display()
+            )));
+        }
+        Ok(Self {
+            inner,
+            dir,
+            count: 0,
+        })
+    }
+
+    fn save(&mut self, interaction: &Interaction) -> Result<()> {
+        self.count += 1;
+        let bytes = serde_json::to_vec_pretty(interaction).map_err(json_error)?;
+        fs::write(self.dir.join(format!("{:04}.json", self.count)), bytes)?;
+        Ok(())
+    }
+}
+
+impl Cassette for Recorder {
+    fn endpoint(&self) -> String {
+        self.inner.endpoint()
+    }
+
+    fn rate_limit(&self) -> Option<RateLimitHeaders> {
+        self.inner.rate_limit()
+    }
+
+    fn call<T: Serialize + DeserializeOwned>(
+        &mut self,
+        method: &str,
+        path: String,
+        body: Option<Value>,
+        f: impl FnOnce(&mut dyn Backend) -> Result<T>,
+    ) -> Result<T> {
+        let result = f(self.inner.as_mut());
+        let response = match &result {
+            Ok(value) => Response::Ok(redacted(value)?),
+            Err(Error::Rejected(message)) => Response::Error(message.clone()),
+            Err(error) => Response::Error(error.to_string()),
+        };
+        let body = body.as_ref().map(redacted).transpose()?;
+        self.save(&Interaction {
+            method: method.to_owned(),
+            path,
+            body,
+            response,
+        })?;
+        result
+    }
+}
+
+/// Backend that answers api calls from a directory saved by [`Recorder`]
+///
+/// Calls have to be made in the recorded order.
+pub struct Replay {
+    interactions: VecDeque<Interaction>,
+}
+
+impl Replay {
+    pub fn from_dir<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let mut paths = fs::read_dir(dir)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        paths.retain(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "json")
+        });
+        paths.sort();
+        paths
+            .iter()
+            .map(|path| {
+                let bytes = fs::read(path)?;
+                serde_json::from_slice::<Interaction>(&bytes).map_err(json_error)
+            })
+            .collect::<Result<VecDeque<_>>>()
+            .map(|interactions| Self { interactions })
+    }
+}
+
+impl Cassette for Replay {
+    fn endpoint(&self) -> String {
+        "replay:/".to_owned()
+    }
+
+    /// Replayed calls are not made, so they do not count
+    fn rate_limit(&self) -> Option<RateLimitHeaders> {
+        None
+    }
+
+    fn call<T: Serialize + DeserializeOwned>(
+        &mut self,
+        method: &str,
+        path: String,
+        body: Option<Value>,
+        _: impl FnOnce(&mut dyn Backend) -> Result<T>,
+    ) -> Result<T> {
+        let request = format!("{method} {path}");
+        let interaction = self
+            .interactions
+            .pop_front()
+            .ok_or_else(|| Error::Rejected(format!("{request} not in recording")))?;
+        let body = body.as_ref().map(redacted).transpose()?;
+        if interaction.method != method || interaction.path != path || interaction.body != body {
+            return Err(Error::Rejected(format!(
+                "{request} does not match recorded {} {}",
+                interaction.method, interaction.path
+            )));
+        }
+        match interaction.response {
+            Response::Ok(value) => serde_json::from_value(value).map_err(json_error),
+            Response::Error(message) => Err(Error::Rejected(message)),
+        }
+    }
+}
+
+impl<C: Cassette> Backend for C {
+    fn endpoint(&self) -> String {
+        Cassette::endpoint(self)
+    }
+
+    fn rate_limit(&self) -> Option<RateLimitHeaders> {
+        Cassette::rate_limit(self)
+    }
+
+    fn api_test(&mut self) -> Result<String> {
+        self.call("GET", "api-test".to_owned(), None, |b| b.api_test())
+    }
+
+    fn availability_zones(&mut self) -> Result<Vec<AvailabilityZone>> {
+        self.call("GET", "availability-zones".to_owned(), None, |b| {
+            b.availability_zones()
+        })
+    }
+
+    fn dns_entry_delete(&mut self, domain_name: &str, entry: DnsEntry) -> Result<()> {
+        let path = format!("domains/{domain_name}/dns");
+        let body = json!({ "dnsEntry": entry });
+        self.call("DELETE", path, Some(body), |b| {
+            b.dns_entry_delete(domain_name, entry)
+        })
+    }
+
+    fn dns_entry_insert(&mut self, domain_name: &str, entry: DnsEntry) -> Result<()> {
+        let path = format!("domains/{domain_name}/dns");
+        let body = json!({ "dnsEntry": entry });
+        self.call("POST", path, Some(body), |b| {
+            b.dns_entry_insert(domain_name, entry)
+        })
+    }
+
+    fn dns_entry_list(&mut self, domain_name: &str) -> Result<Vec<DnsEntry>> {
+        let path = format!("domains/{domain_name}/dns");
+        self.call("GET", path, None, |b| b.dns_entry_list(domain_name))
+    }
+
+    fn domain_dnssec_list(&mut self, domain_name: &str) -> Result<Vec<DnsSecEntry>> {
+        let path = format!("domains/{domain_name}/dnssec");
+        self.call("GET", path, None, |b| b.domain_dnssec_list(domain_name))
+    }
+
+    fn domain_dnssec_set(&mut self, domain_name: &str, entries: Vec<DnsSecEntry>) -> Result<()> {
+        let path = format!("domains/{domain_name}/dnssec");
+        let body = json!({ "dnsSecEntries": entries });
+        self.call("PUT", path, Some(body), |b| {
+            b.domain_dnssec_set(domain_name, entries)
+        })
+    }
+
+    fn domain_item(&mut self, name: &str) -> Result<Domain> {
+        self.call("GET", format!("domains/{name}"), None, |b| {
+            b.domain_item(name)
+        })
+    }
+
+    fn domain_list(&mut self) -> Result<Vec<Domain>> {
+        self.call("GET", "domains".to_owned(), None, |b| b.domain_list())
+    }
+
+    fn domain_nameservers(&mut self, domain_name: &str) -> Result<Vec<NameServer>> {
+        let path = format!("domains/{domain_name}/nameservers");
+        self.call("GET", path, None, |b| b.domain_nameservers(domain_name))
+    }
+
+    fn domain_nameservers_set(
+        &mut self,
+        domain_name: &str,
+        nameservers: Vec<NameServer>,
+    ) -> Result<()> {
+        let path = format!("domains/{domain_name}/nameservers");
+        let body = json!({ "nameservers": nameservers });
+        self.call("PUT", path, Some(body), |b| {
+            b.domain_nameservers_set(domain_name, nameservers)
+        })
+    }
+
+    fn invoice(&mut self, invoice_number: &str) -> Result<Invoice> {
+        let path = format!("invoices/{invoice_number}");
+        self.call("GET", path, None, |b| b.invoice(invoice_number))
+    }
+
+    fn invoice_list(&mut self) -> Result<Vec<Invoice>> {
+        self.call("GET", "invoices".to_owned(), None, |b| b.invoice_list())
+    }
+
+    fn invoice_pdf(&mut self, invoice_number: &str) -> Result<String> {
+        let path = format!("invoices/{invoice_number}/pdf");
+        self.call("GET", path, None, |b| b.invoice_pdf(invoice_number))
+    }
+
+    fn mailbox_delete(&mut self, domain_name: &str, id: &str) -> Result<()> {
+        let path = format!("email/{domain_name}/mailboxes/{id}");
+        self.call("DELETE", path, None, |b| b.mailbox_delete(domain_name, id))
+    }
+
+    fn mailbox_insert(&mut self, domain_name: &str, mailbox: MailboxInsert) -> Result<()> {
+        let path = format!("email/{domain_name}/mailboxes");
+        let body = json!(mailbox);
+        self.call("POST", path, Some(body), |b| {
+            b.mailbox_insert(domain_name, mailbox)
+        })
+    }
+
+    fn mailbox_item(&mut self, domain_name: &str, id: &str) -> Result<Mailbox> {
+        let path = format!("email/{domain_name}/mailboxes/{id}");
+        self.call("GET", path, None, |b| b.mailbox_item(domain_name, id))
+    }
+
+    fn mailbox_list(&mut self, domain_name: &str) -> Result<Vec<Mailbox>> {
+        let path = format!("email/{domain_name}/mailboxes");
+        self.call("GET", path, None, |b| b.mailbox_list(domain_name))
+    }
+
+    fn mailforward_delete(&mut self, domain_name: &str, id: &str) -> Result<()> {
+        let path = format!("email/{domain_name}/mail-forwards/{id}");
+        self.call("DELETE", path, None, |b| {
+            b.mailforward_delete(domain_name, id)
+        })
+    }
+
+    fn mailforward_insert(
+        &mut self,
+        domain_name: &str,
+        mail_forward: MailForwardInsert,
+    ) -> Result<()> {
+        let path = format!("email/{domain_name}/mail-forwards");
+        let body = json!(mail_forward);
+        self.call("POST", path, Some(body), |b| {
+            b.mailforward_insert(domain_name, mail_forward)
+        })
+    }
+
+    fn mailforward_item(&mut self, domain_name: &str, id: &str) -> Result<MailForward> {
+        let path = format!("email/{domain_name}/mail-forwards/{id}");
+        self.call("GET", path, None, |b| b.mailforward_item(domain_name, id))
+    }
+
+    fn mailforward_list(&mut self, domain_name: &str) -> Result<Vec<MailForward>> {
+        let path = format!("email/{domain_name}/mail-forwards");
+        self.call("GET", path, None, |b| b.mailforward_list(domain_name))
+    }
+
+    fn product_elements(&mut self, name: &str) -> Result<Vec<ProductElement>> {
+        let path = format!("products/{name}/elements");
+        self.call("GET", path, None, |b| b.product_elements(name))
+    }
+
+    fn products(&mut self) -> Result<Products> {
+        self.call("GET", "products".to_owned(), None, |b| b.products())
+    }
+
+    fn vps(&mut self, name: &str) -> Result<Vps> {
+        self.call("GET", format!("vps/{name}"), None, |b| b.vps(name))
+    }
+
+    fn vps_list(&mut self) -> Result<Vec<Vps>> {
+        self.call("GET", "vps".to_owned(), None, |b| b.vps_list())
+    }
+
+    fn vps_reset(&mut self, name: &str) -> Result<()> {
+        let body = json!({ "action": "reset" });
+        self.call("PATCH", format!("vps/{name}"), Some(body), |b| {
+            b.vps_reset(name)
+        })
+    }
+
+    /// Like the api client, reads the vps and puts it back with the lock changed
+    ///
+    /// When recording the api client reads the vps once more, that read is not recorded.
+    fn vps_set_is_locked(&mut self, name: &str, locked: bool) -> Result<()> {
+        let mut vps = Backend::vps(self, name)?;
+        vps.is_customer_locked = locked;
+        let body = json!({ "vps": vps });
+        self.call("PUT", format!("vps/{name}"), Some(body), |b| {
+            b.vps_set_is_locked(name, locked)
+        })
+    }
+
+    fn vps_start(&mut self, name: &str) -> Result<()> {
+        let body = json!({ "action": "start" });
+        self.call("PATCH", format!("vps/{name}"), Some(body), |b| {
+            b.vps_start(name)
+        })
+    }
+
+    fn vps_stop(&mut self, name: &str) -> Result<()> {
+        let body = json!({ "action": "stop" });
+        self.call("PATCH", format!("vps/{name}"), Some(body), |b| {
+            b.vps_stop(name)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+    use transip_command::TransipCommand;
+    use transip_test_support::{TempDir, fixture};
+
+    use super::{Interaction, Replay, Response};
+    use crate::{Client, CommandOutput, Fixture, Memory};
+
+    fn execute(client: &mut Client, line: &str) -> crate::Result<CommandOutput> {
+        let command = line.parse::<TransipCommand>().unwrap();
+        client.execute_output(&command.command)
+    }
+
+    fn dir(name: &str) -> TempDir {
+        TempDir::new(&format!("transip-cassette-{name}"))
+    }
+
+    fn client() -> Client {
+        Client::new(Memory::from(fixture::<Fixture>(json!({
+            "dns": {"example.nl": [{"name": "@", "expire": 300, "type": "A", "content": "1.2.3.4"}]},
+            "mailboxes": {"example.nl": []},
+        }))))
+    }
+
+    #[test]
+    fn record_and_replay() {
+        let dir = dir("replay");
+        let mut client = client();
+        client.record(&dir).unwrap();
+        let listed = execute(&mut client, "dns list example.nl").unwrap();
+        execute(&mut client, "dns acme-validation-set example.nl abc").unwrap();
+        assert!(execute(&mut client, "dns list unknown.nl").is_err());
+
+        let mut replay = Client::new(Replay::from_dir(&dir).unwrap());
+        assert_eq!(
+            serde_json::to_value(execute(&mut replay, "dns list example.nl").unwrap()).unwrap(),
+            serde_json::to_value(listed).unwrap()
+        );
+        execute(&mut replay, "dns acme-validation-set example.nl abc").unwrap();
+        assert!(execute(&mut replay, "dns list unknown.nl").is_err());
+        assert!(execute(&mut replay, "dns list example.nl").is_err());
+    }
+
+    #[test]
+    fn replay_mismatch() {
+        let dir = dir("mismatch");
+        let mut client = client();
+        client.record(&dir).unwrap();
+        execute(&mut client, "dns list example.nl").unwrap();
+
+        let mut replay = Client::new(Replay::from_dir(&dir).unwrap());
+        assert!(execute(&mut replay, "vps list").is_err());
+    }
+
+    #[test]
+    fn password_redacted() {
+        let dir = dir("redacted");
+        let mut client = client();
+        client.record(&dir).unwrap();
+        execute(&mut client, "email-box insert example.nl info secret 1024").unwrap();
+
+        let bytes = std::fs::read(dir.join("0001.json")).unwrap();
+        let interaction = serde_json::from_slice::<Interaction>(&bytes).unwrap();
+        assert_eq!(interaction.method, "POST");
+        assert_eq!(
+            interaction.body.unwrap()["password"],
+            json!(super::REDACTED)
+        );
+        assert_eq!(interaction.response, Response::Ok(json!(null)));
+    }
+}
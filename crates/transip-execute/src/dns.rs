@@ -0,0 +1,306 @@
+//! Minimal dns messages over udp, enough to look up the records used by acme validation
+
+use std::{
+    hash::{BuildHasher, RandomState},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    time::Duration,
+};
+
+use crate::{Error, Result};
+
+pub const A: u16 = 1;
+pub const NS: u16 = 2;
+pub const CNAME: u16 = 5;
+pub const MX: u16 = 15;
+pub const TXT: u16 = 16;
+pub const AAAA: u16 = 28;
+pub const SRV: u16 = 33;
+
+const CLASS_IN: u16 = 1;
+const RECURSION_DESIRED: u16 = 0x0100;
+const RESPONSE: u16 = 0x8000;
+const TRUNCATED: u16 = 0x0200;
+const RECURSION_AVAILABLE: u16 = 0x0080;
+const NXDOMAIN: u16 = 3;
+const MAX_POINTERS: usize = 16;
+const TTL: u32 = 60;
+
+/// Question of a dns request, as received by a server
+#[derive(Clone, Debug, PartialEq)]
+pub struct Question {
+    pub id: u16,
+    pub name: String,
+    pub record_type: u16,
+}
+
+/// Data of an answer record, types that are not needed are kept as Other
+#[derive(Clone, Debug, PartialEq)]
+pub enum RData {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Cname(String),
+    Ns(String),
+    Txt(String),
+    Other(u16),
+}
+
+impl RData {
+    fn record_type(&self) -> u16 {
+        match self {
+            RData::A(_) => A,
+            RData::Aaaa(_) => AAAA,
+            RData::Cname(_) => CNAME,
+            RData::Ns(_) => NS,
+            RData::Txt(_) => TXT,
+            RData::Other(record_type) => *record_type,
+        }
+    }
+}
+
+/// Answer record of a dns response
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+    pub name: String,
+    pub data: RData,
+}
+
+fn malformed() -> Error {
+    Error::Rejected("malformed dns message".to_owned())
+}
+
+/// Big endian number at position of message
+pub fn u16_at(message: &[u8], position: usize) -> Result<u16> {
+    message
+        .get(position..position + 2)
+        .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+        .ok_or_else(malformed)
+}
+
+/// Appends name as uncompressed labels
+pub fn push_name(buffer: &mut Vec<u8>, name: &str) {
+    for label in name
+        .trim_end_matches('.')
+        .split('.')
+        .filter(|label| !label.is_empty())
+    {
+        buffer.push(label.len().min(63) as u8);
+        buffer.extend(label.bytes().take(63));
+    }
+    buffer.push(0);
+}
+
+/// Reads a possibly compressed name, returns the name and the position after it
+pub fn read_name(message: &[u8], mut position: usize) -> Result<(String, usize)> {
+    let mut labels = vec![];
+    let mut end = None;
+    for _ in 0..MAX_POINTERS {
+        loop {
+            let length = *message.get(position).ok_or_else(malformed)? as usize;
+            if length & 0xC0 == 0xC0 {
+                let pointer = u16_at(message, position)? as usize & 0x3FFF;
+                end.get_or_insert(position + 2);
+                position = pointer;
+                break;
+            }
+            if length == 0 {
+                return Ok((labels.join("."), end.unwrap_or(position + 1)));
+            }
+            let label = message
+                .get(position + 1..position + 1 + length)
+                .ok_or_else(malformed)?;
+            labels.push(String::from_utf8_lossy(label).to_lowercase());
+            position += 1 + length;
+        }
+    }
+    Err(malformed())
+}
+
+fn header(id: u16, flags: u16, questions: u16, answers: u16) -> Vec<u8> {
+    [id, flags, questions, answers, 0, 0]
+        .iter()
+        .flat_map(|value| value.to_be_bytes())
+        .collect()
+}
+
+/// Request for records of record_type, recursion is asked of resolvers but not of authoritative servers
+pub fn request(id: u16, name: &str, record_type: u16, recursion: bool) -> Vec<u8> {
+    let flags = if recursion { RECURSION_DESIRED } else { 0 };
+    let mut buffer = header(id, flags, 1, 0);
+    push_name(&mut buffer, name);
+    buffer.extend(record_type.to_be_bytes());
+    buffer.extend(CLASS_IN.to_be_bytes());
+    buffer
+}
+
+/// First question of a request
+pub fn question(message: &[u8]) -> Result<Question> {
+    let id = u16_at(message, 0)?;
+    if u16_at(message, 4)? == 0 {
+        return Err(malformed());
+    }
+    let (name, position) = read_name(message, 12)?;
+    Ok(Question {
+        id,
+        name,
+        record_type: u16_at(message, position)?,
+    })
+}
+
+/// Response to question with answers, or name error if the name does not exist
+pub fn response(question: &Question, answers: Option<&[RData]>) -> Vec<u8> {
+    let rcode = if answers.is_none() { NXDOMAIN } else { 0 };
+    let answers = answers.unwrap_or_default();
+    let mut buffer = header(
+        question.id,
+        RESPONSE | RECURSION_DESIRED | RECURSION_AVAILABLE | rcode,
+        1,
+        answers.len() as u16,
+    );
+    push_name(&mut buffer, &question.name);
+    buffer.extend(question.record_type.to_be_bytes());
+    buffer.extend(CLASS_IN.to_be_bytes());
+    for data in answers {
+        let rdata = match data {
+            RData::A(address) => address.octets().to_vec(),
+            RData::Aaaa(address) => address.octets().to_vec(),
+            RData::Cname(name) | RData::Ns(name) => {
+                let mut rdata = vec![];
+                push_name(&mut rdata, name);
+                rdata
+            }
+            RData::Txt(text) => text
+                .as_bytes()
+                .chunks(255)
+                .flat_map(|chunk| std::iter::once(chunk.len() as u8).chain(chunk.iter().copied()))
+                .collect(),
+            RData::Other(_) => vec![],
+        };
+        // pointer to the name of the question
+        buffer.extend([0xC0, 12]);
+        buffer.extend(data.record_type().to_be_bytes());
+        buffer.extend(CLASS_IN.to_be_bytes());
+        buffer.extend(TTL.to_be_bytes());
+        buffer.extend((rdata.len() as u16).to_be_bytes());
+        buffer.extend(rdata);
+    }
+    buffer
+}
+
+fn rdata(message: &[u8], record_type: u16, data: &[u8], position: usize) -> Result<RData> {
+    Ok(match record_type {
+        A => RData::A(<[u8; 4]>::try_from(data).map_err(|_| malformed())?.into()),
+        AAAA => RData::Aaaa(<[u8; 16]>::try_from(data).map_err(|_| malformed())?.into()),
+        CNAME => RData::Cname(read_name(message, position)?.0),
+        NS => RData::Ns(read_name(message, position)?.0),
+        TXT => {
+            let mut text = vec![];
+            let mut rest = data;
+            while let Some((length, tail)) = rest.split_first() {
+                let length = (*length as usize).min(tail.len());
+                text.extend(&tail[..length]);
+                rest = &tail[length..];
+            }
+            RData::Txt(String::from_utf8_lossy(&text).into_owned())
+        }
+        other => RData::Other(other),
+    })
+}
+
+/// Answer records of a response to request id, none if the name does not exist
+pub fn answers(id: u16, message: &[u8]) -> Result<Vec<Record>> {
+    let flags = u16_at(message, 2)?;
+    if u16_at(message, 0)? != id || flags & RESPONSE == 0 {
+        return Err(malformed());
+    }
+    if flags & TRUNCATED != 0 {
+        return Err(Error::Rejected("dns response truncated".to_owned()));
+    }
+    match flags & 0x000F {
+        0 => {}
+        NXDOMAIN => return Ok(vec![]),
+        rcode => return Err(Error::Rejected(format!("dns response code {rcode}"))),
+    }
+    let mut position = 12;
+    for _ in 0..u16_at(message, 4)? {
+        position = read_name(message, position)?.1 + 4;
+    }
+    let mut records = vec![];
+    for _ in 0..u16_at(message, 6)? {
+        let (name, next) = read_name(message, position)?;
+        let record_type = u16_at(message, next)?;
+        let length = u16_at(message, next + 8)? as usize;
+        let start = next + 10;
+        let data = message.get(start..start + length).ok_or_else(malformed)?;
+        records.push(Record {
+            name,
+            data: rdata(message, record_type, data, start)?,
+        });
+        position = start + length;
+    }
+    Ok(records)
+}
+
+/// Sends a request to server and waits at most timeout for the answer
+pub fn lookup(
+    server: SocketAddr,
+    name: &str,
+    record_type: u16,
+    recursion: bool,
+    timeout: Duration,
+) -> Result<Vec<Record>> {
+    let local: SocketAddr = if server.is_ipv4() {
+        (Ipv4Addr::UNSPECIFIED, 0).into()
+    } else {
+        (Ipv6Addr::UNSPECIFIED, 0).into()
+    };
+    let socket = UdpSocket::bind(local)?;
+    socket.set_read_timeout(Some(timeout))?;
+    socket.connect(server)?;
+    let id = RandomState::new().hash_one(name) as u16;
+    socket.send(&request(id, name, record_type, recursion))?;
+    let mut buffer = [0u8; 4096];
+    let length = socket.recv(&mut buffer)?;
+    answers(id, &buffer[..length])
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::Ipv4Addr;
+
+    use super::{RData, Record, TXT, answers, question, request, response};
+
+    #[test]
+    fn round_trip() {
+        let request = request(7, "_acme-challenge.Example.nl.", TXT, false);
+        let question = question(&request).unwrap();
+        assert_eq!(question.name, "_acme-challenge.example.nl");
+        assert_eq!(question.record_type, TXT);
+
+        let long = "x".repeat(300);
+        let data = [
+            RData::Txt("abc".to_owned()),
+            RData::Txt(long.clone()),
+            RData::A(Ipv4Addr::LOCALHOST),
+            RData::Ns("ns0.transip.net".to_owned()),
+        ];
+        let records = answers(7, &response(&question, Some(&data))).unwrap();
+        assert_eq!(
+            records,
+            data.into_iter()
+                .map(|data| Record {
+                    name: "_acme-challenge.example.nl".to_owned(),
+                    data
+                })
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(records[1].data, RData::Txt(long));
+    }
+
+    #[test]
+    fn name_error() {
+        let question = question(&request(1, "www.example.nl", TXT, true)).unwrap();
+        assert!(answers(1, &response(&question, None)).unwrap().is_empty());
+        assert!(answers(2, &response(&question, None)).is_err());
+        assert!(answers(1, &[0, 1]).is_err());
+    }
+}
@@ -0,0 +1,329 @@
+use std::path::Path;
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, Result};
+
+/// Flag of a key that signs the zone
+const ZONE_KEY: u16 = 0x0100;
+/// Flag of a key revoked in a rollover of rfc 5011
+const REVOKE: u16 = 0x0080;
+/// Flag of a key signing key, the key the registry publishes a ds record for
+const SECURE_ENTRY_POINT: u16 = 0x0001;
+const CLASSES: &[&str] = &["IN", "CH", "HS"];
+/// Protocol field of every dnskey record
+const PROTOCOL: u8 = 3;
+/// Algorithms the registries accept, rsa/sha-1 variants 5 and 7 included for older zones
+const ALGORITHMS: &[u8] = &[5, 7, 8, 10, 13, 14, 15, 16];
+
+/// Key of a domain as the api publishes it at the registry
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DnsSecEntry {
+    pub key_tag: u16,
+    pub flags: u16,
+    pub algorithm: u8,
+    pub public_key: String,
+}
+
+/// Ds record, only its key tag and algorithm are used to select a key
+#[derive(Debug, PartialEq)]
+struct Ds {
+    key_tag: u16,
+    algorithm: u8,
+}
+
+/// Dnskey record, with the reason it cannot be published if it cannot
+#[derive(Debug, PartialEq)]
+struct Key {
+    entry: DnsSecEntry,
+    problem: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Record {
+    DnsKey(Key),
+    Ds(Ds),
+}
+
+/// Key tag of rfc 4034 appendix B, over the rdata of the dnskey record
+fn key_tag(flags: u16, algorithm: u8, public_key: &[u8]) -> u16 {
+    let rdata = flags
+        .to_be_bytes()
+        .into_iter()
+        .chain([PROTOCOL, algorithm])
+        .chain(public_key.iter().copied());
+    let sum = rdata.enumerate().fold(0u32, |sum, (index, byte)| {
+        sum + if index % 2 == 0 {
+            u32::from(byte) << 8
+        } else {
+            u32::from(byte)
+        }
+    });
+    ((sum + (sum >> 16)) & 0xffff) as u16
+}
+
+fn invalid(line: &str, reason: &str) -> Error {
+    Error::Rejected(format!("{reason} in {line}"))
+}
+
+fn number<T: std::str::FromStr>(field: Option<&str>, line: &str) -> Result<T> {
+    field
+        .and_then(|field| field.parse().ok())
+        .ok_or_else(|| invalid(line, "number expected"))
+}
+
+/// Record of the apex of domain in a line without comments, None for other names and types
+///
+/// A line without owner has the owner of the line before it, apex tells whether that is domain.
+fn record(line: &str, domain: &str, apex: &mut bool) -> Result<Option<Record>> {
+    let mut fields = line.split_whitespace().peekable();
+    if !line.starts_with(char::is_whitespace)
+        && let Some(owner) = fields.next()
+    {
+        let owner = owner.trim_end_matches('.').to_ascii_lowercase();
+        *apex = owner == "@" || owner == domain;
+    }
+    if !*apex {
+        return Ok(None);
+    }
+    // ttl and class come before the type, in any order
+    while fields
+        .next_if(|field| {
+            field.parse::<u32>().is_ok() || CLASSES.contains(&field.to_ascii_uppercase().as_str())
+        })
+        .is_some()
+    {}
+    let Some(record_type) = fields.next().map(str::to_ascii_uppercase) else {
+        return Ok(None);
+    };
+    let mut rdata = fields;
+    if record_type == "DS" {
+        return Ok(Some(Record::Ds(Ds {
+            key_tag: number(rdata.next(), line)?,
+            algorithm: number(rdata.next(), line)?,
+        })));
+    }
+    if record_type != "DNSKEY" {
+        return Ok(None);
+    }
+    let flags = number::<u16>(rdata.next(), line)?;
+    let protocol = number::<u8>(rdata.next(), line)?;
+    let algorithm = number::<u8>(rdata.next(), line)?;
+    let public_key = rdata.collect::<String>();
+    let bytes = STANDARD
+        .decode(&public_key)
+        .map_err(|_| invalid(line, "public key not base64"))?;
+    let problem = if flags & ZONE_KEY == 0 {
+        Some(format!("flags {flags} without the zone key bit"))
+    } else if flags & REVOKE != 0 {
+        Some(format!("flags {flags} of a revoked key"))
+    } else if protocol != PROTOCOL {
+        Some(format!("protocol not {PROTOCOL}"))
+    } else if !ALGORITHMS.contains(&algorithm) {
+        Some(format!("algorithm {algorithm} not supported"))
+    } else {
+        None
+    };
+    Ok(Some(Record::DnsKey(Key {
+        entry: DnsSecEntry {
+            key_tag: key_tag(flags, algorithm, &bytes),
+            flags,
+            algorithm,
+            public_key,
+        },
+        problem: problem.map(|reason| format!("{reason} in {line}")),
+    })))
+}
+
+/// Apex records of domain in zone file text, with comments removed and parentheses joined
+fn records(text: &str, domain: &str) -> Result<Vec<Record>> {
+    let mut records = vec![];
+    let mut pending = String::new();
+    let mut depth = 0usize;
+    let mut apex = true;
+    for line in text.lines() {
+        let line = line.split_once(';').map_or(line, |(data, _)| data);
+        depth = (depth + line.matches('(').count()).saturating_sub(line.matches(')').count());
+        pending.push_str(&line.replace(['(', ')'], " "));
+        if depth == 0 {
+            if let Some(record) = record(&pending, domain, &mut apex)? {
+                records.push(record);
+            }
+            pending.clear();
+        } else {
+            pending.push(' ');
+        }
+    }
+    Ok(records)
+}
+
+/// Key signing keys of domain in the output files of a zone signer
+///
+/// Files hold dnskey records, like the .key files of dnssec-keygen or a signed zone.
+/// If they hold ds records too, like a dsset file, only the keys of those ds records are taken
+/// and each of them must be valid.
+/// Otherwise the valid keys with the secure entry point flag are taken,
+/// revoked keys and keys of algorithms that are rolled over are skipped.
+pub fn entries<P: AsRef<Path>>(domain: &str, files: &[P]) -> Result<Vec<DnsSecEntry>> {
+    let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+    let mut keys = vec![];
+    let mut ds = vec![];
+    for file in files {
+        let text = std::fs::read_to_string(file)?;
+        for record in records(&text, &domain)? {
+            match record {
+                Record::DnsKey(key) if !keys.contains(&key) => keys.push(key),
+                Record::DnsKey(_) => {}
+                Record::Ds(record) => ds.push(record),
+            }
+        }
+    }
+    if keys.is_empty() {
+        let hint = if ds.is_empty() {
+            ""
+        } else {
+            ", ds records have no public key"
+        };
+        return Err(Error::Rejected(format!(
+            "no dnskey records of {domain} found{hint}"
+        )));
+    }
+    keys.retain(|key| {
+        if ds.is_empty() {
+            if let Some(problem) = key.problem.as_ref() {
+                tracing::info!("Skipped dnskey, {problem}");
+            }
+            key.problem.is_none() && key.entry.flags & SECURE_ENTRY_POINT != 0
+        } else {
+            ds.iter()
+                .any(|ds| ds.key_tag == key.entry.key_tag && ds.algorithm == key.entry.algorithm)
+        }
+    });
+    if let Some(problem) = keys.iter().find_map(|key| key.problem.as_ref()) {
+        return Err(Error::Rejected(problem.clone()));
+    }
+    if keys.is_empty() {
+        return Err(Error::Rejected(format!(
+            "no key signing key of {domain} found"
+        )));
+    }
+    Ok(keys.into_iter().map(|key| key.entry).collect())
+}
+
+#[cfg(test)]
+mod test {
+    use base64::{Engine, engine::general_purpose::STANDARD};
+    use transip_test_support::TempDir;
+
+    use super::{DnsSecEntry, entries, key_tag};
+
+    /// Zone key of the example in rfc 4034 section 5.4, with key tag 60485
+    const KEY: &str = "AQOeiiR0GOMYkDshWoSKz9XzfwJr1AYtsmx3TGkJaNXVbfi/2pHm822aJ5iI9BMzNXxeYCmZDRD99WYwYqUSdjMmmAphXdvxegXd/M5+X7OrzKBaMbCVdFLUUh6DhweJBjEVv5f2wwjM9XzcnOf+EPbtG9DMBmADjFDc2w/rljwvFw==";
+
+    fn dir(name: &str) -> TempDir {
+        TempDir::new(&format!("transip-dnssec-{name}"))
+    }
+
+    #[test]
+    fn rfc_key_tag() {
+        assert_eq!(key_tag(256, 5, &STANDARD.decode(KEY).unwrap()), 60485);
+    }
+
+    #[test]
+    fn key_file() {
+        let dir = dir("key");
+        let file = dir.join("Kdskey.example.com.+005+60486.key");
+        std::fs::write(
+            &file,
+            format!(
+                "; This is a key-signing key, keyid 60486, for dskey.example.com.\n\
+                 dskey.example.com. 86400 IN DNSKEY 257 3 5 ( {}\n {} ) ; ksk\n",
+                &KEY[..80],
+                &KEY[80..]
+            ),
+        )
+        .unwrap();
+        assert_eq!(
+            entries("dskey.example.com", &[file]).unwrap(),
+            vec![DnsSecEntry {
+                key_tag: 60486,
+                flags: 257,
+                algorithm: 5,
+                public_key: KEY.to_owned(),
+            }]
+        );
+    }
+
+    #[test]
+    fn signed_zone() {
+        let dir = dir("zone");
+        let zone = dir.join("dskey.example.com.signed");
+        std::fs::write(
+            &zone,
+            format!(
+                "dskey.example.com. 3600 IN SOA ns1 admin 1 2 3 4 5\n\
+                 \t\t3600 DNSKEY 256 3 5 {KEY}\n\
+                 \t\t3600 DNSKEY 257 3 5 {KEY}\n\
+                 \t\t3600 RRSIG DNSKEY 5 3 3600 20300101000000 20200101000000 60485 dskey.example.com. c2ln\n\
+                 www.dskey.example.com. 3600 A 192.0.2.1\n\
+                 \t\t3600 DNSKEY 257 3 8 {KEY}\n"
+            ),
+        )
+        .unwrap();
+        let keys = entries("dskey.example.com.", &[&zone]).unwrap();
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys[0].flags, 257);
+
+        let dsset = dir.join("dsset-dskey.example.com.");
+        std::fs::write(
+            &dsset,
+            "dskey.example.com. IN DS 60485 5 1 2BB183AF5F22588179A53B0A98631FAD1A292118\n",
+        )
+        .unwrap();
+        let keys = entries("dskey.example.com", &[&dsset, &zone]).unwrap();
+        assert_eq!(keys.len(), 1);
+        assert_eq!((keys[0].key_tag, keys[0].flags), (60485, 256));
+        assert!(entries("dskey.example.com", &[&dsset]).is_err());
+        assert!(entries("other.example.com", &[&zone]).is_err());
+    }
+
+    #[test]
+    fn rollover() {
+        let dir = dir("rollover");
+        let zone = dir.join("example.com.signed");
+        std::fs::write(
+            &zone,
+            format!(
+                "example.com. 3600 IN DNSKEY 385 3 13 {KEY}\n\
+                 example.com. 3600 IN DNSKEY 257 3 3 {KEY}\n\
+                 example.com. 3600 IN DNSKEY 257 3 13 {KEY}\n"
+            ),
+        )
+        .unwrap();
+        let keys = entries("example.com", &[&zone]).unwrap();
+        assert_eq!(keys.len(), 1);
+        assert_eq!((keys[0].flags, keys[0].algorithm), (257, 13));
+
+        let revoked = key_tag(385, 13, &STANDARD.decode(KEY).unwrap());
+        let dsset = dir.join("dsset-example.com.");
+        std::fs::write(&dsset, format!("example.com. IN DS {revoked} 13 2 00\n")).unwrap();
+        assert!(entries("example.com", &[&dsset, &zone]).is_err());
+    }
+
+    #[test]
+    fn invalid() {
+        let dir = dir("invalid");
+        let file = dir.join("invalid.key");
+        for record in [
+            format!("example.com. DNSKEY 257 3 1 {KEY}"),
+            format!("example.com. DNSKEY 257 2 13 {KEY}"),
+            format!("example.com. DNSKEY 1 3 13 {KEY}"),
+            "example.com. DNSKEY 257 3 13 not-base64!".to_owned(),
+        ] {
+            std::fs::write(&file, &record).unwrap();
+            assert!(entries("example.com", &[&file]).is_err(), "{record}");
+        }
+    }
+}
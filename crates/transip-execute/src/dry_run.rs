@@ -0,0 +1,307 @@
+use serde::Serialize;
+use serde_json::{Value, json};
+use transip::api::dns::DnsEntry;
+use transip_command::{
+    DnsCommand, DnssecCommand, DomainCommand, EmailBoxCommand, EmailForwardCommand, REDACTED,
+    SubCommand, VpsCommand,
+};
+
+use crate::{Client, Result, acme::AcmeRecord, dnssec, name_servers};
+
+/// Request a mutating command would send to the api when dry-run is on
+#[derive(Debug, PartialEq, Serialize)]
+pub struct PlannedRequest {
+    pub method: &'static str,
+    pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body: Option<Value>,
+}
+
+impl PlannedRequest {
+    fn new(method: &'static str, url: String, body: Option<Value>) -> Self {
+        Self { method, url, body }
+    }
+}
+
+fn dns_entry(entry: &transip_command::DnsEntry) -> DnsEntry {
+    DnsEntry {
+        name: entry.name.clone(),
+        expire: entry.ttl,
+        entry_type: format!("{:?}", entry.r#type),
+        content: entry.content.clone(),
+    }
+}
+
+impl Client {
+    fn url(&self, path: String) -> String {
+        format!("{}{path}", self.inner.endpoint())
+    }
+
+    fn plan_dns_delete(&self, domain: &str, entry: &DnsEntry) -> PlannedRequest {
+        PlannedRequest::new(
+            "DELETE",
+            self.url(format!("domains/{domain}/dns")),
+            Some(json!({ "dnsEntry": entry })),
+        )
+    }
+
+    fn plan_dns_insert(&self, domain: &str, entry: &DnsEntry) -> PlannedRequest {
+        PlannedRequest::new(
+            "POST",
+            self.url(format!("domains/{domain}/dns")),
+            Some(json!({ "dnsEntry": entry })),
+        )
+    }
+
+    fn plan_acme_delete(
+        &mut self,
+        record: &AcmeRecord,
+        matches: impl Fn(&DnsEntry) -> bool,
+    ) -> Result<Vec<PlannedRequest>> {
+        self.inner.dns_entry_list(&record.zone).map(|entries| {
+            entries
+                .iter()
+                .filter(|entry| matches(entry))
+                .map(|entry| self.plan_dns_delete(&record.zone, entry))
+                .collect()
+        })
+    }
+
+    fn plan_dns(&mut self, command: &DnsCommand) -> Option<Result<Vec<PlannedRequest>>> {
+        match command {
+            DnsCommand::AcmeValidationDelete {
+                domain,
+                challenge,
+                follow_cname,
+            } => Some(self.acme_record(domain, *follow_cname).and_then(|record| {
+                self.plan_acme_delete(&record, record.deleted(challenge.as_deref())?)
+            })),
+            DnsCommand::AcmeValidationSet {
+                domain,
+                challenge,
+                append,
+                follow_cname,
+            } => Some(self.acme_record(domain, *follow_cname).and_then(|record| {
+                let replaced = (*append || record.delegated).then_some(challenge.as_str());
+                let mut requests = self.plan_acme_delete(&record, record.matches(replaced))?;
+                requests.push(self.plan_dns_insert(&record.zone, &record.entry(challenge)));
+                Ok(requests)
+            })),
+            DnsCommand::Delete(entry) => Some(Ok(vec![
+                self.plan_dns_delete(&entry.domain, &dns_entry(entry)),
+            ])),
+            DnsCommand::Insert(entry) => Some(Ok(vec![
+                self.plan_dns_insert(&entry.domain, &dns_entry(entry)),
+            ])),
+            _ => None,
+        }
+    }
+
+    fn plan_dnssec(&self, domain: &str, entries: Vec<dnssec::DnsSecEntry>) -> PlannedRequest {
+        PlannedRequest::new(
+            "PUT",
+            self.url(format!("domains/{domain}/dnssec")),
+            Some(json!({ "dnsSecEntries": entries })),
+        )
+    }
+
+    fn plan_domain(&self, command: &DomainCommand) -> Option<Result<PlannedRequest>> {
+        match command {
+            DomainCommand::Dnssec(DnssecCommand::Set { domain, files }) => Some(
+                dnssec::entries(domain, files).map(|entries| self.plan_dnssec(domain, entries)),
+            ),
+            DomainCommand::Dnssec(DnssecCommand::Delete { domain }) => {
+                Some(Ok(self.plan_dnssec(domain, vec![])))
+            }
+            DomainCommand::NameserversSet {
+                domain,
+                nameservers,
+            } => Some(name_servers(domain, nameservers).map(|nameservers| {
+                PlannedRequest::new(
+                    "PUT",
+                    self.url(format!("domains/{domain}/nameservers")),
+                    Some(json!({ "nameservers": nameservers })),
+                )
+            })),
+            _ => None,
+        }
+    }
+
+    fn plan_email_box(&self, command: &EmailBoxCommand) -> Option<PlannedRequest> {
+        match command {
+            EmailBoxCommand::Delete { domain, id } => Some(PlannedRequest::new(
+                "DELETE",
+                self.url(format!("email/{domain}/mailboxes/{id}")),
+                None,
+            )),
+            EmailBoxCommand::Insert {
+                domain,
+                username,
+                password: _,
+                mb_size,
+            } => Some(PlannedRequest::new(
+                "POST",
+                self.url(format!("email/{domain}/mailboxes")),
+                Some(json!({
+                    "localPart": username,
+                    "maxDiskUsage": mb_size,
+                    "password": REDACTED,
+                })),
+            )),
+            _ => None,
+        }
+    }
+
+    fn plan_email_forward(&self, command: &EmailForwardCommand) -> Option<PlannedRequest> {
+        match command {
+            EmailForwardCommand::Delete { domain, id } => Some(PlannedRequest::new(
+                "DELETE",
+                self.url(format!("email/{domain}/mail-forwards/{id}")),
+                None,
+            )),
+            EmailForwardCommand::Insert {
+                domain,
+                local_part,
+                forward_to,
+            } => Some(PlannedRequest::new(
+                "POST",
+                self.url(format!("email/{domain}/mail-forwards")),
+                Some(json!({ "localPart": local_part, "forwardTo": forward_to })),
+            )),
+            _ => None,
+        }
+    }
+
+    fn plan_vps_action(&self, name: &str, action: &str) -> PlannedRequest {
+        PlannedRequest::new(
+            "PATCH",
+            self.url(format!("vps/{name}")),
+            Some(json!({ "action": action })),
+        )
+    }
+
+    fn plan_vps_lock(&mut self, name: &str, locked: bool) -> Result<PlannedRequest> {
+        self.inner.vps(name).map(|mut vps| {
+            vps.is_customer_locked = locked;
+            PlannedRequest::new(
+                "PUT",
+                self.url(format!("vps/{name}")),
+                Some(json!({ "vps": vps })),
+            )
+        })
+    }
+
+    fn plan_vps(&mut self, command: &VpsCommand) -> Option<Result<PlannedRequest>> {
+        match command {
+            VpsCommand::Lock { name } => Some(self.plan_vps_lock(name, true)),
+            VpsCommand::Unlock { name } => Some(self.plan_vps_lock(name, false)),
+            VpsCommand::Reset { name } => Some(Ok(self.plan_vps_action(name, "reset"))),
+            VpsCommand::Start { name } => Some(Ok(self.plan_vps_action(name, "start"))),
+            VpsCommand::Stop { name } => Some(Ok(self.plan_vps_action(name, "stop"))),
+            _ => None,
+        }
+    }
+
+    /// Requests a mutating command would send, None if the command does not change anything
+    pub(crate) fn plan(&mut self, command: &SubCommand) -> Option<Result<Vec<PlannedRequest>>> {
+        match command {
+            SubCommand::Dns(command) => self.plan_dns(command),
+            SubCommand::Domain(command) => self.plan_domain(command).map(|r| r.map(|r| vec![r])),
+            SubCommand::EmailBox(command) => self.plan_email_box(command).map(|r| Ok(vec![r])),
+            SubCommand::EmailForward(command) => {
+                self.plan_email_forward(command).map(|r| Ok(vec![r]))
+            }
+            SubCommand::Vps(command) => self.plan_vps(command).map(|r| r.map(|r| vec![r])),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+    use transip_command::TransipCommand;
+
+    use super::PlannedRequest;
+    use crate::{Client, CommandOutput, Memory};
+
+    fn dry_run_client() -> Client {
+        let mut client = Client::new(Memory::default());
+        client.set_dry_run(true);
+        client
+    }
+
+    fn plan(line: &str) -> Vec<PlannedRequest> {
+        let command = line.parse::<TransipCommand>().unwrap();
+        match dry_run_client().execute_output(&command.command).unwrap() {
+            CommandOutput::DryRun(requests) => requests,
+            _ => panic!("{line} not planned"),
+        }
+    }
+
+    #[test]
+    fn dns_insert() {
+        let requests = plan("dns insert example.nl www 300 A 1.2.3.4");
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, "POST");
+        assert!(requests[0].url.ends_with("domains/example.nl/dns"));
+        assert_eq!(
+            requests[0].body,
+            Some(
+                json!({"dnsEntry": {"name": "www", "expire": 300, "type": "A", "content": "1.2.3.4"}})
+            )
+        );
+    }
+
+    #[test]
+    fn api_url() {
+        let mut client = Client::new(Memory::default());
+        client.set_dry_run(true);
+        let command = "vps stop vps1".parse::<TransipCommand>().unwrap();
+        match client.execute_output(&command.command).unwrap() {
+            CommandOutput::DryRun(requests) => {
+                assert_eq!(requests[0].url, "memory:/vps/vps1")
+            }
+            _ => panic!("vps stop not planned"),
+        }
+    }
+
+    #[test]
+    fn mailbox_password_redacted() {
+        let requests = plan("email-box insert example.nl info secret 1024");
+        assert_eq!(
+            requests[0].body.as_ref().unwrap()["password"],
+            json!(super::REDACTED)
+        );
+    }
+
+    #[test]
+    fn nameservers_set() {
+        let requests =
+            plan("domain nameservers-set example.nl ns1.example.nl=192.0.2.1 ns0.transip.net");
+        assert_eq!(requests[0].method, "PUT");
+        assert!(requests[0].url.ends_with("domains/example.nl/nameservers"));
+        assert_eq!(
+            requests[0].body,
+            Some(json!({"nameservers": [
+                {"hostname": "ns1.example.nl", "ipv4": "192.0.2.1", "ipv6": null},
+                {"hostname": "ns0.transip.net", "ipv4": null, "ipv6": null},
+            ]}))
+        );
+    }
+
+    #[test]
+    fn dnssec_delete() {
+        let requests = plan("domain dnssec delete example.nl");
+        assert_eq!(requests[0].method, "PUT");
+        assert!(requests[0].url.ends_with("domains/example.nl/dnssec"));
+        assert_eq!(requests[0].body, Some(json!({"dnsSecEntries": []})));
+    }
+
+    #[test]
+    fn vps_start() {
+        let requests = plan("vps start vps1");
+        assert_eq!(requests[0].method, "PATCH");
+        assert_eq!(requests[0].body, Some(json!({"action": "start"})));
+    }
+}
@@ -1,62 +1,308 @@
-use std::{mem::size_of, time::Duration};
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::Duration,
+};
 
-use serde::{Serialize, Serializer};
+use serde::Serializer;
 pub use transip::configuration_from_environment;
-use transip::{Configuration, api::email::MailForwardInsert};
-pub use transip::{Error, Result};
-use transip_command::{
-    DnsCommand, DomainCommand, EmailBoxCommand, EmailForwardCommand, InvoiceCommand, OnError,
-    ProductCommand, VpsCommand,
+use transip::{
+    Configuration,
+    api::{domain::NameServer, email::MailForwardInsert},
 };
+pub use transip::{Error, Result};
+use transip_command::{ACME_CHALLENGE, OnError, ProductCommand, Switch};
+
+pub use api::{Api, Settings};
+pub use backend::Backend;
+pub use cassette::{Interaction, Recorder, Replay, Response};
+pub use dnssec::DnsSecEntry;
+pub use dry_run::PlannedRequest;
+pub use memory::{Fixture, Memory};
+pub use output::{Changed, CommandOutput, Deleted, DnsChange};
+#[cfg(feature = "propagation")]
+pub use propagation::ServerStatus;
+pub use rate_limit::{RateLimit, RateLimitHeaders};
+pub use retry::RetryPolicy;
+
+mod acme;
+mod api;
+mod backend;
+mod cassette;
+#[cfg(feature = "propagation")]
+pub mod dns;
+pub mod dnssec;
+mod dry_run;
+mod memory;
+mod output;
+mod parallel;
+#[cfg(feature = "propagation")]
+mod propagation;
+mod rate_limit;
+mod retry;
 
 // reexport TransipCommand
-pub use transip_command::{ErrorKind, SubCommand, TransipCommand};
+pub use transip_command::{
+    AuditCommand, DnsCommand, DnsEntry, DnssecCommand, DomainCommand, EmailBoxCommand,
+    EmailForwardCommand, ErrorKind, Impact, InvoiceCommand, Nameserver, OutputFormat, RecordType,
+    Redirect, Resource, SubCommand, TransipCommand, VpsCommand,
+};
+
+#[cfg(feature = "propagation")]
+pub use transip_command::Nameservers;
+
+const TRANSIP_API_ENDPOINT: &str = "TRANSIP_API_ENDPOINT";
+
+/// Client configured with the environment variables of [`configuration_from_environment`]
+///
+/// If `TRANSIP_API_ENDPOINT` is set, for example to a local transip-mock,
+/// requests are sent to that endpoint instead, signed in with the same key.
+pub fn client_from_environment() -> Result<Client> {
+    let endpoint = std::env::var(TRANSIP_API_ENDPOINT).ok();
+    let settings = Settings::from(configuration_from_environment()?.as_ref());
+    let mut client = Client::connect(settings.clone(), endpoint.clone())?;
+    client.set_workers(move || {
+        let token_path = PathBuf::from(&settings.token_path);
+        parallel::with_token_lock(&token_path, || {
+            Api::try_new(settings.clone(), endpoint.as_deref())
+        })?
+        .map(|api| Box::new(api) as Box<dyn Backend>)
+    });
+    client.set_retry_policy(RetryPolicy::from_environment()?);
+    Ok(client)
+}
+
+/// Lock that is still usable after a thread panicked while holding it
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
 
 pub struct Client {
-    inner: transip::Client,
+    inner: Box<dyn Backend>,
     onerror: transip_command::OnError,
+    dry_run: bool,
+    confirm: bool,
+    retry: Arc<Mutex<RetryPolicy>>,
+    budget: Arc<Mutex<rate_limit::Budget>>,
+    workers: Option<parallel::Workers>,
+    /// Names of the domains of the account, listed by the first acme command
+    zones: Option<Vec<String>>,
+    /// Token file of the api client, locked when the client is dropped
+    token_path: Option<PathBuf>,
 }
 
 impl Client {
+    /// Client that executes commands with the api calls of backend
+    ///
+    /// Api calls failing with a transient error are retried with the default [`RetryPolicy`].
+    pub fn new(backend: impl Backend + 'static) -> Self {
+        Self::with_shared(
+            Box::new(backend),
+            Arc::new(Mutex::new(RetryPolicy::default())),
+            Arc::new(Mutex::new(rate_limit::Budget::default())),
+        )
+    }
+
+    fn with_shared(
+        backend: Box<dyn Backend>,
+        retry: Arc<Mutex<RetryPolicy>>,
+        budget: Arc<Mutex<rate_limit::Budget>>,
+    ) -> Self {
+        Self {
+            inner: Box::new(retry::Retrying::new(backend, retry.clone(), budget.clone())),
+            onerror: OnError::Print,
+            dry_run: false,
+            confirm: false,
+            retry,
+            budget,
+            workers: None,
+            zones: None,
+            token_path: None,
+        }
+    }
+
     pub fn exit_on_error(&self) -> bool {
         self.onerror == OnError::Exit
     }
+
+    pub fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// When on, mutating commands report the requests they would send instead of sending them
+    pub fn set_dry_run(&mut self, dry_run: bool) {
+        self.dry_run = dry_run;
+    }
+
+    /// Whether destructive commands need confirmation, the client itself never asks
+    pub fn confirm(&self) -> bool {
+        self.confirm
+    }
+
+    pub fn set_confirm(&mut self, confirm: bool) {
+        self.confirm = confirm;
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        *lock(&self.retry)
+    }
+
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        *lock(&self.retry) = policy;
+    }
+
+    /// Api calls remaining before the api starts refusing them, as the api last reported
+    pub fn rate_limit(&self) -> Option<RateLimit> {
+        lock(&self.budget).status(rate_limit::now())
+    }
+
+    /// Saves every api request and response from now on in dir, see [`Recorder`]
+    ///
+    /// Commands in parallel blocks are executed one after another while recording.
+    pub fn record<P: AsRef<Path>>(&mut self, dir: P) -> Result<()> {
+        let inner = std::mem::replace(&mut self.inner, Box::new(Memory::default()));
+        self.inner = Box::new(Recorder::new(inner, dir)?);
+        self.workers = None;
+        Ok(())
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        if let Some(token_path) = self.token_path.take() {
+            let inner = std::mem::replace(&mut self.inner, Box::new(Memory::default()));
+            if let Err(error) = parallel::with_token_lock(&token_path, || drop(inner)) {
+                tracing::error!("Cannot lock token file {}: {error}", token_path.display());
+            }
+        }
+    }
 }
 
 trait Report {
     fn report(self, s: impl Serializer) -> Result<()>;
 }
 
-impl<T: Serialize> Report for Result<T> {
+impl Report for Result<CommandOutput> {
     fn report(self, s: impl Serializer) -> Result<()> {
-        self.map(|result| {
-            if size_of::<T>() > 0 {
-                result.serialize(s).unwrap();
-            }
+        self.map(|output| {
+            output.report(s).unwrap();
         })
     }
 }
 
+impl Client {
+    /// Client of the api endpoint at prefix, or of the api if None, signed in with settings
+    ///
+    /// The rate limit budget is saved next to the token file.
+    pub fn connect(settings: Settings, prefix: Option<String>) -> Result<Self> {
+        let token_path = PathBuf::from(&settings.token_path);
+        parallel::with_token_lock(&token_path, || Api::try_new(settings, prefix.as_deref()))?.map(
+            |api| {
+                let budget = PathBuf::from(format!("{}.ratelimit", api.token_path()));
+                let mut client = Client::new(api);
+                *lock(&client.budget) = rate_limit::Budget::load(budget);
+                client.token_path = Some(token_path);
+                client
+            },
+        )
+    }
+}
+
 impl TryFrom<Box<dyn Configuration>> for Client {
     type Error = transip::Error;
 
     fn try_from(configuration: Box<dyn Configuration>) -> Result<Self> {
-        transip::Client::try_from(configuration).map(|client| Client {
-            inner: client,
-            onerror: OnError::Print,
-        })
+        Client::connect(Settings::from(configuration.as_ref()), None)
     }
 }
 
+/// Nameservers as the api takes them, glue is required inside domain and refused outside it
+pub(crate) fn name_servers(domain: &str, nameservers: &[Nameserver]) -> Result<Vec<NameServer>> {
+    let mut hostnames = nameservers
+        .iter()
+        .map(|nameserver| nameserver.hostname.as_str())
+        .collect::<Vec<_>>();
+    hostnames.sort();
+    if let Some(hostnames) = hostnames.windows(2).find(|pair| pair[0] == pair[1]) {
+        return Err(Error::Rejected(format!(
+            "nameserver {} given twice",
+            hostnames[0]
+        )));
+    }
+    nameservers
+        .iter()
+        .map(
+            |nameserver| match (nameserver.in_zone(domain), nameserver.has_glue()) {
+                (true, false) => Err(Error::Rejected(format!(
+                    "nameserver {} is inside {domain} and needs glue addresses",
+                    nameserver.hostname
+                ))),
+                (false, true) => Err(Error::Rejected(format!(
+                    "nameserver {} is outside {domain} and takes no glue addresses",
+                    nameserver.hostname
+                ))),
+                _ => Ok(NameServer {
+                    hostname: nameserver.hostname.clone(),
+                    ipv4: nameserver.ipv4.map(|ipv4| ipv4.to_string()),
+                    ipv6: nameserver.ipv6.map(|ipv6| ipv6.to_string()),
+                }),
+            },
+        )
+        .collect()
+}
+
 impl Client {
-    fn execute_dns(&mut self, command: &DnsCommand, s: impl Serializer) -> Result<()> {
-        use transip::api::dns::{DnsApi, DnsEntry};
+    fn dns_entry_delete_all(
+        &mut self,
+        domain: &str,
+        f: impl Fn(&transip::api::dns::DnsEntry) -> bool,
+    ) -> Result<Vec<transip::api::dns::DnsEntry>> {
+        let deleted = self
+            .inner
+            .dns_entry_list(domain)?
+            .into_iter()
+            .filter(f)
+            .collect::<Vec<_>>();
+        for dns_entry in deleted.iter() {
+            self.inner.dns_entry_delete(domain, dns_entry.clone())?;
+        }
+        Ok(deleted)
+    }
+
+    /// Reports the entries mutation removed from and added to the zone of domain, as the api lists them
+    fn dns_change(
+        &mut self,
+        domain: &str,
+        mutation: impl FnOnce(&mut dyn Backend) -> Result<()>,
+    ) -> Result<CommandOutput> {
+        let before = self.inner.dns_entry_list(domain)?;
+        mutation(self.inner.as_mut())?;
+        self.inner
+            .dns_entry_list(domain)
+            .map(|after| CommandOutput::DnsChange(DnsChange::between(&before, &after)))
+    }
+
+    fn execute_dns(&mut self, command: &DnsCommand) -> Result<CommandOutput> {
+        use transip::api::dns::DnsEntry;
         match command {
-            DnsCommand::AcmeValidationDelete { domain } => self
+            DnsCommand::AcmeValidationDelete {
+                domain,
+                challenge,
+                follow_cname,
+            } => {
+                let record = self.acme_record(domain, *follow_cname)?;
+                self.dns_entry_delete_all(&record.zone, record.deleted(challenge.as_deref())?)
+                    .map(|deleted| {
+                        CommandOutput::DnsChange(DnsChange {
+                            deleted,
+                            ..Default::default()
+                        })
+                    })
+            }
+            DnsCommand::List { domain } => self
                 .inner
-                .dns_entry_delete_all(domain, DnsEntry::is_acme_challenge)
-                .report(s),
-            DnsCommand::List { domain } => self.inner.dns_entry_list(domain).report(s),
+                .dns_entry_list(domain)
+                .map(CommandOutput::DnsEntries),
             DnsCommand::Delete(dns_entry) => {
                 let entry = DnsEntry {
                     name: dns_entry.name.clone(),
@@ -64,9 +310,9 @@ impl Client {
                     entry_type: format!("{:?}", dns_entry.r#type),
                     content: dns_entry.content.clone(),
                 };
-                self.inner
-                    .dns_entry_delete(&dns_entry.domain, entry)
-                    .report(s)
+                self.dns_change(&dns_entry.domain, |backend| {
+                    backend.dns_entry_delete(&dns_entry.domain, entry)
+                })
             }
             DnsCommand::Insert(dns_entry) => {
                 let entry = DnsEntry {
@@ -75,40 +321,123 @@ impl Client {
                     entry_type: format!("{:?}", dns_entry.r#type),
                     content: dns_entry.content.clone(),
                 };
+                self.dns_change(&dns_entry.domain, |backend| {
+                    backend.dns_entry_insert(&dns_entry.domain, entry)
+                })
+            }
+            DnsCommand::AcmeValidationSet {
+                domain,
+                challenge,
+                append,
+                follow_cname,
+            } => {
+                let record = self.acme_record(domain, *follow_cname)?;
+                let replaced = (*append || record.delegated).then_some(challenge.as_str());
+                let deleted = self.dns_entry_delete_all(&record.zone, record.matches(replaced))?;
+                let entry = record.entry(challenge);
                 self.inner
-                    .dns_entry_insert(&dns_entry.domain, entry)
-                    .report(s)
+                    .dns_entry_insert(&record.zone, entry.clone())
+                    .map(|_| {
+                        CommandOutput::DnsChange(DnsChange {
+                            deleted,
+                            inserted: vec![entry],
+                        })
+                    })
             }
-            DnsCommand::AcmeValidationSet { domain, challenge } => self
-                .inner
-                .dns_entry_delete_all(domain, DnsEntry::is_acme_challenge)
-                .and_then(|_| {
-                    self.inner
-                        .dns_entry_insert(domain, DnsEntry::new_acme_challenge(60, challenge))
-                })
-                .report(s),
             #[cfg(feature = "propagation")]
-            DnsCommand::AcmeValidationCheck { domain, challenge } => {
-                acme_validation_propagation::wait_sync(domain.clone(), challenge.clone())
-                    .map_err(|_| Error::AcmeChallege)
+            DnsCommand::AcmeValidationCheck {
+                domain,
+                challenge,
+                nameservers,
+                interval,
+                timeout,
+                follow_cname,
+                resolver,
+            } => {
+                let name = if *follow_cname {
+                    self.acme_record(domain, true)?.fqdn()
+                } else {
+                    format!("{ACME_CHALLENGE}.{domain}")
+                };
+                propagation::servers(&name, nameservers, *resolver)
+                    .and_then(|servers| {
+                        propagation::wait(
+                            &name,
+                            challenge,
+                            &servers,
+                            Duration::from_secs(*interval),
+                            Duration::from_secs(*timeout),
+                        )
+                    })
+                    .map(CommandOutput::Propagation)
             }
         }
     }
 
-    fn execute_domain(&mut self, command: &DomainCommand, s: impl Serializer) -> Result<()> {
-        use transip::api::domain::DomainApi;
+    fn execute_dnssec(&mut self, command: &DnssecCommand) -> Result<CommandOutput> {
         match command {
-            DomainCommand::Item { domain } => self.inner.domain_item(domain).report(s),
-            DomainCommand::List => self.inner.domain_list().report(s),
+            DnssecCommand::List { domain } => self
+                .inner
+                .domain_dnssec_list(domain)
+                .map(CommandOutput::DnsSecEntries),
+            DnssecCommand::Set { domain, files } => {
+                let entries = dnssec::entries(domain, files)?;
+                let before = self.inner.domain_dnssec_list(domain)?;
+                self.inner
+                    .domain_dnssec_set(domain, entries)
+                    .and_then(|_| self.inner.domain_dnssec_list(domain))
+                    .map(|after| CommandOutput::DnsSecChanged(Changed { before, after }))
+            }
+            DnssecCommand::Delete { domain } => {
+                let entries = self.inner.domain_dnssec_list(domain)?;
+                self.inner
+                    .domain_dnssec_set(domain, vec![])
+                    .map(|_| CommandOutput::DnsSecDeleted(Deleted { deleted: entries }))
+            }
+        }
+    }
+
+    fn execute_domain(&mut self, command: &DomainCommand) -> Result<CommandOutput> {
+        match command {
+            DomainCommand::Dnssec(command) => self.execute_dnssec(command),
+            DomainCommand::Item { domain } => {
+                self.inner.domain_item(domain).map(CommandOutput::Domain)
+            }
+            DomainCommand::List => self.inner.domain_list().map(CommandOutput::Domains),
+            DomainCommand::Nameservers { domain } => self
+                .inner
+                .domain_nameservers(domain)
+                .map(CommandOutput::Nameservers),
+            DomainCommand::NameserversSet {
+                domain,
+                nameservers,
+            } => {
+                let nameservers = name_servers(domain, nameservers)?;
+                let before = self.inner.domain_nameservers(domain)?;
+                self.inner
+                    .domain_nameservers_set(domain, nameservers)
+                    .and_then(|_| self.inner.domain_nameservers(domain))
+                    .map(|after| CommandOutput::NameserversChanged(Changed { before, after }))
+            }
         }
     }
 
-    fn execute_email_box(&mut self, command: &EmailBoxCommand, s: impl Serializer) -> Result<()> {
-        use transip::api::email::EmailApi;
+    fn execute_email_box(&mut self, command: &EmailBoxCommand) -> Result<CommandOutput> {
         match command {
-            EmailBoxCommand::Item { domain, id } => self.inner.mailbox_item(domain, id).report(s),
-            EmailBoxCommand::List { domain } => self.inner.mailbox_list(domain).report(s),
-            EmailBoxCommand::Delete { domain, id } => self.inner.mailbox_delete(domain, id),
+            EmailBoxCommand::Item { domain, id } => self
+                .inner
+                .mailbox_item(domain, id)
+                .map(CommandOutput::Mailbox),
+            EmailBoxCommand::List { domain } => self
+                .inner
+                .mailbox_list(domain)
+                .map(CommandOutput::Mailboxes),
+            EmailBoxCommand::Delete { domain, id } => {
+                let mailbox = self.inner.mailbox_item(domain, id)?;
+                self.inner
+                    .mailbox_delete(domain, id)
+                    .map(|_| CommandOutput::MailboxDeleted(Deleted { deleted: mailbox }))
+            }
             EmailBoxCommand::Insert {
                 domain,
                 username,
@@ -120,22 +449,27 @@ impl Client {
                     max_disk_usage: *mb_size,
                     password: password.clone(),
                 };
-                self.inner.mailbox_insert(domain, mailbox)
+                self.inner.mailbox_insert(domain, mailbox)?;
+                self.inner.mailbox_list(domain).map(|mailboxes| {
+                    mailboxes
+                        .into_iter()
+                        .find(|mailbox| mailbox.local_part == *username)
+                        .map_or(CommandOutput::Unit, CommandOutput::Mailbox)
+                })
             }
         }
     }
 
-    fn execute_email_forward(
-        &mut self,
-        command: &EmailForwardCommand,
-        s: impl Serializer,
-    ) -> Result<()> {
-        use transip::api::email::EmailApi;
+    fn execute_email_forward(&mut self, command: &EmailForwardCommand) -> Result<CommandOutput> {
         match command {
-            EmailForwardCommand::Item { domain, id } => {
-                self.inner.mailforward_item(domain, id).report(s)
-            }
-            EmailForwardCommand::List { domain } => self.inner.mailforward_list(domain).report(s),
+            EmailForwardCommand::Item { domain, id } => self
+                .inner
+                .mailforward_item(domain, id)
+                .map(CommandOutput::MailForward),
+            EmailForwardCommand::List { domain } => self
+                .inner
+                .mailforward_list(domain)
+                .map(CommandOutput::MailForwards),
             EmailForwardCommand::Insert {
                 domain,
                 local_part,
@@ -145,64 +479,149 @@ impl Client {
                     local_part: local_part.clone(),
                     forward_to: forward_to.clone(),
                 };
-                self.inner.mailforward_insert(domain, mail_forward)
+                self.inner.mailforward_insert(domain, mail_forward)?;
+                self.inner.mailforward_list(domain).map(|forwards| {
+                    forwards
+                        .into_iter()
+                        .filter(|forward| {
+                            forward.local_part == *local_part && forward.forward_to == *forward_to
+                        })
+                        .max_by_key(|forward| forward.id)
+                        .map_or(CommandOutput::Unit, CommandOutput::MailForward)
+                })
+            }
+            EmailForwardCommand::Delete { domain, id } => {
+                let forward = self.inner.mailforward_item(domain, id)?;
+                self.inner
+                    .mailforward_delete(domain, id)
+                    .map(|_| CommandOutput::MailForwardDeleted(Deleted { deleted: forward }))
             }
-            EmailForwardCommand::Delete { domain, id } => self.inner.mailforward_delete(domain, id),
         }
     }
 
-    fn execute_invoice(&mut self, command: &InvoiceCommand, s: impl Serializer) -> Result<()> {
-        use transip::api::account::AccountApi;
+    fn execute_invoice(&mut self, command: &InvoiceCommand) -> Result<CommandOutput> {
         match command {
-            InvoiceCommand::Item { number } => self.inner.invoice(number).report(s),
-            InvoiceCommand::Pdf { number } => self.inner.invoice_pdf(number).report(s),
-            InvoiceCommand::List => self.inner.invoice_list().report(s),
+            InvoiceCommand::Item { number } => {
+                self.inner.invoice(number).map(CommandOutput::Invoice)
+            }
+            InvoiceCommand::Pdf { number } => self
+                .inner
+                .invoice_pdf(number)
+                .map(CommandOutput::InvoicePdf),
+            InvoiceCommand::List => self.inner.invoice_list().map(CommandOutput::Invoices),
         }
     }
 
-    fn execute_product(&mut self, command: &ProductCommand, s: impl Serializer) -> Result<()> {
-        use transip::api::general::GeneralApi;
+    fn execute_product(&mut self, command: &ProductCommand) -> Result<CommandOutput> {
         match command {
-            ProductCommand::Elements { name } => self.inner.product_elements(name).report(s),
-            ProductCommand::List => self.inner.products().report(s),
+            ProductCommand::Elements { name } => self
+                .inner
+                .product_elements(name)
+                .map(CommandOutput::ProductElements),
+            ProductCommand::List => self.inner.products().map(CommandOutput::Products),
         }
     }
 
-    fn execute_vps(&mut self, command: &VpsCommand, s: impl Serializer) -> Result<()> {
-        use transip::api::vps::VpsApi;
+    fn execute_vps(&mut self, command: &VpsCommand) -> Result<CommandOutput> {
         match command {
-            VpsCommand::Item { name } => self.inner.vps(name).report(s),
-            VpsCommand::Lock { name } => self.inner.vps_set_is_locked(name, true).report(s),
-            VpsCommand::Reset { name } => self.inner.vps_reset(name).report(s),
-            VpsCommand::Start { name } => self.inner.vps_start(name).report(s),
-            VpsCommand::Stop { name } => self.inner.vps_stop(name).report(s),
-            VpsCommand::Unlock { name } => self.inner.vps_set_is_locked(name, false).report(s),
-            VpsCommand::List => self.inner.vps_list().report(s),
+            VpsCommand::Item { name } => self.inner.vps(name).map(CommandOutput::Vps),
+            VpsCommand::Lock { name } => self
+                .inner
+                .vps_set_is_locked(name, true)
+                .and_then(|_| self.inner.vps(name))
+                .map(CommandOutput::Vps),
+            VpsCommand::Reset { name } => self
+                .inner
+                .vps_reset(name)
+                .and_then(|_| self.inner.vps(name))
+                .map(CommandOutput::Vps),
+            VpsCommand::Start { name } => self
+                .inner
+                .vps_start(name)
+                .and_then(|_| self.inner.vps(name))
+                .map(CommandOutput::Vps),
+            VpsCommand::Stop { name } => self
+                .inner
+                .vps_stop(name)
+                .and_then(|_| self.inner.vps(name))
+                .map(CommandOutput::Vps),
+            VpsCommand::Unlock { name } => self
+                .inner
+                .vps_set_is_locked(name, false)
+                .and_then(|_| self.inner.vps(name))
+                .map(CommandOutput::Vps),
+            VpsCommand::List => self.inner.vps_list().map(CommandOutput::VpsList),
         }
     }
 
-    pub fn execute(&mut self, command: &SubCommand, s: impl Serializer) -> Result<()> {
-        use transip::api::general::GeneralApi;
+    /// Executes command and returns its typed result
+    pub fn execute_output(&mut self, command: &SubCommand) -> Result<CommandOutput> {
+        if self.dry_run
+            && let Some(plan) = self.plan(command)
+        {
+            return plan.map(CommandOutput::DryRun);
+        }
         match command {
-            SubCommand::AvailibilityZones => self.inner.availability_zones().report(s),
-            SubCommand::Comment { text: _ } => Ok(()),
-            SubCommand::Dns(command) => self.execute_dns(command, s),
-            SubCommand::Domain(command) => self.execute_domain(command, s),
-            SubCommand::EmailBox(command) => self.execute_email_box(command, s),
-            SubCommand::EmailForward(command) => self.execute_email_forward(command, s),
-            SubCommand::Invoice(command) => self.execute_invoice(command, s),
+            SubCommand::Audit(_) => Err(Error::Rejected(
+                "audit is only available in transipctl".to_owned(),
+            )),
+            SubCommand::AvailibilityZones => self
+                .inner
+                .availability_zones()
+                .map(CommandOutput::AvailabilityZones),
+            SubCommand::Comment { text: _ } => Ok(CommandOutput::Unit),
+            SubCommand::Confirm { confirm } => {
+                self.confirm = *confirm == Switch::On;
+                Ok(CommandOutput::Unit)
+            }
+            SubCommand::Dns(command) => self.execute_dns(command),
+            SubCommand::Domain(command) => self.execute_domain(command),
+            SubCommand::Dryrun { dry_run } => {
+                self.dry_run = *dry_run == Switch::On;
+                Ok(CommandOutput::Unit)
+            }
+            SubCommand::EmailBox(command) => self.execute_email_box(command),
+            SubCommand::EmailForward(command) => self.execute_email_forward(command),
+            SubCommand::Invoice(command) => self.execute_invoice(command),
             SubCommand::Onerror { on_error } => {
                 self.onerror = on_error.clone();
-                Ok(())
+                Ok(CommandOutput::Unit)
+            }
+            SubCommand::Parallel { .. } => Err(Error::Rejected(
+                "parallel must be followed by commands and end".to_owned(),
+            )),
+            SubCommand::End => Err(Error::Rejected("end without parallel".to_owned())),
+            SubCommand::Ping => self.inner.api_test().map(CommandOutput::Ping),
+            SubCommand::Ratelimit => {
+                self.rate_limit()
+                    .map(CommandOutput::RateLimit)
+                    .ok_or_else(|| {
+                        Error::Rejected(
+                            "rate limit unknown, the api reported none since its reset".to_owned(),
+                        )
+                    })
+            }
+            SubCommand::Retry { retries, max_delay } => {
+                let mut policy = self.retry_policy();
+                policy.retries = *retries;
+                if let Some(max_delay) = max_delay {
+                    policy.max_delay = Duration::from_secs(*max_delay);
+                }
+                self.set_retry_policy(policy);
+                Ok(CommandOutput::Unit)
             }
-            SubCommand::Ping => self.inner.api_test().report(s),
-            SubCommand::Product(command) => self.execute_product(command, s),
+            SubCommand::Product(command) => self.execute_product(command),
             SubCommand::Sleep { number_of_seconds } => {
                 std::thread::sleep(Duration::from_secs(*number_of_seconds));
-                Ok(())
+                Ok(CommandOutput::Unit)
             }
-            SubCommand::Vps(command) => self.execute_vps(command, s),
+            SubCommand::Vps(command) => self.execute_vps(command),
             // _ => Ok(()),
         }
     }
+
+    /// Executes command and serializes its result, nothing is written for commands without a result
+    pub fn execute(&mut self, command: &SubCommand, s: impl Serializer) -> Result<()> {
+        self.execute_output(command).report(s)
+    }
 }
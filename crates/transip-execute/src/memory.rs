@@ -0,0 +1,599 @@
+use std::{collections::BTreeMap, path::Path};
+
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use transip::api::{
+    account::Invoice,
+    dns::DnsEntry,
+    domain::{Domain, NameServer},
+    email::{MailForward, MailForwardInsert, Mailbox, MailboxInsert},
+    general::{AvailabilityZone, ProductElement, Products},
+    vps::Vps,
+};
+
+use crate::{Backend, DnsSecEntry, Error, RateLimitHeaders, Result};
+
+/// State of a [`Memory`] backend, serialized the same way the api does
+///
+/// Dns entries, dnssec entries, mailboxes and mail forwards are keyed by domain name.
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Fixture {
+    pub availability_zones: Vec<AvailabilityZone>,
+    pub dns: BTreeMap<String, Vec<DnsEntry>>,
+    pub dnssec: BTreeMap<String, Vec<DnsSecEntry>>,
+    pub domains: Vec<Domain>,
+    pub invoices: Vec<Invoice>,
+    pub mail_forwards: BTreeMap<String, Vec<MailForward>>,
+    pub mailboxes: BTreeMap<String, Vec<Mailbox>>,
+    pub product_elements: BTreeMap<String, Vec<ProductElement>>,
+    pub products: Option<Products>,
+    pub vps: Vec<Vps>,
+}
+
+/// Backend that keeps all state in memory, for deterministic tests of scripts
+#[derive(Default)]
+pub struct Memory {
+    fixture: Fixture,
+}
+
+fn not_found(kind: &str, name: &str) -> Error {
+    Error::Rejected(format!("{kind} {name} not found"))
+}
+
+fn json_error(error: serde_json::Error) -> Error {
+    Error::IO(error.into())
+}
+
+/// The api types do not implement Clone, so copy them through their json representation
+fn copy<T: Serialize + DeserializeOwned>(item: &T) -> Result<T> {
+    serde_json::to_value(item)
+        .and_then(serde_json::from_value)
+        .map_err(json_error)
+}
+
+fn copy_all<T: Serialize + DeserializeOwned>(items: &[T]) -> Result<Vec<T>> {
+    items.iter().map(copy).collect()
+}
+
+impl From<Fixture> for Memory {
+    fn from(fixture: Fixture) -> Self {
+        Self { fixture }
+    }
+}
+
+impl Memory {
+    /// Reads the initial state from a json file
+    pub fn from_fixture<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let bytes = std::fs::read(path)?;
+        serde_json::from_slice::<Fixture>(&bytes)
+            .map(Self::from)
+            .map_err(json_error)
+    }
+
+    pub fn fixture(&self) -> &Fixture {
+        &self.fixture
+    }
+
+    fn zone(&mut self, domain_name: &str) -> Result<&mut Vec<DnsEntry>> {
+        self.fixture
+            .dns
+            .get_mut(domain_name)
+            .ok_or_else(|| not_found("domain", domain_name))
+    }
+
+    fn mailboxes(&mut self, domain_name: &str) -> Result<&mut Vec<Mailbox>> {
+        self.fixture
+            .mailboxes
+            .get_mut(domain_name)
+            .ok_or_else(|| not_found("domain", domain_name))
+    }
+
+    fn mail_forwards(&mut self, domain_name: &str) -> Result<&mut Vec<MailForward>> {
+        self.fixture
+            .mail_forwards
+            .get_mut(domain_name)
+            .ok_or_else(|| not_found("domain", domain_name))
+    }
+
+    fn domain_mut(&mut self, name: &str) -> Result<&mut Domain> {
+        self.fixture
+            .domains
+            .iter_mut()
+            .find(|domain| domain.name == name)
+            .ok_or_else(|| not_found("domain", name))
+    }
+
+    fn vps_mut(&mut self, name: &str) -> Result<&mut Vps> {
+        self.fixture
+            .vps
+            .iter_mut()
+            .find(|vps| vps.name == name)
+            .ok_or_else(|| not_found("vps", name))
+    }
+}
+
+impl Backend for Memory {
+    fn endpoint(&self) -> String {
+        "memory:/".to_owned()
+    }
+
+    fn rate_limit(&self) -> Option<RateLimitHeaders> {
+        None
+    }
+
+    fn api_test(&mut self) -> Result<String> {
+        Ok("pong".to_owned())
+    }
+
+    fn availability_zones(&mut self) -> Result<Vec<AvailabilityZone>> {
+        copy_all(&self.fixture.availability_zones)
+    }
+
+    fn dns_entry_delete(&mut self, domain_name: &str, entry: DnsEntry) -> Result<()> {
+        let zone = self.zone(domain_name)?;
+        let index = zone
+            .iter()
+            .position(|existing| *existing == entry)
+            .ok_or_else(|| not_found("dns entry", &entry.to_string()))?;
+        zone.remove(index);
+        Ok(())
+    }
+
+    fn dns_entry_insert(&mut self, domain_name: &str, entry: DnsEntry) -> Result<()> {
+        self.zone(domain_name).map(|zone| zone.push(entry))
+    }
+
+    fn dns_entry_list(&mut self, domain_name: &str) -> Result<Vec<DnsEntry>> {
+        self.zone(domain_name).map(|zone| zone.clone())
+    }
+
+    fn domain_dnssec_list(&mut self, domain_name: &str) -> Result<Vec<DnsSecEntry>> {
+        self.domain_mut(domain_name)?;
+        Ok(self
+            .fixture
+            .dnssec
+            .get(domain_name)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    fn domain_dnssec_set(&mut self, domain_name: &str, entries: Vec<DnsSecEntry>) -> Result<()> {
+        self.domain_mut(domain_name)?.has_dns_sec = !entries.is_empty();
+        self.fixture.dnssec.insert(domain_name.to_owned(), entries);
+        Ok(())
+    }
+
+    fn domain_item(&mut self, name: &str) -> Result<Domain> {
+        self.domain_mut(name).and_then(|domain| copy(domain))
+    }
+
+    fn domain_list(&mut self) -> Result<Vec<Domain>> {
+        copy_all(&self.fixture.domains)
+    }
+
+    fn domain_nameservers(&mut self, domain_name: &str) -> Result<Vec<NameServer>> {
+        self.domain_mut(domain_name)
+            .and_then(|domain| copy_all(&domain.nameservers))
+    }
+
+    fn domain_nameservers_set(
+        &mut self,
+        domain_name: &str,
+        nameservers: Vec<NameServer>,
+    ) -> Result<()> {
+        self.domain_mut(domain_name)
+            .map(|domain| domain.nameservers = nameservers)
+    }
+
+    fn invoice(&mut self, invoice_number: &str) -> Result<Invoice> {
+        self.fixture
+            .invoices
+            .iter()
+            .find(|invoice| invoice.invoice_number == invoice_number)
+            .ok_or_else(|| not_found("invoice", invoice_number))
+            .and_then(copy)
+    }
+
+    fn invoice_list(&mut self) -> Result<Vec<Invoice>> {
+        copy_all(&self.fixture.invoices)
+    }
+
+    fn invoice_pdf(&mut self, invoice_number: &str) -> Result<String> {
+        self.invoice(invoice_number).map(|_| String::new())
+    }
+
+    fn mailbox_delete(&mut self, domain_name: &str, id: &str) -> Result<()> {
+        let mailboxes = self.mailboxes(domain_name)?;
+        let index = mailboxes
+            .iter()
+            .position(|mailbox| mailbox.identifier == id)
+            .ok_or_else(|| not_found("mailbox", id))?;
+        mailboxes.remove(index);
+        Ok(())
+    }
+
+    fn mailbox_insert(&mut self, domain_name: &str, mailbox: MailboxInsert) -> Result<()> {
+        let mailboxes = self.mailboxes(domain_name)?;
+        mailboxes.push(Mailbox {
+            identifier: format!("{}@{domain_name}", mailbox.local_part),
+            domain: domain_name.to_owned(),
+            available_disk_space: mailbox.max_disk_usage,
+            local_part: mailbox.local_part,
+            ..Default::default()
+        });
+        Ok(())
+    }
+
+    fn mailbox_item(&mut self, domain_name: &str, id: &str) -> Result<Mailbox> {
+        self.mailboxes(domain_name)?
+            .iter()
+            .find(|mailbox| mailbox.identifier == id)
+            .ok_or_else(|| not_found("mailbox", id))
+            .and_then(copy)
+    }
+
+    fn mailbox_list(&mut self, domain_name: &str) -> Result<Vec<Mailbox>> {
+        self.mailboxes(domain_name)
+            .and_then(|mailboxes| copy_all(mailboxes))
+    }
+
+    fn mailforward_delete(&mut self, domain_name: &str, id: &str) -> Result<()> {
+        let forwards = self.mail_forwards(domain_name)?;
+        let index = forwards
+            .iter()
+            .position(|forward| forward.id.to_string() == id)
+            .ok_or_else(|| not_found("mail forward", id))?;
+        forwards.remove(index);
+        Ok(())
+    }
+
+    fn mailforward_insert(
+        &mut self,
+        domain_name: &str,
+        mail_forward: MailForwardInsert,
+    ) -> Result<()> {
+        let forwards = self.mail_forwards(domain_name)?;
+        let id = forwards.iter().map(|forward| forward.id).max().unwrap_or(0) + 1;
+        forwards.push(MailForward {
+            id,
+            local_part: mail_forward.local_part,
+            domain: domain_name.to_owned(),
+            status: "created".to_owned(),
+            forward_to: mail_forward.forward_to,
+        });
+        Ok(())
+    }
+
+    fn mailforward_item(&mut self, domain_name: &str, id: &str) -> Result<MailForward> {
+        self.mail_forwards(domain_name)?
+            .iter()
+            .find(|forward| forward.id.to_string() == id)
+            .ok_or_else(|| not_found("mail forward", id))
+            .and_then(copy)
+    }
+
+    fn mailforward_list(&mut self, domain_name: &str) -> Result<Vec<MailForward>> {
+        self.mail_forwards(domain_name)
+            .and_then(|forwards| copy_all(forwards))
+    }
+
+    fn product_elements(&mut self, name: &str) -> Result<Vec<ProductElement>> {
+        self.fixture
+            .product_elements
+            .get(name)
+            .ok_or_else(|| not_found("product", name))
+            .and_then(|elements| copy_all(elements))
+    }
+
+    fn products(&mut self) -> Result<Products> {
+        self.fixture
+            .products
+            .as_ref()
+            .ok_or_else(|| not_found("products", "list"))
+            .and_then(copy)
+    }
+
+    fn vps(&mut self, name: &str) -> Result<Vps> {
+        self.vps_mut(name).and_then(|vps| copy(vps))
+    }
+
+    fn vps_list(&mut self) -> Result<Vec<Vps>> {
+        copy_all(&self.fixture.vps)
+    }
+
+    fn vps_reset(&mut self, name: &str) -> Result<()> {
+        self.vps_mut(name)
+            .map(|vps| "running".clone_into(&mut vps.status))
+    }
+
+    fn vps_set_is_locked(&mut self, name: &str, locked: bool) -> Result<()> {
+        self.vps_mut(name)
+            .map(|vps| vps.is_customer_locked = locked)
+    }
+
+    fn vps_start(&mut self, name: &str) -> Result<()> {
+        self.vps_mut(name)
+            .map(|vps| "running".clone_into(&mut vps.status))
+    }
+
+    fn vps_stop(&mut self, name: &str) -> Result<()> {
+        self.vps_mut(name)
+            .map(|vps| "stopped".clone_into(&mut vps.status))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::{Value, json};
+    use transip_command::TransipCommand;
+    use transip_test_support::{TempDir, domain, fixture};
+
+    use super::{Fixture, Memory};
+    use crate::{Client, CommandOutput};
+
+    fn client_with(json: Value) -> Client {
+        Client::new(Memory::from(fixture::<Fixture>(json)))
+    }
+
+    fn client() -> Client {
+        client_with(json!({
+            "dns": {
+                "example.nl": [
+                    {"name": "@", "expire": 300, "type": "A", "content": "1.2.3.4"},
+                    {"name": "_acme-challenge", "expire": 60, "type": "TXT", "content": "old"},
+                ]
+            },
+            "mailForwards": {"example.nl": []},
+        }))
+    }
+
+    fn execute(client: &mut Client, line: &str) -> CommandOutput {
+        let command = line.parse::<TransipCommand>().unwrap();
+        client.execute_output(&command.command).unwrap()
+    }
+
+    fn dns_entries(client: &mut Client) -> Vec<String> {
+        match execute(client, "dns list example.nl") {
+            CommandOutput::DnsEntries(entries) => {
+                entries.into_iter().map(|entry| entry.content).collect()
+            }
+            _ => panic!("dns list returned no entries"),
+        }
+    }
+
+    #[test]
+    fn acme_validation() {
+        let mut client = client();
+        execute(&mut client, "dns acme-validation-set example.nl new");
+        assert_eq!(dns_entries(&mut client), vec!["1.2.3.4", "new"]);
+
+        execute(&mut client, "dns acme-validation-delete example.nl");
+        assert_eq!(dns_entries(&mut client), vec!["1.2.3.4"]);
+    }
+
+    #[test]
+    fn dns_insert_and_delete() {
+        let mut client = client();
+        match execute(&mut client, "dns insert example.nl www 300 A 1.2.3.4") {
+            CommandOutput::DnsChange(change) => {
+                assert!(change.deleted.is_empty());
+                assert_eq!(change.inserted[0].to_string(), "www 300 A 1.2.3.4");
+            }
+            _ => panic!("inserted entry not reported"),
+        }
+        match execute(&mut client, "dns delete example.nl @ 300 A 1.2.3.4") {
+            CommandOutput::DnsChange(change) => {
+                assert_eq!(change.deleted[0].to_string(), "@ 300 A 1.2.3.4");
+                assert!(change.inserted.is_empty());
+            }
+            _ => panic!("deleted entry not reported"),
+        }
+        assert_eq!(dns_entries(&mut client), vec!["old", "1.2.3.4"]);
+    }
+
+    #[test]
+    fn acme_validation_append() {
+        let mut client = client();
+        execute(
+            &mut client,
+            "dns acme-validation-set example.nl apex --append",
+        );
+        execute(
+            &mut client,
+            "dns acme-validation-set example.nl wildcard --append",
+        );
+        execute(
+            &mut client,
+            "dns acme-validation-set example.nl apex --append",
+        );
+        assert_eq!(
+            dns_entries(&mut client),
+            vec!["1.2.3.4", "old", "wildcard", "apex"]
+        );
+
+        execute(&mut client, "dns acme-validation-delete example.nl apex");
+        assert_eq!(dns_entries(&mut client), vec!["1.2.3.4", "old", "wildcard"]);
+    }
+
+    #[test]
+    fn acme_validation_subdomain() {
+        let mut client = client_with(json!({
+            "dns": {"example.nl": []},
+            "domains": [domain("example.nl")],
+        }));
+        match execute(
+            &mut client,
+            "dns acme-validation-set sub.app.example.nl new",
+        ) {
+            CommandOutput::DnsChange(change) => {
+                assert_eq!(change.inserted[0].name, "_acme-challenge.sub.app")
+            }
+            _ => panic!("acme challenge not inserted"),
+        }
+        assert_eq!(dns_entries(&mut client), vec!["new"]);
+
+        execute(&mut client, "dns acme-validation-delete example.nl");
+        assert_eq!(dns_entries(&mut client), vec!["new"]);
+        execute(&mut client, "dns acme-validation-delete sub.app.example.nl");
+        assert!(dns_entries(&mut client).is_empty());
+    }
+
+    #[test]
+    fn acme_validation_cname() {
+        let mut client = client_with(json!({
+            "dns": {
+                "example.nl": [
+                    {"name": "_acme-challenge.www", "expire": 300, "type": "CNAME", "content": "_acme-challenge.www.acme.example.net."},
+                    {"name": "_acme-challenge.mail", "expire": 300, "type": "CNAME", "content": "_acme-challenge.mail.example.com."},
+                    {"name": "_acme-challenge.web", "expire": 300, "type": "CNAME", "content": "acme.example.net."},
+                ],
+                "acme.example.net": [
+                    {"name": "@", "expire": 300, "type": "TXT", "content": "v=spf1 -all"},
+                    {"name": "_acme-challenge.www", "expire": 60, "type": "TXT", "content": "other"},
+                ],
+            },
+            "domains": [domain("example.nl"), domain("acme.example.net")],
+        }));
+        let acme_entries = |client: &mut Client| match execute(client, "dns list acme.example.net")
+        {
+            CommandOutput::DnsEntries(entries) => entries
+                .into_iter()
+                .map(|entry| format!("{} {}", entry.name, entry.content))
+                .collect::<Vec<_>>(),
+            _ => panic!("dns list returned no entries"),
+        };
+        let refused = |client: &mut Client, line: &str| {
+            let command = line.parse::<TransipCommand>().unwrap();
+            match client.execute_output(&command.command) {
+                Err(error) => error.to_string(),
+                Ok(_) => panic!("{line} executed"),
+            }
+        };
+        execute(
+            &mut client,
+            "dns acme-validation-set www.example.nl new --follow-cname",
+        );
+        assert_eq!(
+            acme_entries(&mut client),
+            vec![
+                "@ v=spf1 -all",
+                "_acme-challenge.www other",
+                "_acme-challenge.www new"
+            ]
+        );
+        assert_eq!(dns_entries(&mut client).len(), 3);
+
+        let error = refused(
+            &mut client,
+            "dns acme-validation-set mail.example.nl new --follow-cname",
+        );
+        assert!(error.contains("mail.example.com"));
+        let error = refused(
+            &mut client,
+            "dns acme-validation-set web.example.nl new --follow-cname",
+        );
+        assert!(error.contains("not an _acme-challenge name"));
+        let error = refused(
+            &mut client,
+            "dns acme-validation-delete www.example.nl --follow-cname",
+        );
+        assert!(error.contains("give the challenge"));
+
+        execute(
+            &mut client,
+            "dns acme-validation-delete www.example.nl new --follow-cname",
+        );
+        assert_eq!(
+            acme_entries(&mut client),
+            vec!["@ v=spf1 -all", "_acme-challenge.www other"]
+        );
+    }
+
+    #[test]
+    fn nameservers() {
+        let mut client = client_with(json!({
+            "domains": [domain("example.nl")],
+        }));
+        match execute(
+            &mut client,
+            "domain nameservers-set example.nl ns1.example.nl=192.0.2.1,2001:db8::1 ns.example.net",
+        ) {
+            CommandOutput::NameserversChanged(changed) => {
+                assert!(changed.before.is_empty());
+                assert_eq!(changed.after.len(), 2);
+                assert_eq!(changed.after[0].ipv6.as_deref(), Some("2001:db8::1"));
+                assert_eq!(changed.after[1].hostname, "ns.example.net");
+            }
+            _ => panic!("nameservers not reported"),
+        }
+
+        for line in [
+            "domain nameservers-set example.nl ns1.example.nl",
+            "domain nameservers-set example.nl ns.example.net=192.0.2.1",
+            "domain nameservers-set example.nl ns.example.net ns.example.net",
+        ] {
+            let command = line.parse::<TransipCommand>().unwrap();
+            assert!(client.execute_output(&command.command).is_err(), "{line}");
+        }
+        match execute(&mut client, "domain nameservers example.nl") {
+            CommandOutput::Nameservers(nameservers) => assert_eq!(nameservers.len(), 2),
+            _ => panic!("nameservers not listed"),
+        }
+    }
+
+    #[test]
+    fn dnssec() {
+        let dir = TempDir::new("transip-memory-dnssec");
+        let key = dir.join("Kexample.nl.+013+00000.key");
+        std::fs::write(
+            &key,
+            "example.nl. IN DNSKEY 257 3 13 mdsswUyr3DPW132mOi8V9xESWE8jTo0dxCjjnopKl+GqJxpVXckHAeF+KkxLbxILfDLUT0rAK9iUzy1L53eKGQ==\n",
+        )
+        .unwrap();
+        let mut client = client_with(json!({
+            "domains": [domain("example.nl")],
+        }));
+        let line = format!("domain dnssec set example.nl {}", key.display());
+        match execute(&mut client, &line) {
+            CommandOutput::DnsSecChanged(changed) => {
+                assert!(changed.before.is_empty());
+                assert_eq!(changed.after.len(), 1);
+                assert_eq!(
+                    (changed.after[0].flags, changed.after[0].algorithm),
+                    (257, 13)
+                );
+            }
+            _ => panic!("dnssec entries not reported"),
+        }
+        match execute(&mut client, "domain item example.nl") {
+            CommandOutput::Domain(domain) => assert!(domain.has_dns_sec),
+            _ => panic!("domain not reported"),
+        }
+        match execute(&mut client, "domain dnssec delete example.nl") {
+            CommandOutput::DnsSecDeleted(deleted) => assert_eq!(deleted.deleted.len(), 1),
+            _ => panic!("deleted dnssec entries not reported"),
+        }
+        match execute(&mut client, "domain dnssec list example.nl") {
+            CommandOutput::DnsSecEntries(entries) => assert!(entries.is_empty()),
+            _ => panic!("dnssec entries not listed"),
+        }
+    }
+
+    #[test]
+    fn mail_forward_insert() {
+        let mut client = client();
+        match execute(&mut client, "email-forward insert example.nl info a@b.nl") {
+            CommandOutput::MailForward(forward) => {
+                assert_eq!(forward.id, 1);
+                assert_eq!(forward.forward_to, "a@b.nl");
+            }
+            _ => panic!("inserted forward not reported"),
+        }
+    }
+
+    #[test]
+    fn unknown_domain() {
+        let command = "dns list unknown.nl".parse::<TransipCommand>().unwrap();
+        assert!(client().execute_output(&command.command).is_err());
+    }
+}
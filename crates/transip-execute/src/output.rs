@@ -0,0 +1,172 @@
+use serde::{Serialize, Serializer};
+
+#[cfg(feature = "propagation")]
+use crate::ServerStatus;
+use crate::{DnsSecEntry, PlannedRequest, RateLimit};
+use transip::api::{
+    account::Invoice,
+    dns::DnsEntry,
+    domain::{Domain, NameServer},
+    email::{MailForward, Mailbox},
+    general::{AvailabilityZone, ProductElement, Products},
+    vps::Vps,
+};
+
+/// Dns entries removed and added by a dns command
+#[derive(Default, Serialize)]
+pub struct DnsChange {
+    pub deleted: Vec<DnsEntry>,
+    pub inserted: Vec<DnsEntry>,
+}
+
+/// Entries of after that are not in before, an entry of before cancels out one equal entry of after
+fn missing(before: &[DnsEntry], after: &[DnsEntry]) -> Vec<DnsEntry> {
+    let mut before = before.to_vec();
+    after
+        .iter()
+        .filter(
+            |entry| match before.iter().position(|other| other == *entry) {
+                Some(index) => {
+                    before.remove(index);
+                    false
+                }
+                None => true,
+            },
+        )
+        .cloned()
+        .collect()
+}
+
+impl DnsChange {
+    /// Change between two listings of the same zone
+    pub(crate) fn between(before: &[DnsEntry], after: &[DnsEntry]) -> Self {
+        Self {
+            deleted: missing(after, before),
+            inserted: missing(before, after),
+        }
+    }
+}
+
+/// Resource as it existed just before it was deleted
+#[derive(Serialize)]
+pub struct Deleted<T> {
+    pub deleted: T,
+}
+
+/// State of a resource before and after a command set it, reported as the state after
+#[derive(Serialize)]
+#[serde(transparent)]
+pub struct Changed<T> {
+    #[serde(skip)]
+    pub before: T,
+    pub after: T,
+}
+
+/// Typed result of executing a command
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum CommandOutput {
+    AvailabilityZones(Vec<AvailabilityZone>),
+    DnsChange(DnsChange),
+    DnsEntries(Vec<DnsEntry>),
+    DnsSecChanged(Changed<Vec<DnsSecEntry>>),
+    DnsSecDeleted(Deleted<Vec<DnsSecEntry>>),
+    DnsSecEntries(Vec<DnsSecEntry>),
+    Domain(Domain),
+    Domains(Vec<Domain>),
+    DryRun(Vec<PlannedRequest>),
+    Invoice(Invoice),
+    InvoicePdf(String),
+    Invoices(Vec<Invoice>),
+    MailForward(MailForward),
+    MailForwardDeleted(Deleted<MailForward>),
+    MailForwards(Vec<MailForward>),
+    Mailbox(Mailbox),
+    MailboxDeleted(Deleted<Mailbox>),
+    Mailboxes(Vec<Mailbox>),
+    Nameservers(Vec<NameServer>),
+    NameserversChanged(Changed<Vec<NameServer>>),
+    Ping(String),
+    ProductElements(Vec<ProductElement>),
+    Products(Products),
+    #[cfg(feature = "propagation")]
+    Propagation(Vec<ServerStatus>),
+    RateLimit(RateLimit),
+    Vps(Vps),
+    VpsList(Vec<Vps>),
+    /// Command has no result to report
+    Unit,
+}
+
+impl CommandOutput {
+    pub fn is_unit(&self) -> bool {
+        matches!(self, CommandOutput::Unit)
+    }
+
+    /// Serializes the result, nothing is written for [`CommandOutput::Unit`]
+    pub fn report<S: Serializer>(&self, s: S) -> Result<(), S::Error> {
+        if self.is_unit() {
+            Ok(())
+        } else {
+            self.serialize(s).map(|_| ())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use transip::api::{dns::DnsEntry, domain::NameServer};
+
+    use super::{Changed, CommandOutput, DnsChange};
+
+    fn json(output: CommandOutput) -> String {
+        let mut buffer: Vec<u8> = Vec::new();
+        output
+            .report(&mut serde_json::Serializer::new(&mut buffer))
+            .unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+
+    #[test]
+    fn unit_reports_nothing() {
+        assert_eq!(json(CommandOutput::Unit), "");
+    }
+
+    #[test]
+    fn dns_entries() {
+        let output = CommandOutput::DnsEntries(vec![DnsEntry::new_acme_challenge(60, "abc")]);
+        assert_eq!(
+            json(output),
+            r#"[{"name":"_acme-challenge","expire":60,"type":"TXT","content":"abc"}]"#
+        );
+    }
+
+    #[test]
+    fn dns_change() {
+        let output = CommandOutput::DnsChange(DnsChange {
+            inserted: vec![DnsEntry::new_acme_challenge(60, "abc")],
+            ..Default::default()
+        });
+        assert_eq!(
+            json(output),
+            r#"{"deleted":[],"inserted":[{"name":"_acme-challenge","expire":60,"type":"TXT","content":"abc"}]}"#
+        );
+    }
+
+    #[test]
+    fn changed_reports_after() {
+        let nameserver = |hostname: &str| NameServer {
+            hostname: hostname.to_owned(),
+            ipv4: None,
+            ipv6: None,
+        };
+        let output = CommandOutput::NameserversChanged(Changed {
+            before: vec![nameserver("ns0.transip.net")],
+            after: vec![nameserver("ns.example.net")],
+        });
+        assert_eq!(
+            json(output),
+            r#"[{"hostname":"ns.example.net","ipv4":null,"ipv6":null}]"#
+        );
+    }
+}
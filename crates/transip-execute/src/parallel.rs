@@ -0,0 +1,225 @@
+use std::{
+    fs::OpenOptions,
+    path::Path,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use transip_command::SubCommand;
+
+use crate::{Backend, Client, CommandOutput, Error, Result, lock};
+
+/// Creates the backend of a client that executes commands on another thread
+pub(crate) type Workers = Arc<dyn Fn() -> Result<Box<dyn Backend>> + Send + Sync>;
+
+static TOKEN_FILE: Mutex<()> = Mutex::new(());
+
+/// Runs f while holding a lock on the token file, for other threads and other processes
+///
+/// The transip client reads the token file when it is created and writes it when it is dropped.
+pub(crate) fn with_token_lock<T>(token_path: &Path, f: impl FnOnce() -> T) -> Result<T> {
+    let _guard = lock(&TOKEN_FILE);
+    let mut path = token_path.as_os_str().to_owned();
+    path.push(".lock");
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)?;
+    file.lock()?;
+    Ok(f())
+}
+
+/// Errors can not be sent between threads, so only their message is kept
+fn message(error: Error) -> String {
+    match error {
+        Error::Rejected(message) => message,
+        error => error.to_string(),
+    }
+}
+
+/// Commands that change the state of the client can not run on a worker
+fn is_independent(command: &SubCommand) -> bool {
+    !matches!(
+        command,
+        SubCommand::Confirm { .. }
+            | SubCommand::Dryrun { .. }
+            | SubCommand::End
+            | SubCommand::Onerror { .. }
+            | SubCommand::Parallel { .. }
+            | SubCommand::Retry { .. }
+    )
+}
+
+impl Client {
+    /// Sets how backends are created for executing commands in parallel
+    pub fn set_workers(
+        &mut self,
+        create: impl Fn() -> Result<Box<dyn Backend>> + Send + Sync + 'static,
+    ) {
+        self.workers = Some(Arc::new(create));
+    }
+
+    /// Executes commands on at most jobs threads, results are returned in the order of commands
+    ///
+    /// Commands are executed one after another when the backend can not be created more than once,
+    /// for example when recording.
+    pub fn execute_parallel(
+        &mut self,
+        jobs: usize,
+        commands: &[&SubCommand],
+    ) -> Vec<Result<CommandOutput>> {
+        let independent = |command: &SubCommand| {
+            if is_independent(command) {
+                Ok(())
+            } else {
+                Err(Error::Rejected(format!(
+                    "{command:?} not allowed in parallel block"
+                )))
+            }
+        };
+        let workers = match self.workers.clone() {
+            Some(workers) if jobs > 1 && commands.len() > 1 => workers,
+            _ => {
+                return commands
+                    .iter()
+                    .map(|command| independent(command).and_then(|_| self.execute_output(command)))
+                    .collect();
+            }
+        };
+
+        let next = AtomicUsize::new(0);
+        let results = commands
+            .iter()
+            .map(|_| Mutex::new(None))
+            .collect::<Vec<_>>();
+        std::thread::scope(|scope| {
+            for _ in 0..jobs.min(commands.len()) {
+                let (next, results, workers) = (&next, &results, &workers);
+                let (retry, budget) = (self.retry.clone(), self.budget.clone());
+                let (dry_run, token_path) = (self.dry_run, self.token_path.clone());
+                scope.spawn(move || {
+                    let mut client = workers().map(|backend| {
+                        let mut client = Client::with_shared(backend, retry, budget);
+                        client.dry_run = dry_run;
+                        client.token_path = token_path;
+                        client
+                    });
+                    while let Some(index) = Some(next.fetch_add(1, Ordering::SeqCst))
+                        .filter(|index| *index < commands.len())
+                    {
+                        let command = commands[index];
+                        let result = match client.as_mut() {
+                            Ok(client) => independent(command)
+                                .and_then(|_| client.execute_output(command))
+                                .map_err(message),
+                            Err(error) => Err(format!("worker failed: {error}")),
+                        };
+                        *lock(&results[index]) = Some(result);
+                    }
+                });
+            }
+        });
+        results
+            .into_iter()
+            .map(|result| {
+                result
+                    .into_inner()
+                    .unwrap_or_else(|error| error.into_inner())
+                    .unwrap_or_else(|| Err("command not executed".to_owned()))
+                    .map_err(Error::Rejected)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+    use transip_command::TransipCommand;
+    use transip_test_support::fixture;
+
+    use crate::{Client, CommandOutput, Fixture, Memory};
+
+    fn memory() -> Memory {
+        Memory::from(fixture::<Fixture>(json!({
+            "dns": {
+                "a.nl": [{"name": "@", "expire": 300, "type": "A", "content": "1.1.1.1"}],
+                "b.nl": [{"name": "@", "expire": 300, "type": "A", "content": "2.2.2.2"}],
+                "c.nl": [{"name": "@", "expire": 300, "type": "A", "content": "3.3.3.3"}],
+            },
+        })))
+    }
+
+    fn commands(lines: &[&str]) -> Vec<TransipCommand> {
+        lines
+            .iter()
+            .map(|line| line.parse::<TransipCommand>().unwrap())
+            .collect()
+    }
+
+    fn content(output: &crate::Result<CommandOutput>) -> String {
+        match output {
+            Ok(CommandOutput::DnsEntries(entries)) => entries[0].content.clone(),
+            _ => panic!("no dns entries"),
+        }
+    }
+
+    fn parallel(client: &mut Client, lines: &[&str]) -> Vec<crate::Result<CommandOutput>> {
+        let commands = commands(lines);
+        let commands = commands
+            .iter()
+            .map(|command| &command.command)
+            .collect::<Vec<_>>();
+        client.execute_parallel(2, &commands)
+    }
+
+    #[test]
+    fn source_order() {
+        let mut client = Client::new(memory());
+        client.set_workers(|| Ok(Box::new(memory())));
+        let results = parallel(
+            &mut client,
+            &[
+                "dns list c.nl",
+                "dns list a.nl",
+                "dns list b.nl",
+                "dns list a.nl",
+            ],
+        );
+        assert_eq!(
+            results.iter().map(content).collect::<Vec<_>>(),
+            vec!["3.3.3.3", "1.1.1.1", "2.2.2.2", "1.1.1.1"]
+        );
+    }
+
+    #[test]
+    fn sequential_without_workers() {
+        let mut client = Client::new(memory());
+        let results = parallel(&mut client, &["dns list b.nl", "dns list a.nl"]);
+        assert_eq!(
+            results.iter().map(content).collect::<Vec<_>>(),
+            vec!["2.2.2.2", "1.1.1.1"]
+        );
+    }
+
+    #[test]
+    fn state_commands_rejected() {
+        let mut client = Client::new(memory());
+        client.set_workers(|| Ok(Box::new(memory())));
+        let results = parallel(&mut client, &["dryrun on", "dns list a.nl"]);
+        assert!(results[0].is_err());
+        assert!(results[1].is_ok());
+        assert!(!client.dry_run());
+    }
+
+    #[test]
+    fn worker_failure() {
+        let mut client = Client::new(memory());
+        client.set_workers(|| Err(crate::Error::Rejected("no backend".to_owned())));
+        let results = parallel(&mut client, &["dns list a.nl", "dns list b.nl"]);
+        assert!(results.iter().all(Result::is_err));
+    }
+}
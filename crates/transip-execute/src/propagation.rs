@@ -0,0 +1,355 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+use transip_command::Nameservers;
+
+use crate::{
+    Error, Result,
+    dns::{self, RData},
+};
+
+const PUBLIC: &[(&str, IpAddr)] = &[
+    ("google", IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8))),
+    ("cloudflare", IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1))),
+    ("quad9", IpAddr::V4(Ipv4Addr::new(9, 9, 9, 9))),
+];
+/// Resolvers of Google that find the authoritative nameservers, the ipv6 one for ipv6 only hosts
+const BOOTSTRAP: &[IpAddr] = &[
+    IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8)),
+    IpAddr::V6(Ipv6Addr::new(0x2001, 0x4860, 0x4860, 0, 0, 0, 0, 0x8888)),
+];
+const QUERY_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Nameserver that is polled for the challenge
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Server {
+    name: String,
+    address: SocketAddr,
+    /// Resolvers are asked to recurse, authoritative servers are not
+    recursion: bool,
+}
+
+impl Server {
+    fn new(name: impl Into<String>, address: SocketAddr, recursion: bool) -> Self {
+        Self {
+            name: name.into(),
+            address,
+            recursion,
+        }
+    }
+}
+
+/// Result of polling one nameserver, reported by acme-validation-check
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerStatus {
+    pub server: String,
+    pub address: String,
+    pub propagated: bool,
+    pub attempts: u32,
+    /// Challenges served at the last attempt
+    pub challenges: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ServerStatus {
+    fn new(server: &Server) -> Self {
+        Self {
+            server: server.name.clone(),
+            address: server.address.to_string(),
+            propagated: false,
+            attempts: 0,
+            challenges: vec![],
+            error: None,
+        }
+    }
+
+    fn stale(&self) -> String {
+        let served = match self.error.as_ref() {
+            Some(error) => error.clone(),
+            None if self.challenges.is_empty() => "no challenge".to_owned(),
+            None => format!("{:?}", self.challenges),
+        };
+        format!("{} ({}) serves {served}", self.server, self.address)
+    }
+}
+
+fn public() -> Vec<Server> {
+    PUBLIC
+        .iter()
+        .map(|(name, ip)| Server::new(*name, SocketAddr::new(*ip, 53), true))
+        .collect()
+}
+
+/// First of resolvers that answers a lookup of the nameservers of name, with its answer
+fn bootstrap(resolvers: &[SocketAddr], name: &str) -> Result<(SocketAddr, Vec<dns::Record>)> {
+    let mut last_error = None;
+    for resolver in resolvers {
+        match dns::lookup(*resolver, name, dns::NS, true, QUERY_TIMEOUT) {
+            Ok(records) => return Ok((*resolver, records)),
+            Err(error) => {
+                tracing::info!("Resolver {resolver} failed: {error}");
+                last_error = Some(error);
+            }
+        }
+    }
+    Err(last_error.unwrap_or_else(|| Error::Rejected("no resolver".to_owned())))
+}
+
+/// Nameservers of the zone containing name, looked up with resolver or a public resolver
+///
+/// A name without nameservers of its own is served by the nameservers of a parent domain.
+/// Their addresses are of the family of the resolver that answered, which the host can reach.
+fn authoritative(name: &str, resolver: Option<SocketAddr>) -> Result<Vec<Server>> {
+    let resolvers = match resolver {
+        Some(resolver) => vec![resolver],
+        None => BOOTSTRAP
+            .iter()
+            .map(|ip| SocketAddr::new(*ip, 53))
+            .collect(),
+    };
+    let (resolver, mut records) = bootstrap(&resolvers, name)?;
+    let not_found = || Error::Rejected(format!("no nameservers found for {name}"));
+    let mut zone = name;
+    let hosts = loop {
+        let hosts = records
+            .into_iter()
+            .filter_map(|record| match record.data {
+                RData::Ns(host) => Some(host),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        if !hosts.is_empty() {
+            break hosts;
+        }
+        zone = zone
+            .split_once('.')
+            .map(|(_, parent)| parent)
+            .filter(|parent| parent.contains('.'))
+            .ok_or_else(not_found)?;
+        records = dns::lookup(resolver, zone, dns::NS, true, QUERY_TIMEOUT)?;
+    };
+    let record_type = if resolver.is_ipv6() {
+        dns::AAAA
+    } else {
+        dns::A
+    };
+    let mut servers = vec![];
+    for host in hosts {
+        for record in dns::lookup(resolver, &host, record_type, true, QUERY_TIMEOUT)? {
+            let address = match record.data {
+                RData::A(address) => IpAddr::V4(address),
+                RData::Aaaa(address) => IpAddr::V6(address),
+                _ => continue,
+            };
+            servers.push(Server::new(
+                host.clone(),
+                SocketAddr::new(address, 53),
+                false,
+            ));
+        }
+    }
+    if servers.is_empty() {
+        Err(not_found())
+    } else {
+        Ok(servers)
+    }
+}
+
+pub(crate) fn servers(
+    name: &str,
+    nameservers: &Nameservers,
+    resolver: Option<SocketAddr>,
+) -> Result<Vec<Server>> {
+    match nameservers {
+        Nameservers::Authoritative => authoritative(name, resolver),
+        Nameservers::Public => Ok(public()),
+        Nameservers::Custom(addresses) => Ok(addresses
+            .iter()
+            .map(|address| Server::new(address.to_string(), *address, true))
+            .collect()),
+    }
+}
+
+fn poll(
+    status: &mut ServerStatus,
+    server: &Server,
+    name: &str,
+    challenge: &str,
+    timeout: Duration,
+) {
+    status.attempts += 1;
+    match dns::lookup(server.address, name, dns::TXT, server.recursion, timeout) {
+        Ok(records) => {
+            status.error = None;
+            status.challenges = records
+                .into_iter()
+                .filter_map(|record| match record.data {
+                    RData::Txt(text) => Some(text),
+                    _ => None,
+                })
+                .collect();
+            status.propagated = status.challenges.iter().any(|text| text == challenge);
+        }
+        Err(error) => status.error = Some(error.to_string()),
+    }
+    if status.propagated {
+        tracing::info!(
+            "{} serves the challenge after {} attempts",
+            server.name,
+            status.attempts
+        );
+    } else {
+        tracing::info!("Waiting for {}", status.stale());
+    }
+}
+
+/// Polls servers every interval until all of them serve challenge in the txt record name
+///
+/// Fails after timeout, naming the servers that still serve stale data.
+/// Queries and the last sleep are cut short so the timeout is not overrun.
+pub(crate) fn wait(
+    name: &str,
+    challenge: &str,
+    servers: &[Server],
+    interval: Duration,
+    timeout: Duration,
+) -> Result<Vec<ServerStatus>> {
+    let start = Instant::now();
+    let remaining = || timeout.saturating_sub(start.elapsed());
+    let mut statuses = servers.iter().map(ServerStatus::new).collect::<Vec<_>>();
+    loop {
+        for (status, server) in statuses.iter_mut().zip(servers) {
+            let remaining = remaining();
+            if !status.propagated && !remaining.is_zero() {
+                poll(
+                    status,
+                    server,
+                    name,
+                    challenge,
+                    QUERY_TIMEOUT.min(remaining),
+                );
+            }
+        }
+        if statuses.iter().all(|status| status.propagated) {
+            return Ok(statuses);
+        }
+        let remaining = remaining();
+        if remaining.is_zero() {
+            let stale = statuses
+                .iter()
+                .filter(|status| !status.propagated)
+                .map(ServerStatus::stale)
+                .collect::<Vec<_>>();
+            return Err(Error::Rejected(format!(
+                "acme challenge {name} not propagated after {} seconds: {}",
+                timeout.as_secs(),
+                stale.join(", ")
+            )));
+        }
+        std::thread::sleep(interval.min(remaining));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        net::{SocketAddr, UdpSocket},
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+        time::{Duration, Instant},
+    };
+
+    use crate::dns::{self, RData};
+
+    use super::{Server, wait};
+
+    /// Local dns server answering txt requests with old until it received switch requests
+    fn stand_in(old: &'static str, new: &'static str, switch: usize) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap();
+        let requests = Arc::new(AtomicUsize::new(0));
+        std::thread::spawn(move || {
+            let mut buffer = [0u8; 512];
+            while let Ok((length, peer)) = socket.recv_from(&mut buffer) {
+                let question = dns::question(&buffer[..length]).unwrap();
+                assert_eq!(question.name, "_acme-challenge.example.nl");
+                let count = requests.fetch_add(1, Ordering::SeqCst) + 1;
+                let text = if count > switch { new } else { old };
+                let answers = [RData::Txt(text.to_owned())];
+                socket
+                    .send_to(&dns::response(&question, Some(&answers)), peer)
+                    .unwrap();
+            }
+        });
+        address
+    }
+
+    fn server(address: SocketAddr) -> Server {
+        Server::new(address.to_string(), address, false)
+    }
+
+    #[test]
+    fn propagated() {
+        let servers = [
+            server(stand_in("old", "new", 0)),
+            server(stand_in("old", "new", 2)),
+        ];
+        let statuses = wait(
+            "_acme-challenge.example.nl",
+            "new",
+            &servers,
+            Duration::ZERO,
+            Duration::from_secs(10),
+        )
+        .unwrap();
+        assert_eq!(
+            statuses
+                .iter()
+                .map(|status| status.attempts)
+                .collect::<Vec<_>>(),
+            vec![1, 3]
+        );
+        assert!(statuses.iter().all(|status| status.propagated));
+    }
+
+    #[test]
+    fn stale_servers_reported() {
+        let fresh = stand_in("old", "new", 0);
+        let stale = stand_in("old", "new", usize::MAX);
+        let error = wait(
+            "_acme-challenge.example.nl",
+            "new",
+            &[server(fresh), server(stale)],
+            Duration::from_millis(10),
+            Duration::from_millis(50),
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(error.contains(&format!("{stale} ({stale}) serves [\"old\"]")));
+        assert!(!error.contains(&fresh.to_string()));
+    }
+
+    #[test]
+    fn timeout_not_overrun() {
+        let stale = stand_in("old", "new", usize::MAX);
+        let start = Instant::now();
+        assert!(
+            wait(
+                "_acme-challenge.example.nl",
+                "new",
+                &[server(stale)],
+                Duration::from_secs(10),
+                Duration::from_millis(100),
+            )
+            .is_err()
+        );
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+}
@@ -0,0 +1,220 @@
+use std::{
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use ureq::http::HeaderMap;
+
+/// Requests slow down when less than this part of the limit remains
+const LOW: u32 = 10;
+
+/// Remaining api calls in the current window, reported by the ratelimit command
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RateLimit {
+    pub limit: u32,
+    pub remaining: u32,
+    /// Seconds until the budget is reset
+    pub reset: u64,
+}
+
+/// Rate limit of the account, as the api reports it in the headers of a response
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimitHeaders {
+    pub limit: u32,
+    pub remaining: u32,
+    /// Unix timestamp at which the rate limit is reset
+    pub reset: u64,
+}
+
+impl RateLimitHeaders {
+    /// Rate limit in headers, None if one of the headers is missing or not a number
+    pub(crate) fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let header = |name: &str| headers.get(name)?.to_str().ok()?.parse::<u64>().ok();
+        Some(Self {
+            limit: header("X-Rate-Limit-Limit")?.try_into().ok()?,
+            remaining: header("X-Rate-Limit-Remaining")?.try_into().ok()?,
+            reset: header("X-Rate-Limit-Reset")?,
+        })
+    }
+}
+
+/// Rate limit the api reported in the headers of its last response,
+/// persisted so consecutive runs share the budget
+///
+/// Only the http client reports one, calls to the memory or replay backends leave it alone.
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Budget {
+    #[serde(skip)]
+    path: Option<PathBuf>,
+    #[serde(skip)]
+    changed: bool,
+    limit: u32,
+    remaining: u32,
+    /// Unix timestamp at which the budget is reset
+    reset: u64,
+}
+
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+impl Budget {
+    /// Budget saved in path, an unknown budget if path can not be read
+    pub(crate) fn load(path: PathBuf) -> Self {
+        let budget = std::fs::read(&path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<Budget>(&bytes).ok())
+            .unwrap_or_default();
+        Self {
+            path: Some(path),
+            ..budget
+        }
+    }
+
+    fn save(&self) {
+        if let Some(path) = self.path.as_ref() {
+            let result = serde_json::to_vec(self)
+                .map_err(std::io::Error::from)
+                .and_then(|bytes| std::fs::write(path, bytes));
+            if let Err(error) = result {
+                tracing::warn!("Cannot save rate limit to {}: {error}", path.display());
+            }
+        }
+    }
+
+    /// Remaining calls, None if the api reported none since the last reset
+    pub(crate) fn status(&self, now: u64) -> Option<RateLimit> {
+        (now < self.reset).then(|| RateLimit {
+            limit: self.limit,
+            remaining: self.remaining,
+            reset: self.reset - now,
+        })
+    }
+
+    /// Takes over the rate limit reported in the headers of a response
+    pub(crate) fn update(&mut self, reported: RateLimitHeaders) {
+        self.limit = reported.limit;
+        self.remaining = reported.remaining;
+        self.reset = reported.reset;
+        self.changed = true;
+    }
+
+    /// How long to wait before the next api call
+    ///
+    /// When the budget runs low the remaining calls are spread over the rest of the window.
+    pub(crate) fn delay(&self, now: u64) -> Duration {
+        match self.status(now) {
+            Some(status) if status.remaining == 0 => {
+                tracing::warn!("Rate limit exhausted, waiting {} seconds", status.reset);
+                Duration::from_secs(status.reset)
+            }
+            Some(status) if status.remaining <= status.limit / LOW => {
+                let delay = Duration::from_secs(status.reset) / status.remaining;
+                tracing::warn!(
+                    "Rate limit low, {} calls remaining, waiting {delay:?}",
+                    status.remaining
+                );
+                delay
+            }
+            _ => Duration::ZERO,
+        }
+    }
+}
+
+/// Saved once, when the last client sharing the budget is dropped
+impl Drop for Budget {
+    fn drop(&mut self) {
+        if self.changed {
+            self.save();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use transip_test_support::TempDir;
+    use ureq::http::HeaderMap;
+
+    use super::{Budget, RateLimit, RateLimitHeaders};
+
+    fn reported(remaining: u32, reset: u64) -> RateLimitHeaders {
+        RateLimitHeaders {
+            limit: 1000,
+            remaining,
+            reset,
+        }
+    }
+
+    #[test]
+    fn headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert("X-Rate-Limit-Limit", "1000".parse().unwrap());
+        headers.insert("X-Rate-Limit-Remaining", "998".parse().unwrap());
+        assert_eq!(RateLimitHeaders::from_headers(&headers), None);
+        headers.insert("X-Rate-Limit-Reset", "1700000060".parse().unwrap());
+        assert_eq!(
+            RateLimitHeaders::from_headers(&headers),
+            Some(RateLimitHeaders {
+                limit: 1000,
+                remaining: 998,
+                reset: 1700000060,
+            })
+        );
+    }
+
+    #[test]
+    fn reported_by_api() {
+        let mut budget = Budget::default();
+        assert_eq!(budget.status(100), None);
+        assert_eq!(budget.delay(100), Duration::ZERO);
+        budget.update(reported(999, 160));
+        assert_eq!(
+            budget.status(110),
+            Some(RateLimit {
+                limit: 1000,
+                remaining: 999,
+                reset: 50
+            })
+        );
+        assert_eq!(budget.status(160), None);
+    }
+
+    #[test]
+    fn slow_down() {
+        let mut budget = Budget::default();
+        budget.update(reported(101, 60));
+        assert_eq!(budget.delay(0), Duration::ZERO);
+        budget.update(reported(100, 60));
+        assert_eq!(
+            budget.delay(0),
+            Duration::from_secs(60) / 100,
+            "remaining calls spread over the window"
+        );
+        budget.update(reported(0, 60));
+        assert_eq!(budget.delay(20), Duration::from_secs(40));
+        assert_eq!(budget.delay(60), Duration::ZERO);
+    }
+
+    #[test]
+    fn persisted() {
+        let dir = TempDir::new("transip-execute-rate-limit");
+        let path = dir.join("token.txt.ratelimit");
+        let now = super::now();
+        drop(Budget::load(path.clone()));
+        assert!(!path.exists(), "saved without a reported rate limit");
+        Budget::load(path.clone()).update(reported(999, now + 60));
+        assert_eq!(
+            Budget::load(path)
+                .status(now)
+                .map(|status| status.remaining),
+            Some(999)
+        );
+    }
+}
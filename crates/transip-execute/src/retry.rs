@@ -0,0 +1,474 @@
+use std::{
+    hash::{BuildHasher, RandomState},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use transip::api::{
+    account::Invoice,
+    dns::DnsEntry,
+    domain::{Domain, NameServer},
+    email::{MailForward, MailForwardInsert, Mailbox, MailboxInsert},
+    general::{AvailabilityZone, ProductElement, Products},
+    vps::Vps,
+};
+
+use crate::{
+    Backend, DnsSecEntry, Error, RateLimitHeaders, Result, lock,
+    rate_limit::{self, Budget},
+};
+
+const TRANSIP_RETRIES: &str = "TRANSIP_RETRIES";
+const TRANSIP_RETRY_MAX_DELAY: &str = "TRANSIP_RETRY_MAX_DELAY";
+const FIRST_DELAY: Duration = Duration::from_millis(500);
+
+/// Number of retries and the maximum delay between them for transient api failures
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RetryPolicy {
+    pub retries: u32,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 3,
+            max_delay: Duration::from_secs(10),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Default policy, overruled by `TRANSIP_RETRIES` and `TRANSIP_RETRY_MAX_DELAY` in seconds
+    pub fn from_environment() -> Result<Self> {
+        let mut policy = Self::default();
+        if let Ok(retries) = std::env::var(TRANSIP_RETRIES) {
+            policy.retries = retries.parse()?;
+        }
+        if let Ok(max_delay) = std::env::var(TRANSIP_RETRY_MAX_DELAY) {
+            policy.max_delay = Duration::from_secs(max_delay.parse()?);
+        }
+        Ok(policy)
+    }
+
+    /// Exponential delay before retry number attempt, with half of it random
+    fn delay(&self, attempt: u32) -> Duration {
+        let delay = FIRST_DELAY
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_delay);
+        let half = delay / 2;
+        let jitter = RandomState::new().hash_one(attempt) % (half.as_millis() as u64 + 1);
+        half + Duration::from_millis(jitter)
+    }
+}
+
+/// Whether a request can be sent again when it is unknown if the first one was processed
+#[derive(Clone, Copy, PartialEq)]
+enum Safety {
+    Idempotent,
+    /// Only retried when the api certainly did not process the request
+    NotIdempotent,
+}
+
+fn is_transient(error: &Error, safety: Safety) -> bool {
+    match error {
+        Error::Ureq(ureq::Error::StatusCode(429 | 503))
+        | Error::Ureq(ureq::Error::ConnectionFailed) => true,
+        Error::Ureq(
+            ureq::Error::StatusCode(500 | 502 | 504) | ureq::Error::Io(_) | ureq::Error::Timeout(_),
+        )
+        | Error::IO(_) => safety == Safety::Idempotent,
+        _ => false,
+    }
+}
+
+/// Backend that retries api calls of the wrapped backend that fail with a transient error
+///
+/// Every attempt waits if the rate limit the api reported runs low, and updates it.
+pub(crate) struct Retrying {
+    inner: Box<dyn Backend>,
+    policy: Arc<Mutex<RetryPolicy>>,
+    budget: Arc<Mutex<Budget>>,
+}
+
+impl Retrying {
+    pub(crate) fn new(
+        inner: Box<dyn Backend>,
+        policy: Arc<Mutex<RetryPolicy>>,
+        budget: Arc<Mutex<Budget>>,
+    ) -> Self {
+        Self {
+            inner,
+            policy,
+            budget,
+        }
+    }
+
+    fn call<T>(
+        &mut self,
+        name: &str,
+        safety: Safety,
+        mut f: impl FnMut(&mut dyn Backend) -> Result<T>,
+    ) -> Result<T> {
+        let policy = *lock(&self.policy);
+        let mut attempt = 0;
+        loop {
+            let wait = lock(&self.budget).delay(rate_limit::now());
+            std::thread::sleep(wait);
+            let result = f(self.inner.as_mut());
+            if let Some(reported) = self.inner.rate_limit() {
+                lock(&self.budget).update(reported);
+            }
+            match result {
+                Err(error) if attempt < policy.retries && is_transient(&error, safety) => {
+                    let delay = policy.delay(attempt);
+                    attempt += 1;
+                    tracing::warn!(
+                        "{name} failed: {error}, retry {attempt} of {} in {delay:?}",
+                        policy.retries
+                    );
+                    std::thread::sleep(delay);
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+fn mailbox_insert(mailbox: &MailboxInsert) -> MailboxInsert {
+    MailboxInsert {
+        local_part: mailbox.local_part.clone(),
+        max_disk_usage: mailbox.max_disk_usage,
+        password: mailbox.password.clone(),
+    }
+}
+
+fn mail_forward_insert(mail_forward: &MailForwardInsert) -> MailForwardInsert {
+    MailForwardInsert {
+        local_part: mail_forward.local_part.clone(),
+        forward_to: mail_forward.forward_to.clone(),
+    }
+}
+
+fn name_servers(nameservers: &[NameServer]) -> Vec<NameServer> {
+    nameservers
+        .iter()
+        .map(|nameserver| NameServer {
+            hostname: nameserver.hostname.clone(),
+            ipv4: nameserver.ipv4.clone(),
+            ipv6: nameserver.ipv6.clone(),
+        })
+        .collect()
+}
+
+impl Backend for Retrying {
+    fn endpoint(&self) -> String {
+        self.inner.endpoint()
+    }
+
+    fn rate_limit(&self) -> Option<RateLimitHeaders> {
+        self.inner.rate_limit()
+    }
+
+    fn api_test(&mut self) -> Result<String> {
+        self.call("api test", Safety::Idempotent, |b| b.api_test())
+    }
+
+    fn availability_zones(&mut self) -> Result<Vec<AvailabilityZone>> {
+        self.call("availability zones", Safety::Idempotent, |b| {
+            b.availability_zones()
+        })
+    }
+
+    fn dns_entry_delete(&mut self, domain_name: &str, entry: DnsEntry) -> Result<()> {
+        self.call("dns entry delete", Safety::NotIdempotent, |b| {
+            b.dns_entry_delete(domain_name, entry.clone())
+        })
+    }
+
+    fn dns_entry_insert(&mut self, domain_name: &str, entry: DnsEntry) -> Result<()> {
+        self.call("dns entry insert", Safety::NotIdempotent, |b| {
+            b.dns_entry_insert(domain_name, entry.clone())
+        })
+    }
+
+    fn dns_entry_list(&mut self, domain_name: &str) -> Result<Vec<DnsEntry>> {
+        self.call("dns entry list", Safety::Idempotent, |b| {
+            b.dns_entry_list(domain_name)
+        })
+    }
+
+    fn domain_dnssec_list(&mut self, domain_name: &str) -> Result<Vec<DnsSecEntry>> {
+        self.call("domain dnssec list", Safety::Idempotent, |b| {
+            b.domain_dnssec_list(domain_name)
+        })
+    }
+
+    fn domain_dnssec_set(&mut self, domain_name: &str, entries: Vec<DnsSecEntry>) -> Result<()> {
+        self.call("domain dnssec set", Safety::Idempotent, |b| {
+            b.domain_dnssec_set(domain_name, entries.clone())
+        })
+    }
+
+    fn domain_item(&mut self, name: &str) -> Result<Domain> {
+        self.call("domain item", Safety::Idempotent, |b| b.domain_item(name))
+    }
+
+    fn domain_list(&mut self) -> Result<Vec<Domain>> {
+        self.call("domain list", Safety::Idempotent, |b| b.domain_list())
+    }
+
+    fn domain_nameservers(&mut self, domain_name: &str) -> Result<Vec<NameServer>> {
+        self.call("domain nameservers", Safety::Idempotent, |b| {
+            b.domain_nameservers(domain_name)
+        })
+    }
+
+    fn domain_nameservers_set(
+        &mut self,
+        domain_name: &str,
+        nameservers: Vec<NameServer>,
+    ) -> Result<()> {
+        self.call("domain nameservers set", Safety::Idempotent, |b| {
+            b.domain_nameservers_set(domain_name, name_servers(&nameservers))
+        })
+    }
+
+    fn invoice(&mut self, invoice_number: &str) -> Result<Invoice> {
+        self.call("invoice", Safety::Idempotent, |b| b.invoice(invoice_number))
+    }
+
+    fn invoice_list(&mut self) -> Result<Vec<Invoice>> {
+        self.call("invoice list", Safety::Idempotent, |b| b.invoice_list())
+    }
+
+    fn invoice_pdf(&mut self, invoice_number: &str) -> Result<String> {
+        self.call("invoice pdf", Safety::Idempotent, |b| {
+            b.invoice_pdf(invoice_number)
+        })
+    }
+
+    fn mailbox_delete(&mut self, domain_name: &str, id: &str) -> Result<()> {
+        self.call("mailbox delete", Safety::Idempotent, |b| {
+            b.mailbox_delete(domain_name, id)
+        })
+    }
+
+    fn mailbox_insert(&mut self, domain_name: &str, mailbox: MailboxInsert) -> Result<()> {
+        self.call("mailbox insert", Safety::NotIdempotent, |b| {
+            b.mailbox_insert(domain_name, mailbox_insert(&mailbox))
+        })
+    }
+
+    fn mailbox_item(&mut self, domain_name: &str, id: &str) -> Result<Mailbox> {
+        self.call("mailbox item", Safety::Idempotent, |b| {
+            b.mailbox_item(domain_name, id)
+        })
+    }
+
+    fn mailbox_list(&mut self, domain_name: &str) -> Result<Vec<Mailbox>> {
+        self.call("mailbox list", Safety::Idempotent, |b| {
+            b.mailbox_list(domain_name)
+        })
+    }
+
+    fn mailforward_delete(&mut self, domain_name: &str, id: &str) -> Result<()> {
+        self.call("mail forward delete", Safety::Idempotent, |b| {
+            b.mailforward_delete(domain_name, id)
+        })
+    }
+
+    fn mailforward_insert(
+        &mut self,
+        domain_name: &str,
+        mail_forward: MailForwardInsert,
+    ) -> Result<()> {
+        self.call("mail forward insert", Safety::NotIdempotent, |b| {
+            b.mailforward_insert(domain_name, mail_forward_insert(&mail_forward))
+        })
+    }
+
+    fn mailforward_item(&mut self, domain_name: &str, id: &str) -> Result<MailForward> {
+        self.call("mail forward item", Safety::Idempotent, |b| {
+            b.mailforward_item(domain_name, id)
+        })
+    }
+
+    fn mailforward_list(&mut self, domain_name: &str) -> Result<Vec<MailForward>> {
+        self.call("mail forward list", Safety::Idempotent, |b| {
+            b.mailforward_list(domain_name)
+        })
+    }
+
+    fn product_elements(&mut self, name: &str) -> Result<Vec<ProductElement>> {
+        self.call("product elements", Safety::Idempotent, |b| {
+            b.product_elements(name)
+        })
+    }
+
+    fn products(&mut self) -> Result<Products> {
+        self.call("products", Safety::Idempotent, |b| b.products())
+    }
+
+    fn vps(&mut self, name: &str) -> Result<Vps> {
+        self.call("vps", Safety::Idempotent, |b| b.vps(name))
+    }
+
+    fn vps_list(&mut self) -> Result<Vec<Vps>> {
+        self.call("vps list", Safety::Idempotent, |b| b.vps_list())
+    }
+
+    fn vps_reset(&mut self, name: &str) -> Result<()> {
+        self.call("vps reset", Safety::NotIdempotent, |b| b.vps_reset(name))
+    }
+
+    fn vps_set_is_locked(&mut self, name: &str, locked: bool) -> Result<()> {
+        self.call("vps lock", Safety::Idempotent, |b| {
+            b.vps_set_is_locked(name, locked)
+        })
+    }
+
+    fn vps_start(&mut self, name: &str) -> Result<()> {
+        self.call("vps start", Safety::NotIdempotent, |b| b.vps_start(name))
+    }
+
+    fn vps_stop(&mut self, name: &str) -> Result<()> {
+        self.call("vps stop", Safety::NotIdempotent, |b| b.vps_stop(name))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use transip::api::dns::DnsEntry;
+
+    use super::{RetryPolicy, Retrying};
+    use crate::rate_limit::Budget;
+    use crate::{Backend, Error, Memory, RateLimitHeaders, Result};
+
+    /// Fails the first calls with the given status code before answering from memory
+    struct Flaky {
+        failures: u32,
+        status: u16,
+        memory: Memory,
+    }
+
+    impl Flaky {
+        fn fail(&mut self) -> Result<()> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                Err(Error::Ureq(ureq::Error::StatusCode(self.status)))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    macro_rules! flaky {
+        ($($name:ident($($arg:ident: $type:ty),*) -> $output:ty;)*) => {
+            $(
+                fn $name(&mut self, $($arg: $type),*) -> Result<$output> {
+                    self.fail()?;
+                    self.memory.$name($($arg),*)
+                }
+            )*
+        };
+    }
+
+    impl Backend for Flaky {
+        fn endpoint(&self) -> String {
+            self.memory.endpoint()
+        }
+
+        fn rate_limit(&self) -> Option<RateLimitHeaders> {
+            None
+        }
+
+        flaky! {
+            api_test() -> String;
+            availability_zones() -> Vec<transip::api::general::AvailabilityZone>;
+            dns_entry_delete(domain_name: &str, entry: DnsEntry) -> ();
+            dns_entry_insert(domain_name: &str, entry: DnsEntry) -> ();
+            dns_entry_list(domain_name: &str) -> Vec<DnsEntry>;
+            domain_dnssec_list(domain_name: &str) -> Vec<crate::DnsSecEntry>;
+            domain_dnssec_set(domain_name: &str, entries: Vec<crate::DnsSecEntry>) -> ();
+            domain_item(name: &str) -> transip::api::domain::Domain;
+            domain_list() -> Vec<transip::api::domain::Domain>;
+            domain_nameservers(domain_name: &str) -> Vec<transip::api::domain::NameServer>;
+            domain_nameservers_set(domain_name: &str, nameservers: Vec<transip::api::domain::NameServer>) -> ();
+            invoice(invoice_number: &str) -> transip::api::account::Invoice;
+            invoice_list() -> Vec<transip::api::account::Invoice>;
+            invoice_pdf(invoice_number: &str) -> String;
+            mailbox_delete(domain_name: &str, id: &str) -> ();
+            mailbox_insert(domain_name: &str, mailbox: transip::api::email::MailboxInsert) -> ();
+            mailbox_item(domain_name: &str, id: &str) -> transip::api::email::Mailbox;
+            mailbox_list(domain_name: &str) -> Vec<transip::api::email::Mailbox>;
+            mailforward_delete(domain_name: &str, id: &str) -> ();
+            mailforward_insert(domain_name: &str, mail_forward: transip::api::email::MailForwardInsert) -> ();
+            mailforward_item(domain_name: &str, id: &str) -> transip::api::email::MailForward;
+            mailforward_list(domain_name: &str) -> Vec<transip::api::email::MailForward>;
+            product_elements(name: &str) -> Vec<transip::api::general::ProductElement>;
+            products() -> transip::api::general::Products;
+            vps(name: &str) -> transip::api::vps::Vps;
+            vps_list() -> Vec<transip::api::vps::Vps>;
+            vps_reset(name: &str) -> ();
+            vps_set_is_locked(name: &str, locked: bool) -> ();
+            vps_start(name: &str) -> ();
+            vps_stop(name: &str) -> ();
+        }
+    }
+
+    fn retrying(failures: u32, status: u16) -> Retrying {
+        let policy = RetryPolicy {
+            retries: 2,
+            max_delay: Duration::ZERO,
+        };
+        let flaky = Flaky {
+            failures,
+            status,
+            memory: Memory::default(),
+        };
+        Retrying::new(
+            Box::new(flaky),
+            Arc::new(Mutex::new(policy)),
+            Arc::new(Mutex::new(Budget::default())),
+        )
+    }
+
+    #[test]
+    fn read_retried() {
+        assert_eq!(retrying(2, 502).api_test().unwrap(), "pong");
+        assert!(retrying(3, 502).api_test().is_err());
+    }
+
+    #[test]
+    fn not_idempotent() {
+        assert!(matches!(
+            retrying(1, 429).vps_reset("vps1"),
+            Err(Error::Rejected(_))
+        ));
+        assert!(matches!(
+            retrying(1, 502).vps_reset("vps1"),
+            Err(Error::Ureq(_))
+        ));
+        assert!(retrying(1, 502).vps_start("vps1").is_err());
+        assert!(retrying(1, 502).vps_stop("vps1").is_err());
+        assert!(matches!(retrying(1, 404).vps_list(), Err(Error::Ureq(_))));
+    }
+
+    #[test]
+    fn delay() {
+        let policy = RetryPolicy {
+            retries: 10,
+            max_delay: Duration::from_secs(4),
+        };
+        assert!(policy.delay(0) >= Duration::from_millis(250));
+        assert!(policy.delay(0) <= Duration::from_millis(500));
+        assert!(policy.delay(9) >= Duration::from_secs(2));
+        assert!(policy.delay(9) <= Duration::from_secs(4));
+    }
+}
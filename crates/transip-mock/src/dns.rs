@@ -0,0 +1,115 @@
+use std::{
+    net::UdpSocket,
+    sync::{Arc, Mutex},
+};
+
+use transip::api::dns::DnsEntry;
+use transip_execute::{
+    Fixture, Memory,
+    dns::{self, Question, RData},
+};
+
+fn record_type(entry: &DnsEntry) -> u16 {
+    match entry.entry_type.as_str() {
+        "A" => dns::A,
+        "AAAA" => dns::AAAA,
+        "CNAME" => dns::CNAME,
+        "NS" => dns::NS,
+        "TXT" => dns::TXT,
+        _ => 0,
+    }
+}
+
+fn rdata(entry: &DnsEntry) -> Option<RData> {
+    match record_type(entry) {
+        dns::A => entry.content.parse().ok().map(RData::A),
+        dns::AAAA => entry.content.parse().ok().map(RData::Aaaa),
+        dns::CNAME => Some(RData::Cname(entry.content.clone())),
+        dns::NS => Some(RData::Ns(entry.content.clone())),
+        dns::TXT => Some(RData::Txt(entry.content.clone())),
+        _ => None,
+    }
+}
+
+/// Records of the zone containing the name of question, none if the mock has no such zone
+pub fn answers(fixture: &Fixture, question: &Question) -> Option<Vec<RData>> {
+    let (domain, entries) = fixture
+        .dns
+        .iter()
+        .filter(|(domain, _)| {
+            question.name == **domain || question.name.ends_with(&format!(".{domain}"))
+        })
+        .max_by_key(|(domain, _)| domain.len())?;
+    let name = question
+        .name
+        .strip_suffix(domain.as_str())
+        .and_then(|name| name.strip_suffix('.'))
+        .unwrap_or("@");
+    Some(
+        entries
+            .iter()
+            .filter(|entry| entry.name == name && record_type(entry) == question.record_type)
+            .filter_map(rdata)
+            .collect(),
+    )
+}
+
+/// Answers dns requests for the dns entries in memory, until the socket fails
+pub fn serve(socket: UdpSocket, memory: Arc<Mutex<Memory>>) {
+    let mut buffer = [0u8; 512];
+    while let Ok((length, peer)) = socket.recv_from(&mut buffer) {
+        let Ok(question) = dns::question(&buffer[..length]) else {
+            continue;
+        };
+        let answers = {
+            let memory = memory.lock().unwrap_or_else(|error| error.into_inner());
+            answers(memory.fixture(), &question)
+        };
+        let _ = socket.send_to(&dns::response(&question, answers.as_deref()), peer);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+    use transip_execute::{
+        Fixture,
+        dns::{self, Question, RData},
+    };
+    use transip_test_support::fixture;
+
+    use super::answers;
+
+    fn question(name: &str, record_type: u16) -> Question {
+        Question {
+            id: 1,
+            name: name.to_owned(),
+            record_type,
+        }
+    }
+
+    #[test]
+    fn zone_records() {
+        let fixture = fixture::<Fixture>(json!({
+            "dns": {
+                "example.nl": [
+                    {"name": "@", "expire": 300, "type": "A", "content": "1.2.3.4"},
+                    {"name": "_acme-challenge", "expire": 60, "type": "TXT", "content": "abc"},
+                ],
+            },
+        }));
+        assert_eq!(
+            answers(&fixture, &question("example.nl", dns::A)),
+            Some(vec![RData::A("1.2.3.4".parse().unwrap())])
+        );
+        assert_eq!(
+            answers(&fixture, &question("_acme-challenge.example.nl", dns::TXT)),
+            Some(vec![RData::Txt("abc".to_owned())])
+        );
+        assert_eq!(
+            answers(&fixture, &question("www.example.nl", dns::A)),
+            Some(vec![])
+        );
+        assert_eq!(answers(&fixture, &question("example.com", dns::A)), None);
+    }
+}
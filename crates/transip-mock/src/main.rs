@@ -0,0 +1,340 @@
+use std::{
+    net::UdpSocket,
+    path::{Path, PathBuf},
+    process::exit,
+    sync::{Arc, Mutex, mpsc::Sender},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use tiny_http::{Header, Request, Response, Server};
+use transip_execute::{Memory, Result};
+
+mod dns;
+mod routes;
+
+const DEFAULT_ADDRESS: &str = "127.0.0.1:8080";
+/// Calls per window reported in the rate limit headers, the limit of the api
+const LIMIT: u32 = 1000;
+const WINDOW: u64 = 60;
+const USAGE: &str =
+    "Usage: transip-mock [--address <host:port>] [--dns <host:port>] [--state <file.json>]";
+
+struct Arguments {
+    address: String,
+    dns: Option<String>,
+    state: Option<PathBuf>,
+}
+
+fn usage(message: &str) -> ! {
+    #[allow(clippy::print_stderr)]
+    {
+        eprintln!("{message}\n{USAGE}");
+    }
+    exit(1);
+}
+
+fn arguments() -> Arguments {
+    let mut arguments = Arguments {
+        address: DEFAULT_ADDRESS.to_owned(),
+        dns: None,
+        state: None,
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .unwrap_or_else(|| usage(&format!("value missing after {arg}")))
+        };
+        match arg.as_str() {
+            "--address" => arguments.address = value(),
+            "--dns" => arguments.dns = Some(value()),
+            "--state" => arguments.state = Some(PathBuf::from(value())),
+            _ => usage(&format!("unknown argument {arg}")),
+        }
+    }
+    arguments
+}
+
+fn save(memory: &Memory, path: &Path) -> Result<()> {
+    let json = serde_json::to_vec_pretty(memory.fixture()).map_err(std::io::Error::from)?;
+    std::fs::write(path, json).map_err(Into::into)
+}
+
+/// Calls answered in the current window, reported like the api does but not enforced
+#[derive(Default)]
+struct RateLimit {
+    window_start: u64,
+    used: u32,
+}
+
+impl RateLimit {
+    /// Counts a call and returns the rate limit headers of its response
+    fn count(&mut self) -> [Header; 3] {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+        if now >= self.window_start + WINDOW {
+            self.window_start = now;
+            self.used = 0;
+        }
+        self.used += 1;
+        let header = |name: &str, value: String| Header::from_bytes(name, value).unwrap();
+        [
+            header("X-Rate-Limit-Limit", LIMIT.to_string()),
+            header(
+                "X-Rate-Limit-Remaining",
+                LIMIT.saturating_sub(self.used).to_string(),
+            ),
+            header(
+                "X-Rate-Limit-Reset",
+                (self.window_start + WINDOW).to_string(),
+            ),
+        ]
+    }
+}
+
+fn header<'a>(request: &'a Request, name: &'static str) -> Option<&'a str> {
+    request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv(name))
+        .map(|header| header.value.as_str())
+}
+
+/// Method, url and body of a request the mock received
+type Received = (String, String, String);
+
+/// Answers the requests of server until it stops, saving mutations in state if given
+///
+/// Every request is passed to received if given, so tests can see what a client sent.
+fn serve(
+    server: Server,
+    memory: Arc<Mutex<Memory>>,
+    state: Option<&Path>,
+    received: Option<Sender<Received>>,
+) -> Result<()> {
+    let content_type = Header::from_bytes("Content-Type", "application/json").unwrap();
+    let mut rate_limit = RateLimit::default();
+    for mut request in server.incoming_requests() {
+        let mut body = String::new();
+        request.as_reader().read_to_string(&mut body)?;
+        if let Some(received) = received.as_ref() {
+            let method = request.method().as_str().to_owned();
+            let _ = received.send((method, request.url().to_owned(), body.clone()));
+        }
+        let mut memory = memory.lock().unwrap_or_else(|error| error.into_inner());
+        let reply = routes::unauthorized(
+            request.url(),
+            header(&request, "Signature"),
+            header(&request, "Authorization"),
+        )
+        .unwrap_or_else(|| {
+            routes::handle(&mut memory, request.method().as_str(), request.url(), &body)
+        });
+        if reply.is_mutation() {
+            if let Some(path) = state {
+                save(&memory, path)?;
+            }
+        }
+        drop(memory);
+        let mut response =
+            Response::from_string(reply.body.map(|body| body.to_string()).unwrap_or_default())
+                .with_status_code(reply.status)
+                .with_header(content_type.clone());
+        for header in rate_limit.count() {
+            response.add_header(header);
+        }
+        request.respond(response)?;
+    }
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let arguments = arguments();
+    let memory = Arc::new(Mutex::new(match arguments.state.as_ref() {
+        Some(path) if path.exists() => Memory::from_fixture(path)?,
+        _ => Memory::default(),
+    }));
+    if let Some(address) = arguments.dns.as_ref() {
+        let socket = UdpSocket::bind(address)
+            .unwrap_or_else(|error| usage(&format!("cannot listen on {address}: {error}")));
+        let memory = memory.clone();
+        std::thread::spawn(move || dns::serve(socket, memory));
+    }
+    let server = Server::http(&arguments.address)
+        .unwrap_or_else(|error| usage(&format!("cannot listen on {}: {error}", arguments.address)));
+    serve(server, memory, arguments.state.as_deref(), None)
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{
+        Arc, Mutex,
+        mpsc::{self, Sender},
+    };
+
+    use serde_json::{Value, json};
+    use tiny_http::Server;
+    use transip_execute::{
+        Api, Client, CommandOutput, Fixture, Interaction, Memory, Replay, Settings, TransipCommand,
+    };
+    use transip_test_support::{TempDir, domain, fixture};
+
+    use super::Received;
+
+    /// Throwaway key generated for these tests only, to sign the auth request the mock does not verify
+    const KEY: &str = include_str!("throwaway-test-key.pem");
+    /// Public key of a key signing key, in the base64 of a dnskey record
+    const DNSKEY: &str =
+        "mdsswUyr3DPW132mOi8V9xESWE8jTo0dxCjjnopKl+GqJxpVXckHAeF+KkxLbxILfDLUT0rAK9iUzy1L53eKGQ==";
+
+    fn execute(client: &mut Client, line: &str) -> CommandOutput {
+        let command = line.parse::<TransipCommand>().unwrap();
+        client.execute_output(&command.command).unwrap()
+    }
+
+    /// Api client of the test account on a mock serving memory, with its token in dir
+    fn mock(dir: &TempDir, memory: Memory, received: Option<Sender<Received>>) -> Api {
+        let private_key = dir.join("key.pem");
+        std::fs::write(&private_key, KEY).unwrap();
+        let settings = Settings {
+            user_name: "test".to_owned(),
+            private_key_pem_file: private_key.display().to_string(),
+            token_path: dir.join("token.txt").display().to_string(),
+            whitelisted_only: false,
+            read_only: false,
+            ipv6_only: false,
+            token_expiration: "5 minutes".to_owned(),
+        };
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let address = server.server_addr().to_ip().unwrap();
+        let memory = Arc::new(Mutex::new(memory));
+        std::thread::spawn(move || {
+            super::serve(server, memory, None, received).map_err(|e| e.to_string())
+        });
+        Api::try_new(settings, Some(&format!("http://{address}/v6"))).unwrap()
+    }
+
+    #[test]
+    fn real_client() {
+        let dir = TempDir::new("transip-mock");
+        let memory = Memory::from(fixture::<Fixture>(json!({
+            "dns": {"example.nl": [{"name": "@", "expire": 300, "type": "A", "content": "192.0.2.1"}]},
+            "domains": [domain("example.nl")],
+        })));
+        let client = mock(&dir, memory, None);
+        let token_path = client.token_path().to_owned();
+        let mut client = Client::new(client);
+        assert_eq!(client.rate_limit(), None);
+        match execute(&mut client, "dns insert example.nl www 300 A 192.0.2.2") {
+            CommandOutput::DnsChange(change) => assert_eq!(change.inserted.len(), 1),
+            _ => panic!("inserted entry not reported"),
+        }
+        match execute(&mut client, "dns delete example.nl @ 300 A 192.0.2.1") {
+            CommandOutput::DnsChange(change) => assert_eq!(change.deleted.len(), 1),
+            _ => panic!("deleted entry not reported"),
+        }
+        match execute(&mut client, "dns list example.nl") {
+            CommandOutput::DnsEntries(entries) => {
+                assert_eq!(entries.len(), 1);
+                assert_eq!(entries[0].content, "192.0.2.2");
+            }
+            _ => panic!("dns entries not listed"),
+        }
+        execute(
+            &mut client,
+            "domain nameservers-set example.nl ns1.example.net ns2.example.nl=192.0.2.53",
+        );
+        match execute(&mut client, "domain nameservers example.nl") {
+            CommandOutput::Nameservers(nameservers) => {
+                assert_eq!(nameservers.len(), 2);
+                assert_eq!(nameservers[1].ipv4.as_deref(), Some("192.0.2.53"));
+            }
+            _ => panic!("nameservers not listed"),
+        }
+        let key = dir.join("Kexample.nl.+013+12345.key");
+        std::fs::write(&key, format!("example.nl. IN DNSKEY 257 3 13 {DNSKEY}\n")).unwrap();
+        execute(
+            &mut client,
+            &format!("domain dnssec set example.nl {}", key.display()),
+        );
+        match execute(&mut client, "domain dnssec list example.nl") {
+            CommandOutput::DnsSecEntries(entries) => assert_eq!(entries[0].public_key, DNSKEY),
+            _ => panic!("dnssec entries not listed"),
+        }
+        match execute(&mut client, "ratelimit") {
+            CommandOutput::RateLimit(rate_limit) => {
+                assert_eq!(rate_limit.limit, 1000);
+                assert!(rate_limit.remaining < 1000);
+            }
+            _ => panic!("rate limit not reported"),
+        }
+        drop(client);
+        assert!(
+            std::fs::read_to_string(token_path)
+                .unwrap()
+                .starts_with("eyJ")
+        );
+    }
+
+    /// Recordings hold the requests the api client sends, not a description of them
+    #[test]
+    fn recording() {
+        let dir = TempDir::new("transip-mock-recording");
+        let memory = Memory::from(fixture::<Fixture>(json!({
+            "dns": {"example.nl": [{"name": "@", "expire": 300, "type": "A", "content": "192.0.2.1"}]},
+            "domains": [domain("example.nl")],
+            "vps": [{
+                "name": "example-vps", "uuid": "00000000-0000-0000-0000-000000000001",
+                "description": "", "productName": "vps-bladevps-x1", "operatingSystem": "debian",
+                "diskSize": 157286400, "memorySize": 1048576, "cpus": 1, "status": "running",
+                "ipAddress": "192.0.2.10", "macAddress": "52:54:00:00:00:01",
+                "currentSnapshots": 0, "maxSnapshots": 1, "isLocked": false, "isBlocked": false,
+                "isCustomerLocked": false, "availabilityZone": "ams0", "tags": [],
+            }],
+        })));
+        let (sender, received) = mpsc::channel();
+        let mut client = Client::new(mock(&dir, memory, Some(sender)));
+        let cassette = dir.join("cassette");
+        client.record(&cassette).unwrap();
+        let lines = [
+            "dns insert example.nl www 300 A 192.0.2.2",
+            "dns delete example.nl @ 300 A 192.0.2.1",
+            "domain nameservers-set example.nl ns1.example.net ns2.example.net",
+            "vps lock example-vps",
+            "vps stop example-vps",
+        ];
+        for line in lines {
+            execute(&mut client, line);
+        }
+        drop(client);
+
+        let mut paths = std::fs::read_dir(&cassette)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect::<Vec<_>>();
+        paths.sort();
+        let mut sent = received
+            .try_iter()
+            .filter(|(_, url, _)| url != "/v6/auth")
+            .map(|(method, url, body)| {
+                let path = url.trim_start_matches("/v6/").to_owned();
+                (method, path, serde_json::from_str::<Value>(&body).ok())
+            });
+        for path in paths {
+            let interaction =
+                serde_json::from_slice::<Interaction>(&std::fs::read(&path).unwrap()).unwrap();
+            let recorded = (interaction.method, interaction.path, interaction.body);
+            assert!(
+                sent.any(|request| request == recorded),
+                "{recorded:?} of {} not sent",
+                path.display()
+            );
+        }
+
+        let mut replay = Client::new(Replay::from_dir(&cassette).unwrap());
+        for line in lines {
+            execute(&mut replay, line);
+        }
+    }
+}
@@ -0,0 +1,384 @@
+use serde::de::DeserializeOwned;
+use serde_json::{Value, json};
+use transip::{
+    Error,
+    api::{
+        dns::DnsEntry,
+        domain::NameServer,
+        email::{MailForwardInsert, MailboxInsert},
+    },
+};
+use transip_execute::{Backend, DnsSecEntry, Memory};
+
+const PREFIX: &str = "/v6/";
+/// Unsigned jwt with the claims of an api token, that expires in 2100
+const TOKEN: &str = "eyJ0eXAiOiJKV1QiLCJhbGciOiJub25lIn0.eyJpc3MiOiJ0cmFuc2lwLW1vY2siLCJhdWQiOiJ0cmFuc2lwLW1vY2siLCJqdGkiOiJ0cmFuc2lwLW1vY2siLCJpYXQiOjE1Nzc4MzY4MDAsIm5iZiI6MTU3NzgzNjgwMCwiZXhwIjo0MTAyNDQ0ODAwLCJjaWQiOjEsInJvIjpmYWxzZSwiZ2siOnRydWUsImt2Ijp0cnVlfQ.";
+
+/// Status code and json body sent back to the client
+#[derive(Debug, PartialEq)]
+pub struct Reply {
+    pub status: u16,
+    pub body: Option<Value>,
+}
+
+impl Reply {
+    fn ok(body: Value) -> Self {
+        Self {
+            status: 200,
+            body: Some(body),
+        }
+    }
+
+    fn created() -> Self {
+        Self {
+            status: 201,
+            body: None,
+        }
+    }
+
+    fn no_content() -> Self {
+        Self {
+            status: 204,
+            body: None,
+        }
+    }
+
+    fn error(status: u16, message: String) -> Self {
+        Self {
+            status,
+            body: Some(json!({ "error": message })),
+        }
+    }
+
+    /// Mutations change the state, which has to be saved when a state file is used
+    pub fn is_mutation(&self) -> bool {
+        self.status == 201 || self.status == 204
+    }
+}
+
+fn reply<T>(result: transip::Result<T>, f: impl FnOnce(T) -> Reply) -> Reply {
+    match result {
+        Ok(value) => f(value),
+        Err(Error::Rejected(message)) => Reply::error(404, message),
+        Err(error) => Reply::error(500, error.to_string()),
+    }
+}
+
+fn body<T: DeserializeOwned>(body: &str, field: Option<&str>) -> Result<T, Reply> {
+    let value = serde_json::from_str::<Value>(body)
+        .map_err(|error| Reply::error(400, error.to_string()))?;
+    let value = match field {
+        Some(field) => value
+            .get(field)
+            .cloned()
+            .ok_or_else(|| Reply::error(400, format!("{field} missing in body")))?,
+        None => value,
+    };
+    serde_json::from_value(value).map_err(|error| Reply::error(400, error.to_string()))
+}
+
+fn dns(memory: &mut Memory, method: &str, domain: &str, request: &str) -> Reply {
+    match method {
+        "GET" => reply(memory.dns_entry_list(domain), |entries| {
+            Reply::ok(json!({ "dnsEntries": entries }))
+        }),
+        "POST" => match body::<DnsEntry>(request, Some("dnsEntry")) {
+            Ok(entry) => reply(memory.dns_entry_insert(domain, entry), |_| Reply::created()),
+            Err(reply) => reply,
+        },
+        "DELETE" => match body::<DnsEntry>(request, Some("dnsEntry")) {
+            Ok(entry) => reply(memory.dns_entry_delete(domain, entry), |_| {
+                Reply::no_content()
+            }),
+            Err(reply) => reply,
+        },
+        _ => Reply::error(405, format!("{method} not allowed")),
+    }
+}
+
+fn dnssec(memory: &mut Memory, method: &str, domain: &str, request: &str) -> Reply {
+    match method {
+        "GET" => reply(memory.domain_dnssec_list(domain), |entries| {
+            Reply::ok(json!({ "dnsSecEntries": entries }))
+        }),
+        "PUT" => match body::<Vec<DnsSecEntry>>(request, Some("dnsSecEntries")) {
+            Ok(entries) => reply(memory.domain_dnssec_set(domain, entries), |_| {
+                Reply::no_content()
+            }),
+            Err(reply) => reply,
+        },
+        _ => Reply::error(405, format!("{method} not allowed")),
+    }
+}
+
+fn nameservers(memory: &mut Memory, method: &str, domain: &str, request: &str) -> Reply {
+    match method {
+        "GET" => reply(memory.domain_nameservers(domain), |nameservers| {
+            Reply::ok(json!({ "nameservers": nameservers }))
+        }),
+        "PUT" => match body::<Vec<NameServer>>(request, Some("nameservers")) {
+            Ok(nameservers) => reply(memory.domain_nameservers_set(domain, nameservers), |_| {
+                Reply::no_content()
+            }),
+            Err(reply) => reply,
+        },
+        _ => Reply::error(405, format!("{method} not allowed")),
+    }
+}
+
+fn vps(memory: &mut Memory, method: &str, name: &str, request: &str) -> Reply {
+    match method {
+        "GET" => reply(memory.vps(name), |vps| Reply::ok(json!({ "vps": vps }))),
+        "PATCH" => match body::<String>(request, Some("action")) {
+            Ok(action) => match action.as_str() {
+                "start" => reply(memory.vps_start(name), |_| Reply::no_content()),
+                "stop" => reply(memory.vps_stop(name), |_| Reply::no_content()),
+                "reset" => reply(memory.vps_reset(name), |_| Reply::no_content()),
+                _ => Reply::error(400, format!("unknown action {action}")),
+            },
+            Err(reply) => reply,
+        },
+        "PUT" => match body::<Value>(request, Some("vps")) {
+            Ok(vps) => match vps.get("isCustomerLocked").and_then(Value::as_bool) {
+                Some(locked) => reply(memory.vps_set_is_locked(name, locked), |_| {
+                    Reply::no_content()
+                }),
+                None => Reply::error(400, "isCustomerLocked missing in body".to_owned()),
+            },
+            Err(reply) => reply,
+        },
+        _ => Reply::error(405, format!("{method} not allowed")),
+    }
+}
+
+fn mailboxes(memory: &mut Memory, method: &str, path: &[&str], request: &str) -> Reply {
+    match (method, path) {
+        ("GET", [domain]) => reply(memory.mailbox_list(domain), |mailboxes| {
+            Reply::ok(json!({ "mailboxes": mailboxes }))
+        }),
+        ("POST", [domain]) => match body::<MailboxInsert>(request, None) {
+            Ok(mailbox) => reply(memory.mailbox_insert(domain, mailbox), |_| Reply::created()),
+            Err(reply) => reply,
+        },
+        ("GET", [domain, id]) => reply(memory.mailbox_item(domain, id), |mailbox| {
+            Reply::ok(json!({ "mailbox": mailbox }))
+        }),
+        ("DELETE", [domain, id]) => {
+            reply(memory.mailbox_delete(domain, id), |_| Reply::no_content())
+        }
+        _ => Reply::error(405, format!("{method} not allowed")),
+    }
+}
+
+fn mail_forwards(memory: &mut Memory, method: &str, path: &[&str], request: &str) -> Reply {
+    match (method, path) {
+        ("GET", [domain]) => reply(memory.mailforward_list(domain), |forwards| {
+            Reply::ok(json!({ "forwards": forwards }))
+        }),
+        ("POST", [domain]) => match body::<MailForwardInsert>(request, None) {
+            Ok(forward) => reply(memory.mailforward_insert(domain, forward), |_| {
+                Reply::created()
+            }),
+            Err(reply) => reply,
+        },
+        ("GET", [domain, id]) => reply(memory.mailforward_item(domain, id), |forward| {
+            Reply::ok(json!({ "forward": forward }))
+        }),
+        ("DELETE", [domain, id]) => reply(memory.mailforward_delete(domain, id), |_| {
+            Reply::no_content()
+        }),
+        _ => Reply::error(405, format!("{method} not allowed")),
+    }
+}
+
+/// Token for a sign in request with a login and nonce, the signature is required but not verified
+fn auth(request: &str) -> Reply {
+    match body::<Value>(request, None) {
+        Ok(value) if value.get("login").is_some() && value.get("nonce").is_some() => {
+            Reply::ok(json!({ "token": TOKEN }))
+        }
+        Ok(_) => Reply::error(400, "login and nonce missing in body".to_owned()),
+        Err(reply) => reply,
+    }
+}
+
+/// Refusal of a request without the credentials the api requires, None if it may be handled
+///
+/// Sign in requests need a signature, all other requests the token handed out by sign in.
+pub fn unauthorized(
+    url: &str,
+    signature: Option<&str>,
+    authorization: Option<&str>,
+) -> Option<Reply> {
+    let path = url.split_once('?').map_or(url, |(path, _)| path);
+    let authorized = if path.trim_end_matches('/') == format!("{PREFIX}auth") {
+        signature.is_some_and(|signature| !signature.is_empty())
+    } else {
+        authorization == Some(format!("Bearer {TOKEN}").as_str())
+    };
+    (!authorized).then(|| Reply::error(401, "authentication required".to_owned()))
+}
+
+/// Handles one api request on the state kept in memory
+pub fn handle(memory: &mut Memory, method: &str, url: &str, request: &str) -> Reply {
+    let path = url.split_once('?').map_or(url, |(path, _)| path);
+    let Some(path) = path.strip_prefix(PREFIX) else {
+        return Reply::error(404, format!("{url} not found"));
+    };
+    let segments = path
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect::<Vec<_>>();
+    match (method, segments.as_slice()) {
+        ("POST", ["auth"]) => auth(request),
+        ("GET", ["api-test"]) => {
+            reply(memory.api_test(), |ping| Reply::ok(json!({ "ping": ping })))
+        }
+        ("GET", ["availability-zones"]) => reply(memory.availability_zones(), |zones| {
+            Reply::ok(json!({ "availabilityZones": zones }))
+        }),
+        ("GET", ["products"]) => reply(memory.products(), |products| {
+            Reply::ok(json!({ "products": products }))
+        }),
+        ("GET", ["products", name, "elements"]) => {
+            reply(memory.product_elements(name), |elements| {
+                Reply::ok(json!({ "productElements": elements }))
+            })
+        }
+        ("GET", ["domains"]) => reply(memory.domain_list(), |domains| {
+            Reply::ok(json!({ "domains": domains }))
+        }),
+        ("GET", ["domains", name]) => reply(memory.domain_item(name), |domain| {
+            Reply::ok(json!({ "domain": domain }))
+        }),
+        (_, ["domains", name, "dns"]) => dns(memory, method, name, request),
+        (_, ["domains", name, "dnssec"]) => dnssec(memory, method, name, request),
+        (_, ["domains", name, "nameservers"]) => nameservers(memory, method, name, request),
+        ("GET", ["invoices"]) => reply(memory.invoice_list(), |invoices| {
+            Reply::ok(json!({ "invoices": invoices }))
+        }),
+        ("GET", ["invoices", number]) => reply(memory.invoice(number), |invoice| {
+            Reply::ok(json!({ "invoice": invoice }))
+        }),
+        ("GET", ["invoices", number, "pdf"]) => reply(memory.invoice_pdf(number), |pdf| {
+            Reply::ok(json!({ "pdf": pdf }))
+        }),
+        ("GET", ["vps"]) => reply(memory.vps_list(), |vpss| {
+            Reply::ok(json!({ "vpss": vpss, "_links": [] }))
+        }),
+        (_, ["vps", name]) => vps(memory, method, name, request),
+        (_, ["email", domain, "mailboxes", rest @ ..]) => {
+            let path = [&[*domain], rest].concat();
+            mailboxes(memory, method, &path, request)
+        }
+        (_, ["email", domain, "mail-forwards", rest @ ..]) => {
+            let path = [&[*domain], rest].concat();
+            mail_forwards(memory, method, &path, request)
+        }
+        _ => Reply::error(404, format!("{method} {url} not found")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+    use transip_execute::{Fixture, Memory};
+    use transip_test_support::{domain, fixture};
+
+    use super::{Reply, TOKEN, handle, unauthorized};
+
+    fn memory() -> Memory {
+        let mut domain = domain("example.nl");
+        domain["nameservers"] =
+            json!([{"hostname": "ns0.transip.net", "ipv4": null, "ipv6": null}]);
+        Memory::from(fixture::<Fixture>(json!({
+            "dns": {"example.nl": [{"name": "@", "expire": 300, "type": "A", "content": "1.2.3.4"}]},
+            "domains": [domain],
+        })))
+    }
+
+    #[test]
+    fn dns_insert_and_list() {
+        let mut memory = memory();
+        let entry =
+            json!({"dnsEntry": {"name": "www", "expire": 300, "type": "A", "content": "1.2.3.4"}});
+        let reply = handle(
+            &mut memory,
+            "POST",
+            "/v6/domains/example.nl/dns",
+            &entry.to_string(),
+        );
+        assert_eq!(reply, Reply::created());
+
+        let reply = handle(&mut memory, "GET", "/v6/domains/example.nl/dns", "");
+        assert_eq!(reply.status, 200);
+        assert_eq!(reply.body.unwrap()["dnsEntries"][1]["name"], json!("www"));
+    }
+
+    #[test]
+    fn nameservers() {
+        let mut memory = memory();
+        let nameservers =
+            json!({"nameservers": [{"hostname": "ns.example.net", "ipv4": null, "ipv6": null}]});
+        let reply = handle(
+            &mut memory,
+            "PUT",
+            "/v6/domains/example.nl/nameservers",
+            &nameservers.to_string(),
+        );
+        assert_eq!(reply, Reply::no_content());
+
+        let reply = handle(&mut memory, "GET", "/v6/domains/example.nl/nameservers", "");
+        assert_eq!(reply, Reply::ok(nameservers));
+    }
+
+    #[test]
+    fn not_found() {
+        let mut memory = memory();
+        assert_eq!(
+            handle(&mut memory, "GET", "/v6/domains/unknown.nl/dns", "").status,
+            404
+        );
+        assert_eq!(handle(&mut memory, "GET", "/v6/unknown", "").status, 404);
+    }
+
+    #[test]
+    fn bad_body() {
+        let mut memory = memory();
+        let reply = handle(&mut memory, "POST", "/v6/domains/example.nl/dns", "{}");
+        assert_eq!(reply.status, 400);
+    }
+
+    #[test]
+    fn authorization() {
+        let url = "/v6/domains";
+        assert_eq!(unauthorized(url, None, None).unwrap().status, 401);
+        assert_eq!(
+            unauthorized(url, None, Some("Bearer other"))
+                .unwrap()
+                .status,
+            401
+        );
+        assert_eq!(
+            unauthorized(url, None, Some(&format!("Bearer {TOKEN}"))),
+            None
+        );
+        assert_eq!(unauthorized("/v6/auth", None, None).unwrap().status, 401);
+        assert_eq!(unauthorized("/v6/auth", Some("c2ln"), None), None);
+
+        let mut memory = memory();
+        let request = json!({"login": "test", "nonce": "1"}).to_string();
+        assert_eq!(
+            handle(&mut memory, "POST", "/v6/auth", &request),
+            Reply::ok(json!({"token": TOKEN}))
+        );
+        assert_eq!(handle(&mut memory, "POST", "/v6/auth", "{}").status, 400);
+    }
+
+    #[test]
+    fn api_test() {
+        let mut memory = memory();
+        let reply = handle(&mut memory, "GET", "/v6/api-test", "");
+        assert_eq!(reply, Reply::ok(json!({"ping": "pong"})));
+    }
+}
@@ -0,0 +1,73 @@
+//! Fixtures for the tests of the crates in this workspace, never published
+
+use std::{
+    ops::Deref,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use serde::de::DeserializeOwned;
+use serde_json::{Value, json};
+
+/// Json of a registered domain without nameservers, with the field names of the api
+pub fn domain(name: &str) -> Value {
+    json!({
+        "name": name,
+        "nameservers": [],
+        "contacts": [],
+        "authCode": null,
+        "isTransferLocked": false,
+        "registrationDate": "2020-01-01",
+        "renewalDate": "2030-01-01",
+        "isWhitelabel": false,
+        "cancellationDate": null,
+        "cancellationStatus": null,
+        "isDnsOnly": false,
+        "tags": [],
+        "canEditDns": true,
+        "hasAutoDns": false,
+        "hasDnsSec": false,
+        "status": "registered",
+    })
+}
+
+/// Value of json, like the fixture of `transipctl --fixture`, panics if it does not match
+pub fn fixture<T: DeserializeOwned>(json: Value) -> T {
+    serde_json::from_value(json).expect("fixture does not match")
+}
+
+/// Directory of one test, unique across tests and test runs and removed when dropped
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "{name}-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&dir).expect("cannot create test directory");
+        Self(dir)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,356 @@
+use std::{
+    fmt::Display,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use ring::{
+    rand::SystemRandom,
+    signature::{ECDSA_P256_SHA256_FIXED_SIGNING, EcdsaKeyPair, KeyPair},
+};
+use serde::{Deserialize, de::DeserializeOwned};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use ureq::{
+    Agent, Body,
+    http::Response,
+    tls::{Certificate, RootCerts, TlsConfig},
+};
+
+use crate::{Result, error::Error};
+
+/// Directory of Let's Encrypt, used when no other directory is given
+pub const LETS_ENCRYPT: &str = "https://acme-v02.api.letsencrypt.org/directory";
+pub const DNS_01: &str = "dns-01";
+const BAD_NONCE: &str = "urn:ietf:params:acme:error:badNonce";
+const JOSE_JSON: &str = "application/jose+json";
+const PEM_CHAIN: &str = "application/pem-certificate-chain";
+const REPLAY_NONCE: &str = "Replay-Nonce";
+const HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+const POLL_TIMEOUT: Duration = Duration::from_secs(300);
+
+fn base64url(bytes: impl AsRef<[u8]>) -> String {
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Directory {
+    new_nonce: String,
+    new_account: String,
+    new_order: String,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Pending,
+    Ready,
+    Processing,
+    Valid,
+    Invalid,
+    Deactivated,
+    Expired,
+    Revoked,
+}
+
+/// Problem document the server sends with an error
+#[derive(Debug, Deserialize)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub detail: Option<String>,
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.detail.as_ref() {
+            Some(detail) => write!(f, "{detail} ({})", self.kind),
+            None => write!(f, "{}", self.kind),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Order {
+    pub status: Status,
+    pub authorizations: Vec<String>,
+    pub finalize: String,
+    pub certificate: Option<String>,
+    pub error: Option<Problem>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Identifier {
+    pub value: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Authorization {
+    /// Domain without the `*.` of a wildcard
+    pub identifier: Identifier,
+    pub status: Status,
+    pub challenges: Vec<Challenge>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Challenge {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub url: String,
+    pub token: String,
+    pub error: Option<Problem>,
+}
+
+/// Account on an acme server, every request is signed with its es256 key
+pub struct Account {
+    agent: Agent,
+    directory: Directory,
+    key: EcdsaKeyPair,
+    rng: SystemRandom,
+    nonce: Option<String>,
+    /// Url of the account, the key id of the requests after registration
+    kid: Option<String>,
+}
+
+impl Account {
+    /// Registers the pkcs8 key in pem, or finds the account registered with it before
+    ///
+    /// The certificate in the pem file ca is the only root trusted for the directory, like the one of Pebble.
+    /// The terms of service of the directory are agreed to only if agree_tos is set.
+    pub fn new(
+        directory: &str,
+        ca: Option<&Path>,
+        key: &str,
+        email: Option<&str>,
+        agree_tos: bool,
+    ) -> Result<Self> {
+        let mut config = Agent::config_builder()
+            .http_status_as_error(false)
+            .timeout_global(Some(HTTP_TIMEOUT));
+        if let Some(ca) = ca {
+            let root = Certificate::from_pem(&std::fs::read(ca)?)?;
+            config = config.tls_config(
+                TlsConfig::builder()
+                    .root_certs(RootCerts::new_with_certs(&[root]))
+                    .build(),
+            );
+        }
+        let agent = Agent::new_with_config(config.build());
+        let directory = agent
+            .get(directory)
+            .call()?
+            .body_mut()
+            .read_json::<Directory>()?;
+        let rng = SystemRandom::new();
+        let pkcs8 = rcgen::KeyPair::from_pem(key)?.serialize_der();
+        let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &pkcs8, &rng)
+            .map_err(|error| Error::Acme(format!("account key: {error}")))?;
+        let mut account = Self {
+            agent,
+            directory,
+            key,
+            rng,
+            nonce: None,
+            kid: None,
+        };
+        let contact = email
+            .map(|email| vec![format!("mailto:{email}")])
+            .unwrap_or_default();
+        let url = account.directory.new_account.clone();
+        let payload = json!({"termsOfServiceAgreed": agree_tos, "contact": contact});
+        let (_, location) = account.post_json::<Value>(&url, Some(&payload))?;
+        account.kid = Some(location.ok_or_else(|| Error::Acme("account without url".to_owned()))?);
+        Ok(account)
+    }
+
+    /// Public key of the account, with the members in the order of the thumbprint
+    fn jwk(&self) -> String {
+        let point = self.key.public_key().as_ref();
+        format!(
+            r#"{{"crv":"P-256","kty":"EC","x":"{}","y":"{}"}}"#,
+            base64url(&point[1..33]),
+            base64url(&point[33..])
+        )
+    }
+
+    /// Value of the txt record for the dns-01 challenge with token
+    pub fn dns_value(&self, token: &str) -> String {
+        let thumbprint = base64url(Sha256::digest(self.jwk()));
+        base64url(Sha256::digest(format!("{token}.{thumbprint}")))
+    }
+
+    fn nonce(&mut self) -> Result<String> {
+        if let Some(nonce) = self.nonce.take() {
+            return Ok(nonce);
+        }
+        let response = self.agent.head(&self.directory.new_nonce).call()?;
+        header(&response, REPLAY_NONCE).ok_or_else(|| Error::Acme("no nonce".to_owned()))
+    }
+
+    fn jws(&mut self, url: &str, payload: Option<&Value>) -> Result<String> {
+        let mut protected = json!({"alg": "ES256", "nonce": self.nonce()?, "url": url});
+        match self.kid.as_ref() {
+            Some(kid) => protected["kid"] = json!(kid),
+            None => protected["jwk"] = serde_json::from_str(&self.jwk())?,
+        }
+        let protected = base64url(protected.to_string());
+        let payload = payload
+            .map(|payload| base64url(payload.to_string()))
+            .unwrap_or_default();
+        let signature = self
+            .key
+            .sign(&self.rng, format!("{protected}.{payload}").as_bytes())
+            .map_err(|error| Error::Acme(format!("signing: {error}")))?;
+        Ok(
+            json!({"protected": protected, "payload": payload, "signature": base64url(signature)})
+                .to_string(),
+        )
+    }
+
+    /// Signed post of payload, or a post-as-get without payload, retried once on a bad nonce
+    fn post(&mut self, url: &str, payload: Option<&Value>, accept: &str) -> Result<Response<Body>> {
+        let mut retried = false;
+        loop {
+            let body = self.jws(url, payload)?;
+            let mut response = self
+                .agent
+                .post(url)
+                .header("Content-Type", JOSE_JSON)
+                .header("Accept", accept)
+                .send(body)?;
+            self.nonce = header(&response, REPLAY_NONCE);
+            if response.status().is_success() {
+                return Ok(response);
+            }
+            let problem = response.body_mut().read_json::<Problem>()?;
+            if problem.kind != BAD_NONCE || retried {
+                return Err(Error::Acme(format!("{url}: {problem}")));
+            }
+            retried = true;
+        }
+    }
+
+    /// Response and location of a signed post
+    fn post_json<T: DeserializeOwned>(
+        &mut self,
+        url: &str,
+        payload: Option<&Value>,
+    ) -> Result<(T, Option<String>)> {
+        let mut response = self.post(url, payload, "application/json")?;
+        let location = header(&response, "Location");
+        Ok((response.body_mut().read_json()?, location))
+    }
+
+    /// Fetches url until done returns true, fails after the poll timeout
+    fn poll<T: DeserializeOwned>(&mut self, url: &str, done: impl Fn(&T) -> bool) -> Result<T> {
+        let start = Instant::now();
+        loop {
+            let (value, _) = self.post_json::<T>(url, None)?;
+            if done(&value) {
+                return Ok(value);
+            }
+            if start.elapsed() > POLL_TIMEOUT {
+                return Err(Error::Acme(format!(
+                    "{url} not ready after {} seconds",
+                    POLL_TIMEOUT.as_secs()
+                )));
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    /// Order for names and its url
+    pub fn new_order(&mut self, names: &[String]) -> Result<(String, Order)> {
+        let identifiers = names
+            .iter()
+            .map(|name| json!({"type": "dns", "value": name}))
+            .collect::<Vec<_>>();
+        let url = self.directory.new_order.clone();
+        let (order, location) =
+            self.post_json::<Order>(&url, Some(&json!({"identifiers": identifiers})))?;
+        let location = location.ok_or_else(|| Error::Acme("order without url".to_owned()))?;
+        Ok((location, order))
+    }
+
+    pub fn authorization(&mut self, url: &str) -> Result<Authorization> {
+        self.post_json(url, None)
+            .map(|(authorization, _)| authorization)
+    }
+
+    /// Tells the server the challenge can be validated
+    pub fn respond(&mut self, challenge: &Challenge) -> Result<()> {
+        self.post_json::<Value>(&challenge.url, Some(&json!({})))
+            .map(|_| ())
+    }
+
+    /// Waits until the server validated the authorization at url
+    pub fn validated(&mut self, url: &str) -> Result<()> {
+        let authorization = self.poll::<Authorization>(url, |authorization| {
+            !matches!(authorization.status, Status::Pending | Status::Processing)
+        })?;
+        if authorization.status == Status::Valid {
+            return Ok(());
+        }
+        let problem = authorization
+            .challenges
+            .iter()
+            .find_map(|challenge| challenge.error.as_ref())
+            .map(|problem| format!(": {problem}"))
+            .unwrap_or_default();
+        Err(Error::Acme(format!(
+            "validation of {} {:?}{problem}",
+            authorization.identifier.value, authorization.status
+        )))
+    }
+
+    /// Finalizes the order at url with a csr in der, returns the full chain in pem
+    pub fn finalize(&mut self, url: &str, order: &Order, csr: &[u8]) -> Result<String> {
+        let payload = json!({"csr": base64url(csr)});
+        self.post_json::<Order>(&order.finalize, Some(&payload))?;
+        let order = self.poll::<Order>(url, |order| {
+            !matches!(order.status, Status::Ready | Status::Processing)
+        })?;
+        match (order.status, order.certificate) {
+            (Status::Valid, Some(certificate)) => Ok(self
+                .post(&certificate, None, PEM_CHAIN)?
+                .body_mut()
+                .read_to_string()?),
+            (status, _) => Err(Error::Acme(format!(
+                "order {status:?}{}",
+                order
+                    .error
+                    .map(|problem| format!(": {problem}"))
+                    .unwrap_or_default()
+            ))),
+        }
+    }
+}
+
+fn header(response: &Response<Body>, name: &str) -> Option<String> {
+    response
+        .headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Problem, base64url};
+
+    #[test]
+    fn problem() {
+        let problem = serde_json::from_str::<Problem>(
+            r#"{"type": "urn:ietf:params:acme:error:dns", "detail": "no txt record"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            problem.to_string(),
+            "no txt record (urn:ietf:params:acme:error:dns)"
+        );
+        assert_eq!(base64url([0xfb, 0xff]), "-_8");
+    }
+}
@@ -0,0 +1,423 @@
+use std::{io::IsTerminal, net::SocketAddr, path::PathBuf};
+
+use crate::{Result, cert::CertCommand, certbot::Hook, ddns::Source, error::Error};
+
+const ACME_CA: &str = "--acme-ca";
+const ACME_DIRECTORY: &str = "--acme-directory";
+const ACME_EMAIL: &str = "--acme-email";
+const ACME_NAMESERVERS: &str = "--acme-nameservers";
+const AGREE_TOS: &str = "--agree-tos";
+const CERT: &str = "cert";
+const CERT_DIR: &str = "--cert-dir";
+const CERTBOT: &str = "certbot";
+const DAEMON: &str = "--daemon";
+const DDNS: &str = "ddns";
+const DRY_RUN: &str = "--dry-run";
+const FIXTURE: &str = "--fixture";
+const INTERVAL: &str = "--interval";
+const IPV4: &str = "--ipv4";
+const IPV6: &str = "--ipv6";
+const ISSUE: &str = "issue";
+const LISTEN: &str = "--listen";
+const POLICY: &str = "--policy";
+const RECORD: &str = "--record";
+const RENEW: &str = "renew";
+const REPLAY: &str = "--replay";
+const RFC2136: &str = "rfc2136";
+const SERVE: &str = "serve";
+const SOURCE: &str = "--source";
+const TSIG_KEYS: &str = "--tsig-keys";
+const YES: &str = "--yes";
+const WITH_VALUE: &[&str] = &[
+    ACME_CA,
+    ACME_DIRECTORY,
+    ACME_EMAIL,
+    ACME_NAMESERVERS,
+    CERT_DIR,
+    FIXTURE,
+    INTERVAL,
+    LISTEN,
+    POLICY,
+    RECORD,
+    REPLAY,
+    SOURCE,
+    TSIG_KEYS,
+];
+
+/// Options and script file given on the command line
+#[derive(Debug, Default, PartialEq)]
+pub struct Arguments {
+    /// Root certificate trusted for the acme directory
+    pub acme_ca: Option<PathBuf>,
+    pub acme_directory: Option<String>,
+    pub acme_email: Option<String>,
+    /// Nameservers polled for the challenges of `cert`
+    pub acme_nameservers: Option<String>,
+    /// Agree to the terms of service of the acme server, needed to register the account of `cert`
+    pub agree_tos: bool,
+    /// Certificate to issue or renew, `transipctl cert issue|renew`, instead of a script
+    pub cert: Option<CertCommand>,
+    pub cert_dir: Option<PathBuf>,
+    /// Hook certbot runs, `transipctl certbot auth|cleanup`, instead of a script
+    pub certbot: Option<Hook>,
+    /// Keep ddns running, updating every interval
+    pub daemon: bool,
+    /// Domain and name ddns keeps up to date, `transipctl ddns <domain> <name>`, instead of a script
+    pub ddns: Option<Vec<String>>,
+    pub dry_run: bool,
+    pub fixture: Option<PathBuf>,
+    /// Seconds between ddns updates
+    pub interval: Option<u64>,
+    pub ipv4: bool,
+    pub ipv6: bool,
+    /// Address `serve rfc2136` listens on
+    pub listen: Option<SocketAddr>,
+    pub policy: Option<PathBuf>,
+    pub record: Option<PathBuf>,
+    pub replay: Option<PathBuf>,
+    /// Serve rfc 2136 updates, `transipctl serve rfc2136`, instead of a script
+    pub rfc2136: bool,
+    pub script: Option<PathBuf>,
+    /// Where ddns finds the public address
+    pub source: Option<Source>,
+    pub tsig_keys: Option<PathBuf>,
+    pub yes: bool,
+}
+
+impl Arguments {
+    pub fn from_env() -> Result<Self> {
+        Self::parse(std::env::args().skip(1))
+    }
+
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self> {
+        let mut arguments = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let (option, value) = match arg.split_once('=') {
+                Some((option, value)) if WITH_VALUE.contains(&option) => {
+                    (option, Some(value.to_owned()))
+                }
+                _ if WITH_VALUE.contains(&arg.as_str()) => (
+                    arg.as_str(),
+                    Some(
+                        args.next()
+                            .ok_or_else(|| Error::Argument(format!("value missing after {arg}")))?,
+                    ),
+                ),
+                _ => (arg.as_str(), None),
+            };
+            match (option, value) {
+                (ACME_CA, Some(value)) => arguments.acme_ca = Some(PathBuf::from(value)),
+                (ACME_DIRECTORY, Some(value)) => arguments.acme_directory = Some(value),
+                (ACME_EMAIL, Some(value)) => arguments.acme_email = Some(value),
+                (ACME_NAMESERVERS, Some(value)) => arguments.acme_nameservers = Some(value),
+                (CERT_DIR, Some(value)) => arguments.cert_dir = Some(PathBuf::from(value)),
+                (FIXTURE, Some(value)) => arguments.fixture = Some(PathBuf::from(value)),
+                (INTERVAL, Some(value)) => {
+                    arguments.interval = Some(value.parse().map_err(|_| {
+                        Error::Argument(format!("{INTERVAL} expects a number of seconds"))
+                    })?)
+                }
+                (LISTEN, Some(value)) => {
+                    arguments.listen = Some(value.parse().map_err(|_| {
+                        Error::Argument(format!("{LISTEN} expects an address like 127.0.0.1:5353"))
+                    })?)
+                }
+                (POLICY, Some(value)) => arguments.policy = Some(PathBuf::from(value)),
+                (RECORD, Some(value)) => arguments.record = Some(PathBuf::from(value)),
+                (REPLAY, Some(value)) => arguments.replay = Some(PathBuf::from(value)),
+                (SOURCE, Some(value)) => arguments.source = Some(value.parse()?),
+                (TSIG_KEYS, Some(value)) => arguments.tsig_keys = Some(PathBuf::from(value)),
+                _ if arg == AGREE_TOS => arguments.agree_tos = true,
+                _ if arg == DAEMON => arguments.daemon = true,
+                _ if arg == DRY_RUN => arguments.dry_run = true,
+                _ if arg == IPV4 => arguments.ipv4 = true,
+                _ if arg == IPV6 => arguments.ipv6 = true,
+                _ if arg == YES => arguments.yes = true,
+                _ if arg.starts_with("--") => {
+                    return Err(Error::Argument(format!("unknown option {arg}")));
+                }
+                _ if matches!(arguments.cert, Some(CertCommand::Issue(_))) => {
+                    if let Some(CertCommand::Issue(names)) = arguments.cert.as_mut() {
+                        names.push(arg.trim_end_matches('.').to_lowercase());
+                    }
+                }
+                _ if arguments.ddns.is_some() => {
+                    if let Some(ddns) = arguments.ddns.as_mut() {
+                        ddns.push(arg);
+                    }
+                }
+                _ if arguments.certbot.is_some()
+                    || arguments.rfc2136
+                    || arguments.cert.is_some() =>
+                {
+                    return Err(Error::Argument(format!(
+                        "{} does not take a script, got {arg}",
+                        arguments.source()
+                    )));
+                }
+                _ if arg == CERTBOT && arguments.script.is_none() => {
+                    let hook = args.next().ok_or_else(|| {
+                        Error::Argument(format!("auth or cleanup missing after {arg}"))
+                    })?;
+                    arguments.certbot = Some(hook.parse()?);
+                }
+                _ if arg == CERT && arguments.script.is_none() => match args.next().as_deref() {
+                    Some(ISSUE) => arguments.cert = Some(CertCommand::Issue(vec![])),
+                    Some(RENEW) => arguments.cert = Some(CertCommand::Renew),
+                    _ => {
+                        return Err(Error::Argument(format!(
+                            "{ISSUE} or {RENEW} missing after {arg}"
+                        )));
+                    }
+                },
+                _ if arg == DDNS && arguments.script.is_none() => arguments.ddns = Some(vec![]),
+                _ if arg == SERVE && arguments.script.is_none() => match args.next() {
+                    Some(service) if service == RFC2136 => arguments.rfc2136 = true,
+                    _ => {
+                        return Err(Error::Argument(format!("{RFC2136} missing after {arg}")));
+                    }
+                },
+                _ => arguments.script = Some(PathBuf::from(arg)),
+            }
+        }
+        if arguments.replay.is_some() && (arguments.fixture.is_some() || arguments.record.is_some())
+        {
+            return Err(Error::Argument(format!(
+                "{REPLAY} can not be combined with {FIXTURE} or {RECORD}"
+            )));
+        }
+        if arguments.rfc2136 && arguments.tsig_keys.is_none() {
+            return Err(Error::Argument(format!(
+                "{SERVE} {RFC2136} needs {TSIG_KEYS}"
+            )));
+        }
+        if arguments.ddns.as_ref().is_some_and(|ddns| ddns.len() != 2) {
+            return Err(Error::Argument(format!("{DDNS} needs a domain and a name")));
+        }
+        if arguments.interval.is_some() && !arguments.daemon {
+            return Err(Error::Argument(format!("{INTERVAL} needs {DAEMON}")));
+        }
+        if arguments.cert == Some(CertCommand::Issue(vec![])) {
+            return Err(Error::Argument(format!(
+                "{CERT} {ISSUE} needs one or more names"
+            )));
+        }
+        if arguments.cert.is_some() && !arguments.agree_tos {
+            return Err(Error::Argument(format!(
+                "{CERT} needs {AGREE_TOS}, to agree to the terms of service of the acme server"
+            )));
+        }
+        if arguments.cert.is_some() && arguments.dry_run {
+            return Err(Error::Argument(format!(
+                "{DRY_RUN} can not be combined with {CERT}"
+            )));
+        }
+        Ok(arguments)
+    }
+
+    /// Destructive commands are confirmed on the tty, unless --yes is given
+    pub fn confirm(&self) -> bool {
+        self.confirm_on(std::io::stdin().is_terminal())
+    }
+
+    /// Destructive commands are confirmed if commands are typed on a terminal
+    fn confirm_on(&self, terminal: bool) -> bool {
+        terminal
+            && self.script.is_none()
+            && self.certbot.is_none()
+            && self.cert.is_none()
+            && self.ddns.is_none()
+            && !self.rfc2136
+            && !self.yes
+    }
+
+    /// Account the commands are executed on, recorded in the audit log
+    pub fn account(&self) -> String {
+        if self.replay.is_some() {
+            "replay".to_owned()
+        } else if self.fixture.is_some() {
+            "fixture".to_owned()
+        } else {
+            std::env::var("TRANSIP_API_USERNAME").unwrap_or_else(|_| "unknown".to_owned())
+        }
+    }
+
+    /// Script file name, certbot hook, cert command, server or tty, used for logging
+    pub fn source(&self) -> String {
+        match (self.certbot, self.script.as_ref()) {
+            (Some(hook), _) => format!("{CERTBOT} {hook}"),
+            _ if self.rfc2136 => format!("{SERVE} {RFC2136}"),
+            _ if self.cert == Some(CertCommand::Renew) => format!("{CERT} {RENEW}"),
+            _ if self.cert.is_some() => format!("{CERT} {ISSUE}"),
+            _ if self.ddns.is_some() => DDNS.to_owned(),
+            (None, Some(script)) => script.display().to_string(),
+            (None, None) => "tty".to_owned(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use super::Arguments;
+    use crate::{cert::CertCommand, certbot::Hook, ddns::Source};
+
+    fn parse(args: &[&str]) -> crate::Result<Arguments> {
+        Arguments::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn script_with_options() {
+        let arguments = parse(&["--dry-run", "--fixture", "state.json", "script.transip"]).unwrap();
+        assert_eq!(
+            arguments,
+            Arguments {
+                dry_run: true,
+                fixture: Some(PathBuf::from("state.json")),
+                script: Some(PathBuf::from("script.transip")),
+                ..Default::default()
+            }
+        );
+        assert_eq!(arguments.source(), "script.transip");
+    }
+
+    #[test]
+    fn tty() {
+        let arguments = parse(&["--fixture=state.json"]).unwrap();
+        assert_eq!(arguments.fixture, Some(PathBuf::from("state.json")));
+        assert_eq!(arguments.source(), "tty");
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(parse(&["--fixture"]).is_err());
+        assert!(parse(&["--unknown"]).is_err());
+        assert!(parse(&["--replay", "cassette", "--record=other"]).is_err());
+    }
+
+    #[test]
+    fn confirm() {
+        assert!(parse(&[]).unwrap().confirm_on(true));
+        assert!(!parse(&[]).unwrap().confirm_on(false));
+        assert!(!parse(&["--yes"]).unwrap().confirm_on(true));
+        assert!(!parse(&["script.transip"]).unwrap().confirm_on(true));
+    }
+
+    #[test]
+    fn certbot() {
+        let arguments = parse(&["--policy=certbot.toml", "certbot", "auth"]).unwrap();
+        assert_eq!(arguments.certbot, Some(Hook::Auth));
+        assert_eq!(arguments.source(), "certbot auth");
+        assert!(!arguments.confirm_on(true));
+        assert!(parse(&["certbot"]).is_err());
+        assert!(parse(&["certbot", "renew"]).is_err());
+        assert!(parse(&["certbot", "cleanup", "script.transip"]).is_err());
+        assert_eq!(
+            parse(&["script.transip", "certbot"]).unwrap().script,
+            Some(PathBuf::from("certbot"))
+        );
+    }
+
+    #[test]
+    fn serve() {
+        let arguments = parse(&[
+            "--tsig-keys",
+            "keys.toml",
+            "--listen=127.0.0.1:5300",
+            "serve",
+            "rfc2136",
+        ])
+        .unwrap();
+        assert!(arguments.rfc2136);
+        assert_eq!(arguments.listen, Some("127.0.0.1:5300".parse().unwrap()));
+        assert_eq!(arguments.tsig_keys, Some(PathBuf::from("keys.toml")));
+        assert_eq!(arguments.source(), "serve rfc2136");
+        assert!(!arguments.confirm_on(true));
+        assert!(parse(&["serve", "rfc2136"]).is_err());
+        assert!(parse(&["--tsig-keys=keys.toml", "serve", "http"]).is_err());
+        assert!(parse(&["--tsig-keys=keys.toml", "serve", "rfc2136", "script"]).is_err());
+        assert!(parse(&["--listen", "localhost", "script.transip"]).is_err());
+    }
+
+    #[test]
+    fn cert() {
+        let arguments = parse(&[
+            "--acme-directory",
+            "https://localhost:14000/dir",
+            "--cert-dir=certs",
+            "--agree-tos",
+            "cert",
+            "issue",
+            "Example.nl",
+            "*.example.nl",
+        ])
+        .unwrap();
+        assert_eq!(
+            arguments.cert,
+            Some(CertCommand::Issue(vec![
+                "example.nl".to_owned(),
+                "*.example.nl".to_owned()
+            ]))
+        );
+        assert_eq!(
+            arguments.acme_directory.as_deref(),
+            Some("https://localhost:14000/dir")
+        );
+        assert_eq!(arguments.cert_dir, Some(PathBuf::from("certs")));
+        assert_eq!(arguments.source(), "cert issue");
+        assert!(arguments.agree_tos);
+        assert!(!arguments.confirm_on(true));
+        assert_eq!(
+            parse(&["--agree-tos", "cert", "renew"]).unwrap().source(),
+            "cert renew"
+        );
+        assert!(parse(&["cert", "renew"]).is_err());
+        assert!(parse(&["--agree-tos", "cert", "issue"]).is_err());
+        assert!(parse(&["--agree-tos", "cert", "revoke"]).is_err());
+        assert!(parse(&["--agree-tos", "cert", "renew", "example.nl"]).is_err());
+        assert!(parse(&["--agree-tos", "--dry-run", "cert", "renew"]).is_err());
+    }
+
+    #[test]
+    fn ddns() {
+        let arguments = parse(&[
+            "--ipv6",
+            "--source=cmd:ip -6 addr show dev eth0",
+            "--daemon",
+            "--interval",
+            "60",
+            "ddns",
+            "example.nl",
+            "home",
+        ])
+        .unwrap();
+        assert_eq!(
+            arguments.ddns,
+            Some(vec!["example.nl".to_owned(), "home".to_owned()])
+        );
+        assert_eq!(
+            arguments.source,
+            Some(Source::Command("ip -6 addr show dev eth0".to_owned()))
+        );
+        assert!(arguments.ipv6 && !arguments.ipv4 && arguments.daemon);
+        assert_eq!(arguments.interval, Some(60));
+        assert_eq!(arguments.source(), "ddns");
+        assert!(!arguments.confirm_on(true));
+        assert!(parse(&["ddns", "example.nl"]).is_err());
+        assert!(parse(&["ddns", "example.nl", "home", "www"]).is_err());
+        assert!(parse(&["--interval=often", "ddns", "example.nl", "home"]).is_err());
+        assert!(parse(&["--interval=60", "ddns", "example.nl", "home"]).is_err());
+    }
+
+    #[test]
+    fn options_with_value() {
+        let arguments = parse(&["--policy", "certbot.toml", "script.transip"]).unwrap();
+        assert_eq!(arguments.policy, Some(PathBuf::from("certbot.toml")));
+        let arguments = parse(&["--record=cassette", "script.transip"]).unwrap();
+        assert_eq!(arguments.record, Some(PathBuf::from("cassette")));
+        let arguments = parse(&["--replay", "cassette"]).unwrap();
+        assert_eq!(arguments.replay, Some(PathBuf::from("cassette")));
+    }
+}
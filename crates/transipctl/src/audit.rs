@@ -0,0 +1,393 @@
+use std::{
+    fs::OpenOptions,
+    io::{BufRead, BufReader, ErrorKind, Write},
+    path::PathBuf,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use time::{
+    Date, OffsetDateTime, format_description::well_known::Rfc3339, macros::format_description,
+};
+use transip_execute::{CommandOutput, SubCommand};
+
+use crate::{
+    Result,
+    error::{Error, ErrorExt},
+};
+
+const FILE_NAME: &str = "audit.jsonl";
+
+/// Result of a mutating command
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Outcome {
+    Ok,
+    Failed,
+    /// Not executed because the policy does not allow it
+    Refused,
+    /// Not executed because the user did not confirm it
+    Cancelled,
+}
+
+/// One line of the audit log
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Record {
+    /// Rfc 3339 time in utc
+    pub timestamp: String,
+    pub user: String,
+    pub account: String,
+    /// Script file name or tty
+    pub source: String,
+    /// Line in the source, none for the hooks and servers of certbot, cert, ddns and rfc2136
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+    pub command: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub before: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub after: Option<Value>,
+    /// Dns entries removed by a dns command, the other entries of the zone are not recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted: Option<Value>,
+    /// Dns entries added by a dns command
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inserted: Option<Value>,
+    pub outcome: Outcome,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Append-only json lines file with a record for every mutating command
+pub struct Audit {
+    path: PathBuf,
+    user: String,
+    account: String,
+    source: String,
+}
+
+fn user() -> String {
+    ["USER", "USERNAME"]
+        .iter()
+        .find_map(|name| std::env::var(name).ok())
+        .unwrap_or_else(|| "unknown".to_owned())
+}
+
+fn value<T: Serialize>(value: &T) -> Option<Value> {
+    serde_json::to_value(value).ok()
+}
+
+/// Change a command made, as far as the output tells
+#[derive(Default)]
+struct Change {
+    before: Option<Value>,
+    after: Option<Value>,
+    deleted: Option<Value>,
+    inserted: Option<Value>,
+}
+
+impl Change {
+    fn states(before: Option<Value>, after: Option<Value>) -> Self {
+        Self {
+            before,
+            after,
+            ..Default::default()
+        }
+    }
+}
+
+fn change(output: &CommandOutput) -> Change {
+    match output {
+        CommandOutput::DnsChange(change) => Change {
+            deleted: value(&change.deleted),
+            inserted: value(&change.inserted),
+            ..Default::default()
+        },
+        CommandOutput::MailboxDeleted(deleted) => Change::states(value(&deleted.deleted), None),
+        CommandOutput::MailForwardDeleted(deleted) => Change::states(value(&deleted.deleted), None),
+        CommandOutput::NameserversChanged(changed) => {
+            Change::states(value(&changed.before), value(&changed.after))
+        }
+        CommandOutput::DnsSecChanged(changed) => {
+            Change::states(value(&changed.before), value(&changed.after))
+        }
+        CommandOutput::DnsSecDeleted(deleted) => {
+            Change::states(value(&deleted.deleted), Some(Value::Array(vec![])))
+        }
+        CommandOutput::Mailbox(mailbox) => Change::states(None, value(mailbox)),
+        CommandOutput::MailForward(forward) => Change::states(None, value(forward)),
+        CommandOutput::Vps(vps) => Change::states(None, value(vps)),
+        _ => Change::default(),
+    }
+}
+
+/// Start of the day or exact time given with `audit show --since`
+fn since(since: &str) -> Result<OffsetDateTime> {
+    OffsetDateTime::parse(since, &Rfc3339)
+        .or_else(|_| {
+            Date::parse(since, format_description!("[year]-[month]-[day]"))
+                .map(|date| date.midnight().assume_utc())
+        })
+        .map_err(|_| {
+            Error::Argument(format!(
+                "invalid --since {since}, expected a date like 2024-01-31 or an rfc 3339 time"
+            ))
+        })
+}
+
+impl Audit {
+    pub fn new(dir: PathBuf, account: String, source: String) -> Self {
+        Self {
+            path: dir.join(FILE_NAME),
+            user: user(),
+            account,
+            source,
+        }
+    }
+
+    fn record(
+        &self,
+        line: Option<usize>,
+        command: &SubCommand,
+        outcome: Outcome,
+        error: Option<String>,
+        change: Change,
+    ) -> Result<()> {
+        let Some(command) = command.canonical() else {
+            return Ok(());
+        };
+        let record = Record {
+            timestamp: OffsetDateTime::now_utc()
+                .format(&Rfc3339)
+                .unwrap_or_default(),
+            user: self.user.clone(),
+            account: self.account.clone(),
+            source: self.source.clone(),
+            line: line.map(|line| line + 1),
+            command,
+            before: change.before,
+            after: change.after,
+            deleted: change.deleted,
+            inserted: change.inserted,
+            outcome,
+            error,
+        };
+        let mut buffer = serde_json::to_vec(&record)?;
+        buffer.push(b'\n');
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(&buffer))
+            .err_into()
+    }
+
+    /// Records the result of an executed command, nothing is recorded for dry runs
+    pub fn executed(
+        &self,
+        line: Option<usize>,
+        command: &SubCommand,
+        output: &transip_execute::Result<CommandOutput>,
+    ) -> Result<()> {
+        match output {
+            Ok(CommandOutput::DryRun(_)) => Ok(()),
+            Ok(output) => self.record(line, command, Outcome::Ok, None, change(output)),
+            Err(error) => self.record(
+                line,
+                command,
+                Outcome::Failed,
+                Some(error.to_string()),
+                Change::default(),
+            ),
+        }
+    }
+
+    /// Records a command that was not executed
+    pub fn skipped(
+        &self,
+        line: Option<usize>,
+        command: &SubCommand,
+        outcome: Outcome,
+        reason: Option<String>,
+    ) -> Result<()> {
+        self.record(line, command, outcome, reason, Change::default())
+    }
+
+    /// Records written at or after since, all records without since
+    pub fn show(&self, since: Option<&str>) -> Result<Vec<Record>> {
+        let since = since.map(self::since).transpose()?;
+        let file = match std::fs::File::open(&self.path) {
+            Ok(file) => file,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(error) => return Err(error.into()),
+        };
+        let mut records = vec![];
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            match serde_json::from_str::<Record>(&line) {
+                Ok(record) => {
+                    let written = OffsetDateTime::parse(&record.timestamp, &Rfc3339).ok();
+                    if since.is_none_or(|since| written.is_some_and(|written| written >= since)) {
+                        records.push(record);
+                    }
+                }
+                Err(error) => tracing::warn!("Skipping invalid audit record: {error}"),
+            }
+        }
+        Ok(records)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use serde_json::json;
+    use transip_execute::{Client, Fixture, Memory, TransipCommand};
+    use transip_test_support::{TempDir, domain, fixture};
+
+    use super::{Audit, Outcome};
+
+    fn audit(dir: &TempDir) -> Audit {
+        Audit::new(
+            dir.to_path_buf(),
+            "fixture".to_owned(),
+            "test.transip".to_owned(),
+        )
+    }
+
+    fn client() -> Client {
+        let mut domain = domain("example.nl");
+        domain["nameservers"] =
+            json!([{"hostname": "ns0.transip.net", "ipv4": null, "ipv6": null}]);
+        Client::new(Memory::from(fixture::<Fixture>(json!({
+            "dns": {
+                "example.nl": [{"name": "www", "expire": 300, "type": "A", "content": "1.1.1.1"}],
+            },
+            "domains": [domain],
+        }))))
+    }
+
+    fn execute(audit: &Audit, client: &mut Client, line: usize, text: &str) {
+        let command = text.parse::<TransipCommand>().unwrap().command;
+        let output = client.execute_output(&command);
+        audit.executed(Some(line), &command, &output).unwrap();
+    }
+
+    #[test]
+    fn mutations_recorded() {
+        let dir = TempDir::new("transipctl-audit-mutations");
+        let audit = audit(&dir);
+        let mut client = client();
+        execute(&audit, &mut client, 0, "dns list example.nl");
+        execute(
+            &audit,
+            &mut client,
+            1,
+            "dns delete example.nl www 300 A 1.1.1.1",
+        );
+        execute(&audit, &mut client, 2, "vps stop unknown");
+        client.set_dry_run(true);
+        execute(
+            &audit,
+            &mut client,
+            3,
+            "dns insert example.nl www 300 A 2.2.2.2",
+        );
+        let command = "email-box insert example.nl info secret 100"
+            .parse::<TransipCommand>()
+            .unwrap()
+            .command;
+        audit
+            .skipped(
+                Some(4),
+                &command,
+                Outcome::Refused,
+                Some("not allowed".to_owned()),
+            )
+            .unwrap();
+
+        let records = audit.show(None).unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].line, Some(2));
+        assert_eq!(
+            records[0].command,
+            "dns delete example.nl www 300 A 1.1.1.1"
+        );
+        assert_eq!(records[0].outcome, Outcome::Ok);
+        assert_eq!(
+            records[0].deleted.as_ref().unwrap()[0]["content"],
+            "1.1.1.1"
+        );
+        assert_eq!(records[0].inserted, Some(json!([])));
+        assert_eq!(records[0].before, None);
+        assert_eq!(records[1].outcome, Outcome::Failed);
+        assert!(records[1].error.is_some());
+        assert_eq!(records[2].outcome, Outcome::Refused);
+        assert!(!records[2].command.contains("secret"));
+        assert_eq!(records[2].account, "fixture");
+        assert_eq!(records[2].source, "test.transip");
+    }
+
+    #[test]
+    fn states_recorded() {
+        let dir = TempDir::new("transipctl-audit-states");
+        let audit = audit(&dir);
+        let mut client = client();
+        execute(
+            &audit,
+            &mut client,
+            0,
+            "domain nameservers-set example.nl ns.example.net",
+        );
+        let key = dir.join("Kexample.nl.+013+12345.key");
+        std::fs::write(
+            &key,
+            "example.nl. IN DNSKEY 257 3 13 mdsswUyr3DPW132mOi8V9xESWE8jTo0dxCjjnopKl+GqJxpVXckHAeF+KkxLbxILfDLUT0rAK9iUzy1L53eKGQ==\n",
+        )
+        .unwrap();
+        execute(
+            &audit,
+            &mut client,
+            1,
+            &format!("domain dnssec set example.nl {}", key.display()),
+        );
+        execute(&audit, &mut client, 2, "domain dnssec delete example.nl");
+
+        let records = audit.show(None).unwrap();
+        assert_eq!(
+            records[0].before.as_ref().unwrap()[0]["hostname"],
+            "ns0.transip.net"
+        );
+        assert_eq!(
+            records[0].after.as_ref().unwrap()[0]["hostname"],
+            "ns.example.net"
+        );
+        assert_eq!(records[1].before, Some(json!([])));
+        assert_eq!(records[1].after.as_ref().unwrap()[0]["flags"], 257);
+        assert_eq!(records[2].before, records[1].after);
+        assert_eq!(records[2].after, Some(json!([])));
+    }
+
+    #[test]
+    fn since() {
+        let dir = TempDir::new("transipctl-audit-since");
+        let audit = audit(&dir);
+        let mut client = client();
+        execute(&audit, &mut client, 0, "vps start vps1");
+        assert_eq!(audit.show(Some("2000-01-01")).unwrap().len(), 1);
+        assert_eq!(
+            audit.show(Some("2000-01-01T12:00:00+02:00")).unwrap().len(),
+            1
+        );
+        assert!(audit.show(Some("2999-12-31")).unwrap().is_empty());
+        assert!(audit.show(Some("yesterday")).is_err());
+        assert!(
+            Audit::new(PathBuf::from("/nonexistent"), String::new(), String::new())
+                .show(None)
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
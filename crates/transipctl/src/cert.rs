@@ -0,0 +1,594 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use rcgen::{CertificateParams, KeyPair};
+use time::{Duration, OffsetDateTime, PrimitiveDateTime, macros::format_description};
+use transip_execute::Client;
+
+use crate::{
+    Result,
+    acme::{self, Account, Challenge, Status},
+    audit::Audit,
+    error::{Error, ErrorExt},
+    execute,
+    policy::Policy,
+};
+
+const ACCOUNT_KEY: &str = "account.pem";
+const FULLCHAIN: &str = "fullchain.pem";
+const NAMES: &str = "names.json";
+const PRIVKEY: &str = "privkey.pem";
+/// Certificates that expire within this many days are renewed
+const RENEW_DAYS: i64 = 30;
+
+/// `transipctl cert issue <names...>` or `transipctl cert renew`
+#[derive(Clone, Debug, PartialEq)]
+pub enum CertCommand {
+    Issue(Vec<String>),
+    Renew,
+}
+
+/// Acme server and certificate files, given on the command line
+#[derive(Debug)]
+pub struct Settings {
+    pub directory: String,
+    /// Root certificate of the directory, for a test server like Pebble
+    pub ca: Option<PathBuf>,
+    pub email: Option<String>,
+    /// Nameservers acme-validation-check polls, the authoritative ones if not given
+    pub nameservers: Option<String>,
+    /// Holds the account key and a directory per certificate
+    pub dir: PathBuf,
+    /// Agreed to the terms of service of the acme server with --agree-tos
+    pub agree_tos: bool,
+}
+
+/// Challenge of an authorization that is not valid yet
+struct Pending {
+    authorization: String,
+    domain: String,
+    value: String,
+    challenge: Challenge,
+}
+
+/// Issues certificates with dns-01 challenges, set with the policy and audit log of the other commands
+pub struct Cert<'a> {
+    client: &'a mut Client,
+    policy: Option<&'a Policy>,
+    audit: &'a Audit,
+    settings: Settings,
+}
+
+impl Cert<'_> {
+    pub fn new<'a>(
+        client: &'a mut Client,
+        policy: Option<&'a Policy>,
+        audit: &'a Audit,
+        settings: Settings,
+    ) -> Cert<'a> {
+        Cert {
+            client,
+            policy,
+            audit,
+            settings,
+        }
+    }
+
+    fn execute(&mut self, line: &str) -> Result<()> {
+        tracing::info!("Cert executes {line}");
+        execute::line(self.client, self.policy, self.audit, line).map(|_| ())
+    }
+
+    /// Account of the key in the certificate directory, a new key is created the first time
+    fn account(&self) -> Result<Account> {
+        let path = self.settings.dir.join(ACCOUNT_KEY);
+        let key = match std::fs::read_to_string(&path) {
+            Ok(key) => key,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                let key = KeyPair::generate()?.serialize_pem();
+                write_private(&path, &key)?;
+                key
+            }
+            Err(error) => return Err(error.into()),
+        };
+        Account::new(
+            &self.settings.directory,
+            self.settings.ca.as_deref(),
+            &key,
+            self.settings.email.as_deref(),
+            self.settings.agree_tos,
+        )
+    }
+
+    /// Sets the challenges, waits until they propagated and the server validated them
+    fn validate(&mut self, account: &mut Account, pending: &[Pending]) -> Result<()> {
+        for pending in pending {
+            self.execute(&format!(
+                "dns acme-validation-set {} {} --append --follow-cname",
+                pending.domain, pending.value
+            ))?;
+        }
+        for pending in pending {
+            let nameservers = self
+                .settings
+                .nameservers
+                .as_ref()
+                .map(|nameservers| format!(" --nameservers {nameservers}"))
+                .unwrap_or_default();
+            self.execute(&format!(
+                "dns acme-validation-check {} {} --follow-cname{nameservers}",
+                pending.domain, pending.value
+            ))?;
+        }
+        for pending in pending {
+            account.respond(&pending.challenge)?;
+        }
+        pending
+            .iter()
+            .try_for_each(|pending| account.validated(&pending.authorization))
+    }
+
+    /// Writes the key, the full chain and the names to the directory of the first name
+    fn save(&self, names: &[String], key: &KeyPair, chain: &str) -> Result<PathBuf> {
+        let dir = self
+            .settings
+            .dir
+            .join(first(names)?.trim_start_matches("*."));
+        std::fs::create_dir_all(&dir)?;
+        write_private(&dir.join(PRIVKEY), &key.serialize_pem())?;
+        std::fs::write(dir.join(FULLCHAIN), chain)?;
+        std::fs::write(dir.join(NAMES), serde_json::to_vec_pretty(names)?)?;
+        Ok(dir)
+    }
+
+    /// Issues a certificate for names, returns the directory with its files
+    ///
+    /// The challenges are removed afterwards, also when the order fails.
+    pub fn issue(&mut self, names: &[String]) -> Result<PathBuf> {
+        first(names)?;
+        std::fs::create_dir_all(&self.settings.dir)?;
+        let mut account = self.account()?;
+        let (url, order) = account.new_order(names)?;
+        let mut pending = vec![];
+        for authorization_url in &order.authorizations {
+            let authorization = account.authorization(authorization_url)?;
+            if authorization.status == Status::Valid {
+                continue;
+            }
+            let domain = authorization.identifier.value;
+            let challenge = authorization
+                .challenges
+                .into_iter()
+                .find(|challenge| challenge.kind == acme::DNS_01)
+                .ok_or_else(|| {
+                    Error::Acme(format!("no {} challenge for {domain}", acme::DNS_01))
+                })?;
+            pending.push(Pending {
+                authorization: authorization_url.clone(),
+                value: account.dns_value(&challenge.token),
+                domain,
+                challenge,
+            });
+        }
+        let result = self.validate(&mut account, &pending).and_then(|_| {
+            let key = KeyPair::generate()?;
+            let csr = CertificateParams::new(names.to_vec())?.serialize_request(&key)?;
+            let chain = account.finalize(&url, &order, csr.der())?;
+            self.save(names, &key, &chain)
+        });
+        for pending in &pending {
+            let line = format!(
+                "dns acme-validation-delete {} {} --follow-cname",
+                pending.domain, pending.value
+            );
+            if let Err(error) = self.execute(&line) {
+                tracing::error!("Failed to remove challenge of {}: {error}", pending.domain);
+            }
+        }
+        result
+    }
+
+    /// Renews the certificates that expire within RENEW_DAYS days, continues after a failure
+    pub fn renew(&mut self) -> Result<()> {
+        let limit = OffsetDateTime::now_utc() + Duration::days(RENEW_DAYS);
+        let mut failed = vec![];
+        for entry in std::fs::read_dir(&self.settings.dir)? {
+            let dir = entry?.path();
+            let Ok(names) = std::fs::read(dir.join(NAMES)) else {
+                continue;
+            };
+            let (name, names) = match names_of(&names) {
+                Ok(names) => names,
+                Err(error) => {
+                    tracing::error!("Invalid {} of {}: {error}", NAMES, dir.display());
+                    eprintln!("Error reading {}: {error}", dir.join(NAMES).display());
+                    failed.push(dir.display().to_string());
+                    continue;
+                }
+            };
+            let expires = std::fs::read_to_string(dir.join(FULLCHAIN))
+                .ok()
+                .and_then(|pem| not_after(&pem));
+            match expires {
+                Some(expires) if expires > limit => {
+                    tracing::info!("Certificate for {name} valid until {expires}");
+                }
+                _ => match self.issue(&names) {
+                    Ok(dir) => eprintln!("Renewed certificate in {}", dir.display()),
+                    Err(error) => {
+                        tracing::error!("Renewal of {name} failed: {error}");
+                        eprintln!("Error renewing {name}: {error}");
+                        failed.push(name);
+                    }
+                },
+            }
+        }
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Acme(format!(
+                "renewal failed for {}",
+                failed.join(", ")
+            )))
+        }
+    }
+
+    pub fn run(&mut self, command: &CertCommand) -> Result<()> {
+        match command {
+            CertCommand::Issue(names) => {
+                let dir = self.issue(names)?;
+                eprintln!("Certificate written to {}", dir.display());
+                Ok(())
+            }
+            CertCommand::Renew => self.renew(),
+        }
+    }
+}
+
+/// Name the directory of the certificate for names is named after, an error if there is none
+fn first(names: &[String]) -> Result<&str> {
+    match names.first() {
+        Some(name)
+            if names
+                .iter()
+                .all(|name| !name.trim_start_matches("*.").is_empty()) =>
+        {
+            Ok(name)
+        }
+        _ => Err(Error::Acme(
+            "certificate needs one or more names".to_owned(),
+        )),
+    }
+}
+
+/// First name and all names in the contents of a names file, written by an earlier issue or by hand
+fn names_of(json: &[u8]) -> Result<(String, Vec<String>)> {
+    let names = serde_json::from_slice::<Vec<String>>(json)?;
+    Ok((first(&names)?.to_owned(), names))
+}
+
+/// Writes a private key only the owner can read
+fn write_private(path: &Path, pem: &str) -> Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(pem.as_bytes()).err_into()
+}
+
+/// Tag and content of the der element at the start of data, and the data after it
+fn element(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = data.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (length, rest) = if first < 0x80 {
+        (usize::from(first), rest)
+    } else {
+        let count = usize::from(first & 0x7f);
+        if count > 4 || rest.len() < count {
+            return None;
+        }
+        let (bytes, rest) = rest.split_at(count);
+        let length = bytes
+            .iter()
+            .fold(0, |length, byte| length << 8 | usize::from(*byte));
+        (length, rest)
+    };
+    (rest.len() >= length).then(|| (tag, &rest[..length], &rest[length..]))
+}
+
+/// Expiry of the first certificate in pem, the end of the validity in its tbs certificate
+fn not_after(pem: &str) -> Option<OffsetDateTime> {
+    let base64 = pem
+        .split("-----BEGIN CERTIFICATE-----")
+        .nth(1)?
+        .split("-----END CERTIFICATE-----")
+        .next()?;
+    let der = STANDARD
+        .decode(base64.split_whitespace().collect::<String>())
+        .ok()?;
+    let (_, certificate, _) = element(&der)?;
+    let (_, tbs, _) = element(certificate)?;
+    let (tag, _, mut rest) = element(tbs)?;
+    // the optional version is followed by serial, signature and issuer
+    for _ in 0..if tag == 0xa0 { 3 } else { 2 } {
+        rest = element(rest)?.2;
+    }
+    let (_, validity, _) = element(rest)?;
+    let (_, _, validity) = element(validity)?;
+    let (tag, time, _) = element(validity)?;
+    let time = std::str::from_utf8(time).ok()?;
+    let time = match tag {
+        0x17 if time < "50" => format!("20{time}"),
+        0x17 => format!("19{time}"),
+        0x18 => time.to_owned(),
+        _ => return None,
+    };
+    PrimitiveDateTime::parse(
+        &time,
+        format_description!("[year][month][day][hour][minute][second]Z"),
+    )
+    .ok()
+    .map(PrimitiveDateTime::assume_utc)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        net::{SocketAddr, UdpSocket},
+        sync::{Arc, Mutex},
+    };
+
+    use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+    use rcgen::{CertificateParams, KeyPair, date_time_ymd};
+    use serde_json::{Value, json};
+    use sha2::{Digest, Sha256};
+    use tiny_http::{Header, Method, Response, Server};
+    use transip_execute::{
+        Client, CommandOutput, Fixture, Memory, TransipCommand,
+        dns::{self, RData},
+    };
+    use transip_test_support::{TempDir, domain, fixture};
+
+    use super::{Cert, FULLCHAIN, NAMES, PRIVKEY, Settings, names_of, not_after};
+    use crate::audit::Audit;
+
+    fn certificate(year: i32) -> String {
+        let mut params = CertificateParams::new(vec!["example.nl".to_owned()]).unwrap();
+        params.not_after = date_time_ymd(year, 3, 1);
+        params
+            .self_signed(&KeyPair::generate().unwrap())
+            .unwrap()
+            .pem()
+    }
+
+    #[test]
+    fn expiry() {
+        assert_eq!(
+            not_after(&certificate(2049)),
+            Some(date_time_ymd(2049, 3, 1))
+        );
+        assert_eq!(
+            not_after(&certificate(2051)),
+            Some(date_time_ymd(2051, 3, 1))
+        );
+        assert_eq!(not_after("no certificate"), None);
+    }
+
+    #[test]
+    fn names_file() {
+        assert_eq!(
+            names_of(br#"["*.example.nl", "example.nl"]"#).unwrap().0,
+            "*.example.nl"
+        );
+        assert!(names_of(b"[]").is_err());
+        assert!(names_of(br#"["example.nl", "*."]"#).is_err());
+        assert!(names_of(b"example.nl").is_err());
+    }
+
+    fn decode(jws: &Value, field: &str) -> Value {
+        let bytes = URL_SAFE_NO_PAD
+            .decode(jws[field].as_str().unwrap())
+            .unwrap();
+        serde_json::from_slice(&bytes).unwrap_or(Value::Null)
+    }
+
+    /// Acme server that validates every challenge, it adds the txt values it expects to values
+    fn acme_server(values: Arc<Mutex<Vec<String>>>) -> String {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", server.server_addr().to_ip().unwrap());
+        let base = url.clone();
+        std::thread::spawn(move || {
+            let (mut thumbprint, mut domains, mut validated) = (String::new(), vec![], vec![]);
+            for mut request in server.incoming_requests() {
+                let (mut protected, mut payload) = (Value::Null, Value::Null);
+                if *request.method() == Method::Post {
+                    let jws = serde_json::from_reader::<_, Value>(request.as_reader()).unwrap();
+                    (protected, payload) = (decode(&jws, "protected"), decode(&jws, "payload"));
+                    assert_eq!(protected["url"], format!("{base}{}", request.url()));
+                }
+                let order = |status: &str, domains: &Vec<String>| {
+                    json!({
+                        "status": status,
+                        "authorizations": (0..domains.len())
+                            .map(|index| format!("{base}/authz/{index}"))
+                            .collect::<Vec<_>>(),
+                        "finalize": format!("{base}/finalize"),
+                        "certificate": format!("{base}/cert"),
+                    })
+                };
+                let path = request.url().to_owned();
+                let (status, body, location) = match path.as_str() {
+                    "/directory" => (
+                        200,
+                        json!({
+                            "newNonce": format!("{base}/nonce"),
+                            "newAccount": format!("{base}/account"),
+                            "newOrder": format!("{base}/order"),
+                        }),
+                        None,
+                    ),
+                    "/nonce" => (200, Value::Null, None),
+                    "/account" => {
+                        assert_eq!(payload["termsOfServiceAgreed"], true);
+                        let jwk = &protected["jwk"];
+                        let canonical = format!(
+                            r#"{{"crv":"P-256","kty":"EC","x":{},"y":{}}}"#,
+                            jwk["x"], jwk["y"]
+                        );
+                        thumbprint = URL_SAFE_NO_PAD.encode(Sha256::digest(canonical));
+                        (201, json!({"status": "valid"}), Some("/account/1"))
+                    }
+                    "/order" => {
+                        assert_eq!(protected["kid"], format!("{base}/account/1"));
+                        domains = payload["identifiers"]
+                            .as_array()
+                            .unwrap()
+                            .iter()
+                            .map(|identifier| {
+                                let name = identifier["value"].as_str().unwrap();
+                                name.trim_start_matches("*.").to_owned()
+                            })
+                            .collect();
+                        values
+                            .lock()
+                            .unwrap()
+                            .extend((0..domains.len()).map(|index| {
+                                let authorization = format!("token-{index}.{thumbprint}");
+                                URL_SAFE_NO_PAD.encode(Sha256::digest(authorization))
+                            }));
+                        (201, order("pending", &domains), Some("/order/1"))
+                    }
+                    "/finalize" => {
+                        assert!(payload["csr"].is_string());
+                        (200, order("processing", &domains), None)
+                    }
+                    "/order/1" => (200, order("valid", &domains), None),
+                    "/cert" => (200, json!(certificate(2099)), None),
+                    path => {
+                        let (kind, index) = path[1..].split_once('/').unwrap();
+                        let index = index.parse::<usize>().unwrap();
+                        if kind == "challenge" {
+                            validated.push(index);
+                        }
+                        let status = if validated.contains(&index) {
+                            "valid"
+                        } else {
+                            "pending"
+                        };
+                        let challenge = json!({
+                            "type": "dns-01",
+                            "url": format!("{base}/challenge/{index}"),
+                            "token": format!("token-{index}"),
+                        });
+                        let authorization = json!({
+                            "identifier": {"type": "dns", "value": domains[index]},
+                            "status": status,
+                            "challenges": [
+                                {"type": "http-01", "url": format!("{base}/http"), "token": "http"},
+                                challenge,
+                            ],
+                        });
+                        let body = if kind == "challenge" {
+                            challenge
+                        } else {
+                            authorization
+                        };
+                        (200, body, None)
+                    }
+                };
+                let body = match body {
+                    Value::String(pem) => pem,
+                    Value::Null => String::new(),
+                    json => json.to_string(),
+                };
+                let mut response = Response::from_string(body)
+                    .with_status_code(status)
+                    .with_header(Header::from_bytes("Replay-Nonce", "nonce").unwrap());
+                if let Some(location) = location {
+                    let location = format!("{base}{location}");
+                    response.add_header(Header::from_bytes("Location", location).unwrap());
+                }
+                request.respond(response).unwrap();
+            }
+        });
+        format!("{url}/directory")
+    }
+
+    /// Nameserver answering every txt request with the values the acme server expects
+    fn nameserver(values: Arc<Mutex<Vec<String>>>) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let mut buffer = [0u8; 512];
+            while let Ok((length, peer)) = socket.recv_from(&mut buffer) {
+                let question = dns::question(&buffer[..length]).unwrap();
+                let answers = values
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .map(|value| RData::Txt(value.clone()))
+                    .collect::<Vec<_>>();
+                socket
+                    .send_to(&dns::response(&question, Some(&answers)), peer)
+                    .unwrap();
+            }
+        });
+        address
+    }
+
+    fn txt_records(client: &mut Client) -> usize {
+        let command = "dns list example.nl".parse::<TransipCommand>().unwrap();
+        match client.execute_output(&command.command).unwrap() {
+            CommandOutput::DnsEntries(entries) => entries
+                .iter()
+                .filter(|entry| entry.entry_type == "TXT")
+                .count(),
+            _ => panic!("no dns entries"),
+        }
+    }
+
+    #[test]
+    fn issue_and_renew() {
+        let dir = TempDir::new("transipctl-cert");
+        std::fs::create_dir_all(dir.join("log")).unwrap();
+        let mut client = Client::new(Memory::from(fixture::<Fixture>(json!({
+            "dns": {"example.nl": []},
+            "domains": [domain("example.nl")],
+        }))));
+        let audit = Audit::new(dir.join("log"), "fixture".to_owned(), "cert".to_owned());
+        let values = Arc::new(Mutex::new(vec![]));
+        let settings = Settings {
+            directory: acme_server(values.clone()),
+            ca: None,
+            email: Some("admin@example.nl".to_owned()),
+            nameservers: Some(nameserver(values).to_string()),
+            dir: dir.join("certs"),
+            agree_tos: true,
+        };
+        let mut cert = Cert::new(&mut client, None, &audit, settings);
+
+        let names = ["example.nl".to_owned(), "*.example.nl".to_owned()];
+        let issued = cert.issue(&names).unwrap();
+        assert_eq!(issued, dir.join("certs").join("example.nl"));
+        let chain = std::fs::read_to_string(issued.join(FULLCHAIN)).unwrap();
+        assert_eq!(not_after(&chain), Some(date_time_ymd(2099, 3, 1)));
+        assert!(KeyPair::from_pem(&std::fs::read_to_string(issued.join(PRIVKEY)).unwrap()).is_ok());
+        assert_eq!(
+            serde_json::from_slice::<Vec<String>>(&std::fs::read(issued.join(NAMES)).unwrap())
+                .unwrap(),
+            names
+        );
+
+        std::fs::write(issued.join(FULLCHAIN), certificate(2020)).unwrap();
+        cert.renew().unwrap();
+        let chain = std::fs::read_to_string(issued.join(FULLCHAIN)).unwrap();
+        assert_eq!(not_after(&chain), Some(date_time_ymd(2099, 3, 1)));
+        cert.renew().unwrap();
+        assert_eq!(txt_records(&mut client), 0);
+        // the renewal reuses the valid authorizations, so only the first order set challenges
+        assert_eq!(audit.show(None).unwrap().len(), 4);
+    }
+}
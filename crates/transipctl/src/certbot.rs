@@ -0,0 +1,459 @@
+use std::{
+    path::PathBuf,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use transip_execute::{Client, CommandOutput};
+
+use crate::{
+    Result,
+    audit::Audit,
+    error::{Error, ErrorExt},
+    execute,
+    policy::Policy,
+};
+
+const CERTBOT_DOMAIN: &str = "CERTBOT_DOMAIN";
+const CERTBOT_VALIDATION: &str = "CERTBOT_VALIDATION";
+const CERTBOT_REMAINING_CHALLENGES: &str = "CERTBOT_REMAINING_CHALLENGES";
+const CERTBOT_ALL_DOMAINS: &str = "CERTBOT_ALL_DOMAINS";
+const PENDING: &str = "certbot-pending";
+/// Age after which pending challenges are dropped, the default timeout of acme-validation-check
+const EXPIRE: Duration = Duration::from_secs(3600);
+
+/// Exit code when a hook failed, certbot then fails the validation
+pub const EXIT_FAILURE: i32 = 1;
+/// Exit code when the hook is not called by certbot, or called with missing variables
+pub const EXIT_USAGE: i32 = 2;
+
+/// Manual hook certbot calls transipctl for, `transipctl certbot auth|cleanup`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Hook {
+    Auth,
+    Cleanup,
+}
+
+impl FromStr for Hook {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "auth" => Ok(Hook::Auth),
+            "cleanup" => Ok(Hook::Cleanup),
+            _ => Err(Error::Argument(format!(
+                "certbot expects auth or cleanup, not {s}"
+            ))),
+        }
+    }
+}
+
+impl std::fmt::Display for Hook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Hook::Auth => write!(f, "auth"),
+            Hook::Cleanup => write!(f, "cleanup"),
+        }
+    }
+}
+
+/// Challenge certbot asks to set or remove, read from the environment
+#[derive(Debug, PartialEq)]
+struct Challenge {
+    domain: String,
+    validation: String,
+    remaining: usize,
+    /// Domains of the certificate, they tell the run apart from runs for other certificates
+    all_domains: String,
+}
+
+impl Challenge {
+    fn from_lookup(var: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let required = |name: &str| {
+            var(name)
+                .filter(|value| !value.is_empty())
+                .ok_or_else(|| Error::Argument(format!("{name} not set, run from certbot")))
+        };
+        Ok(Self {
+            domain: required(CERTBOT_DOMAIN)?
+                .trim_end_matches('.')
+                .to_lowercase(),
+            validation: required(CERTBOT_VALIDATION)?,
+            remaining: var(CERTBOT_REMAINING_CHALLENGES)
+                .map(|remaining| remaining.parse())
+                .transpose()
+                .map_err(|_| {
+                    Error::Argument(format!("{CERTBOT_REMAINING_CHALLENGES} is not a number"))
+                })?
+                .unwrap_or_default(),
+            all_domains: var(CERTBOT_ALL_DOMAINS).unwrap_or_default(),
+        })
+    }
+
+    /// Domains of the certificate in a fixed order, identifies the run on every platform
+    fn run(&self) -> String {
+        let mut domains = self
+            .all_domains
+            .split(',')
+            .map(|domain| domain.trim().trim_end_matches('.').to_lowercase())
+            .filter(|domain| !domain.is_empty())
+            .collect::<Vec<_>>();
+        domains.sort();
+        domains.join(",")
+    }
+
+    /// True for the first challenge of a run, challenges left by an earlier run are then dropped
+    fn first(&self) -> bool {
+        self.remaining + 1 >= self.run().split(',').count()
+    }
+
+    /// Name of the file with the pending challenges of the certbot run of this challenge
+    fn pending_file(&self) -> String {
+        let digest = Sha256::digest(self.run());
+        let hex = digest[..8]
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<String>();
+        format!("{PENDING}-{hex}.json")
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Challenge set by an earlier auth hook, waited for after the last one
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+struct Pending {
+    domain: String,
+    validation: String,
+    /// Unix time the challenge was set
+    set: u64,
+}
+
+/// Command line that sets or removes the challenge, following a cname on the challenge record
+fn line(hook: Hook, challenge: &Challenge) -> String {
+    let (domain, validation) = (&challenge.domain, &challenge.validation);
+    match hook {
+        Hook::Auth => {
+            format!("dns acme-validation-set {domain} {validation} --append --follow-cname")
+        }
+        Hook::Cleanup => format!("dns acme-validation-delete {domain} {validation} --follow-cname"),
+    }
+}
+
+/// Executes the commands of a hook, with the policy and audit log of the other commands
+pub struct Certbot<'a> {
+    client: &'a mut Client,
+    policy: Option<&'a Policy>,
+    audit: &'a Audit,
+    /// Directory of the files with the challenges set by earlier auth hooks, one per run
+    dir: PathBuf,
+}
+
+impl Certbot<'_> {
+    pub fn new<'a>(
+        client: &'a mut Client,
+        policy: Option<&'a Policy>,
+        audit: &'a Audit,
+    ) -> Certbot<'a> {
+        Certbot {
+            client,
+            policy,
+            audit,
+            dir: crate::log::state_dir(),
+        }
+    }
+
+    fn execute(&mut self, line: &str) -> Result<CommandOutput> {
+        tracing::info!("Certbot hook executes {line}");
+        execute::line(self.client, self.policy, self.audit, line)
+    }
+
+    /// Challenges set earlier in the run of challenge, without the expired ones
+    fn load(&self, challenge: &Challenge) -> Vec<Pending> {
+        let expired = now().saturating_sub(EXPIRE.as_secs());
+        std::fs::read(self.dir.join(challenge.pending_file()))
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<Vec<Pending>>(&bytes).ok())
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|pending| pending.set > expired)
+            .collect()
+    }
+
+    fn save(&self, challenge: &Challenge, pending: &[Pending]) -> Result<()> {
+        let file = self.dir.join(challenge.pending_file());
+        if pending.is_empty() {
+            match std::fs::remove_file(&file) {
+                Err(error) if error.kind() != std::io::ErrorKind::NotFound => Err(error.into()),
+                _ => Ok(()),
+            }
+        } else {
+            std::fs::create_dir_all(&self.dir)?;
+            std::fs::write(&file, serde_json::to_vec(pending)?).err_into()
+        }
+    }
+
+    /// Removes the pending files of runs that ended without cleanup
+    fn sweep(&self) {
+        let Ok(entries) = std::fs::read_dir(&self.dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let expired = entry
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .is_ok_and(|modified| modified.elapsed().is_ok_and(|age| age > EXPIRE));
+            if expired && entry.file_name().to_string_lossy().starts_with(PENDING) {
+                let _ = std::fs::remove_file(entry.path());
+            }
+        }
+    }
+
+    /// Sets the challenge, and after the last one waits until all of them have propagated
+    fn auth(&mut self, challenge: &Challenge) -> Result<()> {
+        self.execute(&line(Hook::Auth, challenge))?;
+        if self.client.dry_run() {
+            return Ok(());
+        }
+        let mut pending = if challenge.first() {
+            vec![]
+        } else {
+            self.load(challenge)
+        };
+        pending.push(Pending {
+            domain: challenge.domain.clone(),
+            validation: challenge.validation.clone(),
+            set: now(),
+        });
+        if challenge.remaining > 0 {
+            return self.save(challenge, &pending);
+        }
+        self.save(challenge, &[])?;
+        pending.iter().try_for_each(|pending| {
+            self.execute(&format!(
+                "dns acme-validation-check {} {} --follow-cname",
+                pending.domain, pending.validation
+            ))
+            .map(|_| ())
+        })
+    }
+
+    fn cleanup(&mut self, challenge: &Challenge) -> Result<()> {
+        let mut pending = self.load(challenge);
+        pending.retain(|pending| {
+            pending.domain != challenge.domain || pending.validation != challenge.validation
+        });
+        self.save(challenge, &pending)?;
+        self.execute(&line(Hook::Cleanup, challenge)).map(|_| ())
+    }
+
+    /// Runs hook for the challenge in the environment, returns the exit code for certbot
+    pub fn run(&mut self, hook: Hook) -> i32 {
+        let challenge = match Challenge::from_lookup(|name| std::env::var(name).ok()) {
+            Ok(challenge) => challenge,
+            Err(error) => {
+                eprintln!("Error: {error}");
+                return EXIT_USAGE;
+            }
+        };
+        tracing::info!("Certbot {hook} hook for {}", challenge.domain);
+        self.sweep();
+        let result = match hook {
+            Hook::Auth => self.auth(&challenge),
+            Hook::Cleanup => self.cleanup(&challenge),
+        };
+        match result {
+            Ok(()) => 0,
+            Err(error) => {
+                tracing::error!("Certbot {hook} hook failed: {error}");
+                eprintln!("Error: {error}");
+                EXIT_FAILURE
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+    use transip_execute::{Client, CommandOutput, Fixture, Memory, TransipCommand};
+    use transip_test_support::{TempDir, domain, fixture};
+
+    use super::{Certbot, Challenge, EXPIRE, Hook, Pending, now};
+    use crate::audit::Audit;
+
+    #[test]
+    fn environment() {
+        let lookup = |values: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                values
+                    .iter()
+                    .find(|(key, _)| *key == name)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+        assert_eq!(
+            Challenge::from_lookup(lookup(&[
+                ("CERTBOT_DOMAIN", "WWW.Example.nl"),
+                ("CERTBOT_VALIDATION", "abc"),
+                ("CERTBOT_REMAINING_CHALLENGES", "1"),
+                ("CERTBOT_ALL_DOMAINS", "example.nl,www.example.nl"),
+            ]))
+            .unwrap(),
+            Challenge {
+                domain: "www.example.nl".to_owned(),
+                validation: "abc".to_owned(),
+                remaining: 1,
+                all_domains: "example.nl,www.example.nl".to_owned(),
+            }
+        );
+        assert!(Challenge::from_lookup(lookup(&[("CERTBOT_DOMAIN", "example.nl")])).is_err());
+        assert_eq!("cleanup".parse::<Hook>().unwrap(), Hook::Cleanup);
+        assert!("renew".parse::<Hook>().is_err());
+        let run = |all_domains: &str, remaining: usize| Challenge {
+            domain: "example.nl".to_owned(),
+            validation: "abc".to_owned(),
+            remaining,
+            all_domains: all_domains.to_owned(),
+        };
+        assert_eq!(
+            run("www.example.nl,example.nl", 1).pending_file(),
+            run("Example.nl., www.example.nl", 0).pending_file()
+        );
+        assert_ne!(
+            run("example.nl", 0).pending_file(),
+            run("example.nl,www.example.nl", 0).pending_file()
+        );
+        assert!(run("example.nl,www.example.nl", 1).first());
+        assert!(!run("example.nl,www.example.nl", 0).first());
+    }
+
+    fn txt_records(client: &mut Client) -> Vec<(String, String)> {
+        let command = "dns list example.nl".parse::<TransipCommand>().unwrap();
+        match client.execute_output(&command.command).unwrap() {
+            CommandOutput::DnsEntries(entries) => entries
+                .into_iter()
+                .filter(|entry| entry.entry_type == "TXT")
+                .map(|entry| (entry.name, entry.content))
+                .collect(),
+            _ => panic!("no dns entries"),
+        }
+    }
+
+    #[test]
+    fn auth_and_cleanup() {
+        let dir = TempDir::new("transipctl-certbot");
+        let mut client = Client::new(Memory::from(fixture::<Fixture>(json!({
+            "dns": {"example.nl": []},
+            "domains": [domain("example.nl")],
+        }))));
+        let audit = Audit::new(
+            dir.to_path_buf(),
+            "fixture".to_owned(),
+            "certbot".to_owned(),
+        );
+        let mut certbot = Certbot::new(&mut client, None, &audit);
+        certbot.dir = dir.to_path_buf();
+        let challenge = |domain: &str, validation: &str| Challenge {
+            domain: domain.to_owned(),
+            validation: validation.to_owned(),
+            remaining: 1,
+            all_domains: "example.nl,www.example.nl,mail.example.nl".to_owned(),
+        };
+
+        let stale = Pending {
+            domain: "example.nl".to_owned(),
+            validation: "stale".to_owned(),
+            set: now(),
+        };
+        certbot
+            .save(&challenge("example.nl", "stale"), &[stale])
+            .unwrap();
+        certbot
+            .auth(&Challenge {
+                remaining: 2,
+                ..challenge("example.nl", "apex")
+            })
+            .unwrap();
+        certbot.auth(&challenge("www.example.nl", "www")).unwrap();
+        assert_eq!(
+            certbot
+                .load(&challenge("example.nl", "apex"))
+                .into_iter()
+                .map(|pending| (pending.domain, pending.validation))
+                .collect::<Vec<_>>(),
+            vec![
+                ("example.nl".to_owned(), "apex".to_owned()),
+                ("www.example.nl".to_owned(), "www".to_owned()),
+            ]
+        );
+        let other_run = Challenge {
+            all_domains: "example.nl".to_owned(),
+            ..challenge("example.nl", "apex")
+        };
+        assert!(certbot.load(&other_run).is_empty());
+        assert!(certbot.auth(&challenge("example.com", "other")).is_err());
+        drop(certbot);
+        assert_eq!(
+            txt_records(&mut client),
+            vec![
+                ("_acme-challenge".to_owned(), "apex".to_owned()),
+                ("_acme-challenge.www".to_owned(), "www".to_owned()),
+            ]
+        );
+        let mut certbot = Certbot::new(&mut client, None, &audit);
+        certbot.dir = dir.to_path_buf();
+
+        certbot.cleanup(&challenge("example.nl", "apex")).unwrap();
+        certbot
+            .cleanup(&challenge("www.example.nl", "www"))
+            .unwrap();
+        assert!(certbot.load(&challenge("example.nl", "apex")).is_empty());
+        assert!(txt_records(&mut client).is_empty());
+        let records = audit.show(None).unwrap();
+        assert_eq!(records.len(), 5);
+        assert!(records.iter().all(|record| record.line.is_none()));
+    }
+
+    #[test]
+    fn expired() {
+        let dir = TempDir::new("transipctl-certbot-expired");
+        let mut client = Client::new(Memory::from(fixture::<Fixture>(json!({"domains": []}))));
+        let audit = Audit::new(
+            dir.to_path_buf(),
+            "fixture".to_owned(),
+            "certbot".to_owned(),
+        );
+        let mut certbot = Certbot::new(&mut client, None, &audit);
+        certbot.dir = dir.to_path_buf();
+        let challenge = Challenge {
+            domain: "example.nl".to_owned(),
+            validation: "new".to_owned(),
+            remaining: 1,
+            all_domains: "example.nl".to_owned(),
+        };
+        let pending = |validation: &str, set: u64| Pending {
+            domain: "example.nl".to_owned(),
+            validation: validation.to_owned(),
+            set,
+        };
+        let set = now();
+        certbot
+            .save(
+                &challenge,
+                &[
+                    pending("old", set - EXPIRE.as_secs() - 1),
+                    pending("new", set),
+                ],
+            )
+            .unwrap();
+        assert_eq!(certbot.load(&challenge), vec![pending("new", set)]);
+    }
+}
@@ -0,0 +1,61 @@
+use std::io::{BufRead, Write};
+
+use transip_execute::{Client, Impact, SubCommand};
+
+/// Asks question on writer and reads the answer from reader, only yes or y confirm
+pub fn ask<R: BufRead, W: Write>(mut reader: R, mut writer: W, question: &str) -> bool {
+    if write!(writer, "{question} [y/N] ")
+        .and_then(|_| writer.flush())
+        .is_err()
+    {
+        return false;
+    }
+    let mut answer = String::new();
+    match reader.read_line(&mut answer) {
+        Ok(_) => matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"),
+        Err(_) => false,
+    }
+}
+
+/// True if command may be executed, asking the user first for destructive commands
+pub fn confirmed(client: &Client, command: &SubCommand) -> bool {
+    if !client.confirm() || client.dry_run() || command.impact() != Impact::Destructive {
+        return true;
+    }
+    let target = command.target().unwrap_or_default();
+    let question = format!("{} {target}?", verb(command));
+    ask(std::io::stdin().lock(), std::io::stderr(), &question)
+}
+
+fn verb(command: &SubCommand) -> &'static str {
+    match command {
+        SubCommand::Vps(transip_execute::VpsCommand::Reset { .. }) => "Reset",
+        SubCommand::Vps(transip_execute::VpsCommand::Stop { .. }) => "Stop",
+        SubCommand::Dns(transip_execute::DnsCommand::AcmeValidationSet { .. }) => "Replace",
+        _ => "Delete",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ask;
+
+    fn answer(input: &str) -> (bool, String) {
+        let mut output = Vec::new();
+        let confirmed = ask(input.as_bytes(), &mut output, "Reset vps vps1?");
+        (confirmed, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn yes() {
+        assert_eq!(answer("y\n"), (true, "Reset vps vps1? [y/N] ".to_owned()));
+        assert!(answer("YES\n").0);
+    }
+
+    #[test]
+    fn no() {
+        assert!(!answer("\n").0);
+        assert!(!answer("n\n").0);
+        assert!(!answer("").0);
+    }
+}
@@ -0,0 +1,208 @@
+use std::io::Write;
+
+use serde_json::Value;
+use transip_execute::{
+    AuditCommand, DnsCommand, DnssecCommand, DomainCommand, EmailBoxCommand, EmailForwardCommand,
+    InvoiceCommand, SubCommand, VpsCommand,
+};
+
+use crate::{Result, error::ErrorExt, query::Query};
+
+/// Csv header and the query path used to fill the column
+type Column = (&'static str, &'static str);
+
+const AUDIT: &[Column] = &[
+    ("timestamp", "timestamp"),
+    ("user", "user"),
+    ("account", "account"),
+    ("source", "source"),
+    ("line", "line"),
+    ("command", "command"),
+    ("outcome", "outcome"),
+    ("error", "error"),
+];
+
+const DNS_ENTRY: &[Column] = &[
+    ("name", "name"),
+    ("expire", "expire"),
+    ("type", "type"),
+    ("content", "content"),
+];
+
+const DNSSEC_ENTRY: &[Column] = &[
+    ("keyTag", "keyTag"),
+    ("flags", "flags"),
+    ("algorithm", "algorithm"),
+    ("publicKey", "publicKey"),
+];
+
+const DOMAIN: &[Column] = &[
+    ("name", "name"),
+    ("status", "status"),
+    ("registrationDate", "registrationDate"),
+    ("renewalDate", "renewalDate"),
+    ("cancellationDate", "cancellationDate"),
+    ("isTransferLocked", "isTransferLocked"),
+    ("isDnsOnly", "isDnsOnly"),
+    ("hasDnsSec", "hasDnsSec"),
+    ("nameservers", "nameservers[].hostname"),
+    ("tags", "tags"),
+];
+
+const INVOICE: &[Column] = &[
+    ("invoiceNumber", "invoiceNumber"),
+    ("creationDate", "creationDate"),
+    ("dueDate", "dueDate"),
+    ("payDate", "payDate"),
+    ("invoiceStatus", "invoiceStatus"),
+    ("currency", "currency"),
+    ("totalAmount", "totalAmount"),
+    ("totalAmountInclVat", "totalAmountInclVat"),
+];
+
+const MAILBOX: &[Column] = &[
+    ("identifier", "identifier"),
+    ("localPart", "localPart"),
+    ("domain", "domain"),
+    ("status", "status"),
+    ("forwardTo", "forwardTo"),
+    ("usedDiskSpace", "usedDiskSpace"),
+    ("availableDiskSpace", "availableDiskSpace"),
+    ("isLocked", "isLocked"),
+];
+
+const MAIL_FORWARD: &[Column] = &[
+    ("id", "id"),
+    ("localPart", "localPart"),
+    ("domain", "domain"),
+    ("forwardTo", "forwardTo"),
+    ("status", "status"),
+];
+
+const NAMESERVER: &[Column] = &[("hostname", "hostname"), ("ipv4", "ipv4"), ("ipv6", "ipv6")];
+
+const VPS: &[Column] = &[
+    ("name", "name"),
+    ("description", "description"),
+    ("productName", "productName"),
+    ("operatingSystem", "operatingSystem"),
+    ("status", "status"),
+    ("ipAddress", "ipAddress"),
+    ("availabilityZone", "availabilityZone"),
+    ("cpus", "cpus"),
+    ("memorySize", "memorySize"),
+    ("diskSize", "diskSize"),
+    ("isLocked", "isLocked"),
+    ("isBlocked", "isBlocked"),
+    ("tags", "tags"),
+];
+
+/// Csv columns for the type returned by command, None if the result is not tabular
+pub fn columns(command: &SubCommand) -> Option<&'static [Column]> {
+    match command {
+        SubCommand::Audit(AuditCommand::Show { .. }) => Some(AUDIT),
+        SubCommand::Dns(DnsCommand::List { .. }) => Some(DNS_ENTRY),
+        SubCommand::Domain(DomainCommand::List | DomainCommand::Item { .. }) => Some(DOMAIN),
+        SubCommand::Domain(DomainCommand::Dnssec(
+            DnssecCommand::List { .. } | DnssecCommand::Set { .. },
+        )) => Some(DNSSEC_ENTRY),
+        SubCommand::Domain(
+            DomainCommand::Nameservers { .. } | DomainCommand::NameserversSet { .. },
+        ) => Some(NAMESERVER),
+        SubCommand::EmailBox(EmailBoxCommand::List { .. } | EmailBoxCommand::Item { .. }) => {
+            Some(MAILBOX)
+        }
+        SubCommand::EmailForward(
+            EmailForwardCommand::List { .. } | EmailForwardCommand::Item { .. },
+        ) => Some(MAIL_FORWARD),
+        SubCommand::Invoice(InvoiceCommand::List | InvoiceCommand::Item { .. }) => Some(INVOICE),
+        SubCommand::Vps(VpsCommand::List | VpsCommand::Item { .. }) => Some(VPS),
+        _ => None,
+    }
+}
+
+fn escape(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+fn cell(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        Value::Array(items) => items.iter().map(cell).collect::<Vec<_>>().join(" "),
+        other => other.to_string(),
+    }
+}
+
+fn write_row<W: Write>(writer: &mut W, fields: impl Iterator<Item = String>) -> Result<()> {
+    let line = fields
+        .map(|field| escape(&field))
+        .collect::<Vec<_>>()
+        .join(",");
+    writeln!(writer, "{line}").err_into()
+}
+
+/// Writes a header and one row for every element of value
+pub fn write<W: Write>(writer: &mut W, columns: &[Column], value: &Value) -> Result<()> {
+    let queries = columns
+        .iter()
+        .map(|(_, path)| path.parse::<Query>())
+        .collect::<Result<Vec<_>>>()?;
+    let rows = match value {
+        Value::Array(items) => items.iter().collect(),
+        Value::Null => vec![],
+        item => vec![item],
+    };
+    write_row(writer, columns.iter().map(|(header, _)| header.to_string()))?;
+    rows.into_iter()
+        .try_for_each(|row| write_row(writer, queries.iter().map(|query| cell(&query.apply(row)))))
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::{DNS_ENTRY, DOMAIN, write};
+
+    fn csv(columns: &[(&'static str, &'static str)], value: serde_json::Value) -> String {
+        let mut buffer = Vec::new();
+        write(&mut buffer, columns, &value).unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+
+    #[test]
+    fn dns_entries() {
+        let value = json!([
+            {"name": "@", "expire": 300, "type": "A", "content": "1.2.3.4"},
+            {"name": "@", "expire": 300, "type": "TXT", "content": "v=spf1 a, \"mx\""},
+        ]);
+        assert_eq!(
+            csv(DNS_ENTRY, value),
+            "name,expire,type,content\n@,300,A,1.2.3.4\n@,300,TXT,\"v=spf1 a, \"\"mx\"\"\"\n"
+        );
+    }
+
+    #[test]
+    fn single_item() {
+        let value = json!({
+            "name": "example.nl",
+            "status": "registered",
+            "nameservers": [{"hostname": "ns0.transip.net"}, {"hostname": "ns1.transip.nl"}],
+            "tags": [],
+            "cancellationDate": null,
+        });
+        assert_eq!(
+            csv(DOMAIN, value).lines().nth(1),
+            Some("example.nl,registered,,,,,,,ns0.transip.net ns1.transip.nl,")
+        );
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(csv(DNS_ENTRY, json!([])), "name,expire,type,content\n");
+    }
+}
@@ -0,0 +1,336 @@
+use std::{net::IpAddr, process::Command, str::FromStr, time::Duration};
+
+use transip_execute::{Client, CommandOutput};
+use ureq::{Agent, config::IpFamily};
+
+use crate::{Result, audit::Audit, error::Error, execute, policy::Policy};
+
+/// Echo service used when no source is given, it answers with the address of the client
+pub const ECHO: &str = "https://icanhazip.com";
+/// Seconds between updates in daemon mode, when no interval is given
+pub const INTERVAL: u64 = 300;
+/// Ttl of a record that did not exist before
+const TTL: u32 = 300;
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Family {
+    V4,
+    V6,
+}
+
+impl Family {
+    fn record_type(&self) -> &'static str {
+        match self {
+            Family::V4 => "A",
+            Family::V6 => "AAAA",
+        }
+    }
+
+    fn contains(&self, address: &IpAddr) -> bool {
+        match self {
+            Family::V4 => address.is_ipv4(),
+            Family::V6 => address.is_ipv6(),
+        }
+    }
+}
+
+/// Where the public address is found, `--source eth0|https://url|cmd:command`
+#[derive(Clone, Debug, PartialEq)]
+pub enum Source {
+    /// First address of the interface that is not link local
+    Interface(String),
+    /// Address in the body of an http echo service
+    Url(String),
+    /// First address in the output of a shell command
+    Command(String),
+}
+
+impl Default for Source {
+    fn default() -> Self {
+        Source::Url(ECHO.to_owned())
+    }
+}
+
+impl FromStr for Source {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s.starts_with("http://") || s.starts_with("https://") {
+            Ok(Source::Url(s.to_owned()))
+        } else if let Some(command) = s.strip_prefix("cmd:") {
+            Ok(Source::Command(command.to_owned()))
+        } else if s.is_empty() {
+            Err(Error::Argument(
+                "source expects an interface, an url or cmd:<command>".to_owned(),
+            ))
+        } else {
+            Ok(Source::Interface(s.to_owned()))
+        }
+    }
+}
+
+/// First address of family in text
+fn first_address(text: &str, family: Family) -> Option<IpAddr> {
+    text.split_whitespace()
+        .filter_map(|word| word.parse::<IpAddr>().ok())
+        .find(|address| family.contains(address))
+}
+
+fn link_local(address: &IpAddr) -> bool {
+    match address {
+        IpAddr::V4(address) => address.is_link_local(),
+        IpAddr::V6(address) => address.is_unicast_link_local(),
+    }
+}
+
+impl Source {
+    pub fn address(&self, family: Family) -> Result<IpAddr> {
+        let not_found =
+            || Error::Ddns(format!("no {} address from {self:?}", family.record_type()));
+        match self {
+            Source::Interface(name) => if_addrs::get_if_addrs()?
+                .into_iter()
+                .filter(|interface| &interface.name == name)
+                .map(|interface| interface.ip())
+                .find(|address| family.contains(address) && !link_local(address))
+                .ok_or_else(not_found),
+            Source::Url(url) => {
+                let ip_family = match family {
+                    Family::V4 => IpFamily::Ipv4Only,
+                    Family::V6 => IpFamily::Ipv6Only,
+                };
+                let agent = Agent::new_with_config(
+                    Agent::config_builder()
+                        .ip_family(ip_family)
+                        .timeout_global(Some(HTTP_TIMEOUT))
+                        .build(),
+                );
+                let body = agent.get(url).call()?.body_mut().read_to_string()?;
+                first_address(&body, family).ok_or_else(not_found)
+            }
+            Source::Command(command) => {
+                let output = Command::new("sh").arg("-c").arg(command).output()?;
+                if !output.status.success() {
+                    return Err(Error::Ddns(format!(
+                        "{command} failed with {}",
+                        output.status
+                    )));
+                }
+                first_address(&String::from_utf8_lossy(&output.stdout), family)
+                    .ok_or_else(not_found)
+            }
+        }
+    }
+}
+
+/// Record to keep up to date, given on the command line
+#[derive(Debug)]
+pub struct Settings {
+    pub domain: String,
+    /// Name relative to the domain, `@` for the domain itself
+    pub name: String,
+    pub families: Vec<Family>,
+    pub source: Source,
+    /// Time between updates in daemon mode, a single update without
+    pub interval: Option<Duration>,
+}
+
+/// Points the address records of a name at the public address, with the policy and audit log of the other commands
+pub struct Ddns<'a> {
+    client: &'a mut Client,
+    policy: Option<&'a Policy>,
+    audit: &'a Audit,
+    settings: Settings,
+}
+
+impl Ddns<'_> {
+    pub fn new<'a>(
+        client: &'a mut Client,
+        policy: Option<&'a Policy>,
+        audit: &'a Audit,
+        settings: Settings,
+    ) -> Ddns<'a> {
+        Ddns {
+            client,
+            policy,
+            audit,
+            settings,
+        }
+    }
+
+    fn execute(&mut self, line: &str) -> Result<CommandOutput> {
+        tracing::info!("Ddns executes {line}");
+        execute::line(self.client, self.policy, self.audit, line)
+    }
+
+    /// Replaces the records of family by the current address, returns true if they changed
+    fn update(&mut self, family: Family) -> Result<bool> {
+        let address = self.settings.source.address(family)?;
+        let (domain, name) = (self.settings.domain.clone(), self.settings.name.clone());
+        let entries = match self.execute(&format!("dns list {domain}"))? {
+            CommandOutput::DnsEntries(entries) => entries,
+            _ => vec![],
+        };
+        let (current, stale) = entries
+            .into_iter()
+            .filter(|entry| entry.name == name && entry.entry_type == family.record_type())
+            .partition::<Vec<_>, _>(|entry| entry.content.parse::<IpAddr>() == Ok(address));
+        if stale.is_empty() && !current.is_empty() {
+            tracing::info!("{name} {} of {domain} is {address}", family.record_type());
+            return Ok(false);
+        }
+        if current.is_empty() {
+            let ttl = stale.first().map_or(TTL, |entry| entry.expire);
+            let record_type = family.record_type();
+            self.execute(&format!(
+                "dns insert {domain} {name} {ttl} {record_type} {address}"
+            ))?;
+        }
+        for entry in stale {
+            self.execute(&format!(
+                "dns delete {domain} {name} {} {} {}",
+                entry.expire, entry.entry_type, entry.content
+            ))?;
+        }
+        eprintln!(
+            "{name} {} of {domain} set to {address}",
+            family.record_type()
+        );
+        Ok(true)
+    }
+
+    /// Updates every family, a failure of one does not stop the others
+    fn update_all(&mut self) -> Result<()> {
+        let mut error = None;
+        for family in self.settings.families.clone() {
+            if let Err(failure) = self.update(family) {
+                tracing::error!("Ddns {} update failed: {failure}", family.record_type());
+                error.get_or_insert(failure);
+            }
+        }
+        error.map_or(Ok(()), Err)
+    }
+
+    /// Updates once, or keeps updating every interval in daemon mode
+    pub fn run(&mut self) -> Result<()> {
+        let Some(interval) = self.settings.interval else {
+            return self.update_all();
+        };
+        loop {
+            if let Err(error) = self.update_all() {
+                eprintln!("Error: {error}");
+            }
+            std::thread::sleep(interval);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::IpAddr;
+
+    use serde_json::json;
+    use tiny_http::{Response, Server};
+    use transip_execute::{Client, CommandOutput, Fixture, Memory, TransipCommand};
+    use transip_test_support::{TempDir, fixture};
+
+    use super::{Ddns, Family, Settings, Source, first_address};
+    use crate::audit::Audit;
+
+    #[test]
+    fn source() {
+        assert_eq!(
+            "https://ifconfig.co/ip".parse::<Source>().unwrap(),
+            Source::Url("https://ifconfig.co/ip".to_owned())
+        );
+        assert_eq!(
+            "cmd:curl -s ifconfig.co".parse::<Source>().unwrap(),
+            Source::Command("curl -s ifconfig.co".to_owned())
+        );
+        assert_eq!(
+            "eth0".parse::<Source>().unwrap(),
+            Source::Interface("eth0".to_owned())
+        );
+        assert!("".parse::<Source>().is_err());
+        let text = "inet 192.0.2.7/24\ninet6 2001:db8::7\n2001:db8::8";
+        assert_eq!(
+            first_address(text, Family::V6),
+            Some("2001:db8::7".parse().unwrap())
+        );
+        assert_eq!(first_address(text, Family::V4), None);
+        assert!(
+            Source::Interface("no-such-interface".to_owned())
+                .address(Family::V4)
+                .is_err()
+        );
+    }
+
+    /// Echo service answering every request with body
+    fn echo(body: &'static str) -> String {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/ip", server.server_addr().to_ip().unwrap());
+        std::thread::spawn(move || {
+            for request in server.incoming_requests() {
+                request.respond(Response::from_string(body)).unwrap();
+            }
+        });
+        url
+    }
+
+    #[test]
+    fn url_source() {
+        let source = Source::Url(echo("192.0.2.9\n"));
+        assert_eq!(
+            source.address(Family::V4).unwrap(),
+            "192.0.2.9".parse::<IpAddr>().unwrap()
+        );
+        assert!(Source::Url(echo("no address")).address(Family::V4).is_err());
+    }
+
+    fn records(client: &mut Client) -> Vec<String> {
+        let command = "dns list example.nl".parse::<TransipCommand>().unwrap();
+        match client.execute_output(&command.command).unwrap() {
+            CommandOutput::DnsEntries(entries) => entries
+                .into_iter()
+                .map(|entry| format!("{} {} {}", entry.name, entry.entry_type, entry.content))
+                .collect(),
+            _ => panic!("no dns entries"),
+        }
+    }
+
+    #[test]
+    fn update() {
+        let dir = TempDir::new("transipctl-ddns");
+        let mut client = Client::new(Memory::from(fixture::<Fixture>(json!({
+            "dns": {"example.nl": [
+                {"name": "home", "expire": 60, "type": "A", "content": "198.51.100.1"},
+                {"name": "home", "expire": 60, "type": "A", "content": "198.51.100.2"},
+                {"name": "www", "expire": 300, "type": "A", "content": "198.51.100.3"},
+            ]},
+        }))));
+        let audit = Audit::new(dir.to_path_buf(), "fixture".to_owned(), "ddns".to_owned());
+        let settings = Settings {
+            domain: "example.nl".to_owned(),
+            name: "home".to_owned(),
+            families: vec![Family::V4, Family::V6],
+            source: Source::Command("echo 192.0.2.7 2001:db8::7".to_owned()),
+            interval: None,
+        };
+        let mut ddns = Ddns::new(&mut client, None, &audit, settings);
+        ddns.run().unwrap();
+        assert!(!ddns.update(Family::V4).unwrap());
+        assert!(!ddns.update(Family::V6).unwrap());
+        ddns.settings.source = Source::Command("exit 1".to_owned());
+        assert!(ddns.run().is_err());
+        assert_eq!(
+            records(&mut client),
+            vec![
+                "www A 198.51.100.3",
+                "home A 192.0.2.7",
+                "home AAAA 2001:db8::7"
+            ]
+        );
+        assert_eq!(audit.show(None).unwrap().len(), 4);
+    }
+}
@@ -38,6 +38,36 @@ pub enum Error {
     #[error("XDG: {0}")]
     Xdg(&'static str),
 
+    #[error("Argument: {0}")]
+    Argument(String),
+
+    #[error("Acme: {0}")]
+    Acme(String),
+
+    #[error("Http: {0}")]
+    Http(#[from] ureq::Error),
+
+    #[error("Certificate: {0}")]
+    Certificate(#[from] rcgen::Error),
+
+    #[error("Certbot: {0}")]
+    Certbot(String),
+
+    #[error("Ddns: {0}")]
+    Ddns(String),
+
+    #[error("Csv: {0}")]
+    Csv(&'static str),
+
+    #[error("Policy: {0}")]
+    Policy(String),
+
+    #[error("Tsig: {0}")]
+    Tsig(String),
+
+    #[error("Query: {0}")]
+    Query(String),
+
     #[error("Line: {0}")]
     Line(#[from] rusty_lines::Error),
 }
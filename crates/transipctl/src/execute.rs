@@ -0,0 +1,27 @@
+use transip_execute::{Client, CommandOutput, TransipCommand};
+
+use crate::{
+    Result,
+    audit::Audit,
+    error::{Error, ErrorExt},
+    policy::Policy,
+};
+
+/// Executes line if the policy allows it, and writes it to the audit log
+pub(crate) fn line(
+    client: &mut Client,
+    policy: Option<&Policy>,
+    audit: &Audit,
+    line: &str,
+) -> Result<CommandOutput> {
+    let command = line
+        .parse::<TransipCommand>()
+        .map_err(|error| Error::ParseTransipCommand(error.to_string()))?
+        .command;
+    if let Some(policy) = policy {
+        policy.check_delegated(client, &command)?;
+    }
+    let output = client.execute_output(&command);
+    audit.executed(None, &command, &output)?;
+    output.err_into()
+}
@@ -21,6 +21,21 @@ pub fn log_dir() -> PathBuf {
         .map_or_else(|_| local_data_dir.unwrap(), PathBuf::from)
 }
 
+/// Directory of the state kept between runs, like certificates, apart from the logs
+///
+/// The xdg state directory where there is one, the configuration directory otherwise.
+pub fn state_dir() -> PathBuf {
+    directories::ProjectDirs::from(QUALIFIER, ORGANISATION, APPLICATION)
+        .map(|project_dirs| {
+            project_dirs
+                .state_dir()
+                .unwrap_or(project_dirs.config_dir())
+                .to_path_buf()
+        })
+        .ok_or(Error::Xdg("XDG not found"))
+        .unwrap()
+}
+
 pub fn setup_logging() {
     LogTracer::init().unwrap();
 
@@ -1,7 +1,23 @@
 use rusty_lines::{FileLinesBuilder, TTYLinesBuilder};
-use std::{env::args, path::PathBuf, process::exit};
+use serde::Serialize;
+use serde_json::Value;
+use std::{fs::OpenOptions, io::Write, process::exit, str::FromStr, time::Duration};
 use transip_execute::{
-    Client, ErrorKind, SubCommand, TransipCommand, configuration_from_environment,
+    AuditCommand, Client, CommandOutput, ErrorKind, Memory, OutputFormat, Redirect, Replay,
+    SubCommand, TransipCommand, client_from_environment,
+};
+
+use crate::{
+    arguments::Arguments,
+    audit::{Audit, Outcome},
+    cert::{Cert, Settings},
+    certbot::Certbot,
+    ddns::{Ddns, Family},
+    error::{Error, ErrorExt},
+    policy::Policy,
+    query::Query,
+    rfc2136::Rfc2136,
+    tsig::Keys,
 };
 
 pub type Result<T> = std::result::Result<T, error::Error>;
@@ -10,8 +26,21 @@ pub const VERSION: &str = concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_V
 const EXIT_ON: &[&str] = &["exit", "quit"];
 const PROMPT: &str = "tipctl";
 
+mod acme;
+mod arguments;
+mod audit;
+mod cert;
+mod certbot;
+mod confirm;
+mod csv;
+mod ddns;
 mod error;
+mod execute;
 mod log;
+mod policy;
+mod query;
+mod rfc2136;
+mod tsig;
 
 fn arg_version() {
     if std::env::args()
@@ -23,8 +52,8 @@ fn arg_version() {
     }
 }
 
-#[allow(dead_code)]
 enum Out {
+    Csv,
     Json,
     // Yaml,
     // Toml,
@@ -37,7 +66,28 @@ fn handle_error(msg: String, exit_on_error: bool) {
     }
 }
 
-fn handle_ok(buffer: Vec<u8>, extra_newline: bool) {
+/// Writes the output of a succeeded command, the file is not opened or truncated before there is output to write
+fn handle_redirect(mut buffer: Vec<u8>, extra_newline: bool, redirect: &Redirect) -> Result<()> {
+    if buffer.is_empty() {
+        return Ok(());
+    }
+    if extra_newline {
+        buffer.push(b'\n');
+    }
+    OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(redirect.append)
+        .truncate(!redirect.append)
+        .open(&redirect.path)?
+        .write_all(&buffer)?;
+    Ok(())
+}
+
+fn handle_ok(buffer: Vec<u8>, extra_newline: bool, redirect: Option<&Redirect>) -> Result<()> {
+    if let Some(redirect) = redirect {
+        return handle_redirect(buffer, extra_newline, redirect);
+    }
     let s = String::from_utf8(buffer).unwrap();
     if !s.is_empty() {
         if extra_newline {
@@ -46,37 +96,270 @@ fn handle_ok(buffer: Vec<u8>, extra_newline: bool) {
             print!("{s}");
         }
     }
+    Ok(())
+}
+
+fn output_value(output: transip_execute::Result<CommandOutput>) -> Result<Option<Value>> {
+    let output = output?;
+    if output.is_unit() {
+        Ok(None)
+    } else {
+        serde_json::to_value(output).err_into().map(Some)
+    }
+}
+
+fn output_query(
+    output: transip_execute::Result<CommandOutput>,
+    query: &str,
+) -> Result<Option<Value>> {
+    let query = query.parse::<Query>()?;
+    output_value(output).map(|value| value.map(|value| query.apply(&value)))
+}
+
+fn output_csv(
+    output: transip_execute::Result<CommandOutput>,
+    command: &TransipCommand,
+) -> Result<Vec<u8>> {
+    if command.query.is_some() {
+        return Err(Error::Csv("--query can not be combined with --output csv"));
+    }
+    let columns =
+        csv::columns(&command.command).ok_or(Error::Csv("no csv columns for this command"))?;
+    let mut buffer: Vec<u8> = Vec::new();
+    if let Some(value) = output_value(output)? {
+        csv::write(&mut buffer, columns, &value)?;
+    }
+    Ok(buffer)
 }
 
-macro_rules! execute_out {
-    ($ser:path, $client:ident, $command:ident, $extra_newline:expr_2021) => {
+macro_rules! render_out {
+    ($ser:path, $output:ident, $command:ident, $exit_on_error:ident, $extra_newline:expr_2021) => {
         let mut buffer: Vec<u8> = Vec::new();
         let mut ser = $ser(&mut buffer);
 
-        match $client.execute($command, &mut ser) {
+        let result = match $command.query.as_deref() {
+            Some(query) => output_query($output, query).and_then(|value| {
+                value.map_or(Ok(()), |value| value.serialize(&mut ser).err_into())
+            }),
+            None => $output
+                .err_into()
+                .and_then(|output| output.report(&mut ser).err_into()),
+        };
+
+        match result {
             Ok(_) => {
-                handle_ok(buffer, $extra_newline);
+                if let Err(error) = handle_ok(buffer, $extra_newline, $command.redirect.as_ref()) {
+                    handle_error(format!("Error: {error}"), $exit_on_error);
+                }
             }
             Err(error) => {
-                handle_error(format!("Error: {error}"), $client.exit_on_error());
+                handle_error(format!("Error: {error}"), $exit_on_error);
             }
         }
     };
 }
 
+impl From<&OutputFormat> for Out {
+    fn from(format: &OutputFormat) -> Self {
+        match format {
+            OutputFormat::Csv => Out::Csv,
+            OutputFormat::Json => Out::Json,
+        }
+    }
+}
+
 impl Out {
-    fn execute(&self, client: &mut Client, command: &SubCommand) {
+    fn render(
+        &self,
+        command: &TransipCommand,
+        output: transip_execute::Result<CommandOutput>,
+        exit_on_error: bool,
+    ) {
         match self {
+            Out::Csv => {
+                if let Err(error) = output_csv(output, command)
+                    .and_then(|buffer| handle_ok(buffer, false, command.redirect.as_ref()))
+                {
+                    handle_error(format!("Error: {error}"), exit_on_error);
+                }
+            }
             Out::Json => {
-                execute_out!(serde_json::Serializer::pretty, client, command, true);
+                render_out!(
+                    serde_json::Serializer::pretty,
+                    output,
+                    command,
+                    exit_on_error,
+                    true
+                );
             } // Out::Toml => {
-              //     execute_out!(toml_edit::ser::ValueSerializer::new, client, command, true);
+              //     render_out!(toml_edit::ser::ValueSerializer::new, output, command, exit_on_error, true);
               // }
               // Out::Yaml => {
-              //     execute_out!(serde_yaml::Serializer::new, client, command, false);
+              //     render_out!(serde_yaml::Serializer::new, output, command, exit_on_error, false);
               // }
         }
     }
+
+    fn execute(
+        &self,
+        client: &mut Client,
+        audit: &Audit,
+        line_number: usize,
+        command: &TransipCommand,
+    ) {
+        let output = client.execute_output(&command.command);
+        audited(
+            client,
+            audit.executed(Some(line_number), &command.command, &output),
+        );
+        self.render(command, output, client.exit_on_error());
+    }
+}
+
+/// A failure to write the audit log is reported, but does not undo the command
+fn audited(client: &Client, result: Result<()>) {
+    if let Err(error) = result {
+        tracing::error!("Failed to write audit log: {error}");
+        handle_error(
+            format!("Error writing audit log: {error}"),
+            client.exit_on_error(),
+        );
+    }
+}
+
+fn audit_show(audit: &Audit, command: &TransipCommand, since: Option<&str>) -> Result<Vec<u8>> {
+    let records = audit.show(since)?;
+    let mut buffer: Vec<u8> = Vec::new();
+    match (&command.output, command.query.as_deref()) {
+        (OutputFormat::Csv, Some(_)) => {
+            return Err(Error::Csv("--query can not be combined with --output csv"));
+        }
+        (OutputFormat::Csv, None) => {
+            let columns = csv::columns(&command.command)
+                .ok_or(Error::Csv("no csv columns for this command"))?;
+            csv::write(&mut buffer, columns, &serde_json::to_value(records)?)?;
+        }
+        (OutputFormat::Json, Some(query)) => {
+            let value = query
+                .parse::<Query>()?
+                .apply(&serde_json::to_value(records)?);
+            serde_json::to_writer_pretty(&mut buffer, &value)?;
+        }
+        (OutputFormat::Json, None) => serde_json::to_writer_pretty(&mut buffer, &records)?,
+    }
+    Ok(buffer)
+}
+
+/// Prints the audit records, audit show is answered here because the client has no access to the log
+fn execute_audit(client: &Client, audit: &Audit, command: &TransipCommand, since: Option<&str>) {
+    let extra_newline = command.output == OutputFormat::Json;
+    if let Err(error) = audit_show(audit, command, since)
+        .and_then(|buffer| handle_ok(buffer, extra_newline, command.redirect.as_ref()))
+    {
+        handle_error(format!("Error: {error}"), client.exit_on_error());
+    }
+}
+
+fn parse_error(error: <TransipCommand as FromStr>::Err, line_number: usize, client: &Client) {
+    if error.kind() == ErrorKind::DisplayHelp {
+        handle_error(error.to_string(), client.exit_on_error())
+    } else {
+        tracing::error!("Failed to proccess command: {error}");
+        handle_error(
+            format!("Error {} parsing line {}", error, line_number + 1),
+            client.exit_on_error(),
+        )
+    }
+}
+
+/// True if the policy and the user allow command to be executed
+fn allowed(
+    client: &mut Client,
+    policy: Option<&Policy>,
+    audit: &Audit,
+    line_number: usize,
+    command: &SubCommand,
+) -> bool {
+    if let Some(Err(error)) = policy.map(|policy| policy.check_delegated(client, command)) {
+        tracing::warn!("Refused {}: {error}", command.name());
+        let reason = Some(error.to_string());
+        audited(
+            client,
+            audit.skipped(Some(line_number), command, Outcome::Refused, reason),
+        );
+        handle_error(format!("Error: {error}"), client.exit_on_error());
+        false
+    } else if !confirm::confirmed(client, command) {
+        audited(
+            client,
+            audit.skipped(Some(line_number), command, Outcome::Cancelled, None),
+        );
+        eprintln!("Cancelled");
+        false
+    } else {
+        true
+    }
+}
+
+/// Executes the commands up to end concurrently and prints their results in source order
+fn execute_parallel<I>(
+    client: &mut Client,
+    policy: Option<&Policy>,
+    audit: &Audit,
+    jobs: usize,
+    lines: &mut I,
+) where
+    I: Iterator<Item = (usize, std::result::Result<String, rusty_lines::Error>)>,
+{
+    let mut commands = vec![];
+    let mut ended = false;
+    for (line_number, line_result) in lines.by_ref() {
+        let line = match line_result {
+            Ok(line) => line,
+            Err(error) => {
+                handle_error(format!("Error: {error}"), client.exit_on_error());
+                continue;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        match line.parse::<TransipCommand>() {
+            Ok(TransipCommand {
+                command: SubCommand::End,
+                ..
+            }) => {
+                ended = true;
+                break;
+            }
+            Ok(command) => {
+                if allowed(client, policy, audit, line_number, &command.command) {
+                    commands.push((line_number, command));
+                }
+            }
+            Err(error) => parse_error(error, line_number, client),
+        }
+    }
+    if !ended {
+        handle_error(
+            "Error: parallel without end".to_owned(),
+            client.exit_on_error(),
+        );
+        return;
+    }
+    tracing::info!("Processing {} commands on {jobs} jobs", commands.len());
+    let subcommands = commands
+        .iter()
+        .map(|(_, command)| &command.command)
+        .collect::<Vec<_>>();
+    let outputs = client.execute_parallel(jobs, &subcommands);
+    for ((line_number, command), output) in commands.iter().zip(outputs) {
+        audited(
+            client,
+            audit.executed(Some(*line_number), &command.command, &output),
+        );
+        Out::from(&command.output).render(command, output, client.exit_on_error());
+    }
 }
 
 fn main() -> Result<()> {
@@ -84,9 +367,92 @@ fn main() -> Result<()> {
     log::setup_logging();
     tracing::info!("Logging initialized");
 
-    let lines = args()
-        .nth(1)
-        .map(PathBuf::from)
+    let arguments = Arguments::from_env()?;
+
+    tracing::info!("Running {} {}", VERSION, arguments.source());
+
+    let policy = arguments
+        .policy
+        .as_ref()
+        .map(Policy::from_file)
+        .transpose()?;
+    let mut client = match (arguments.fixture.as_ref(), arguments.replay.as_ref()) {
+        (_, Some(replay)) => Replay::from_dir(replay).map(Client::new)?,
+        (Some(fixture), None) => Memory::from_fixture(fixture).map(Client::new)?,
+        (None, None) => client_from_environment()?,
+    };
+    if let Some(record) = arguments.record.as_ref() {
+        client.record(record)?;
+    }
+    client.set_dry_run(arguments.dry_run);
+    client.set_confirm(arguments.confirm());
+    tracing::info!("Cliënt initialized");
+    let audit = Audit::new(log::log_dir(), arguments.account(), arguments.source());
+
+    if let Some(hook) = arguments.certbot {
+        let code = Certbot::new(&mut client, policy.as_ref(), &audit).run(hook);
+        tracing::info!("Ending {} {}", VERSION, arguments.source());
+        drop(client);
+        exit(code);
+    }
+
+    if let Some(command) = arguments.cert.as_ref() {
+        let settings = Settings {
+            directory: arguments
+                .acme_directory
+                .clone()
+                .unwrap_or_else(|| acme::LETS_ENCRYPT.to_owned()),
+            ca: arguments.acme_ca.clone(),
+            email: arguments.acme_email.clone(),
+            nameservers: arguments.acme_nameservers.clone(),
+            dir: arguments
+                .cert_dir
+                .clone()
+                .unwrap_or_else(|| log::state_dir().join("certs")),
+            agree_tos: arguments.agree_tos,
+        };
+        Cert::new(&mut client, policy.as_ref(), &audit, settings).run(command)?;
+        tracing::info!("Ending {} {}", VERSION, arguments.source());
+        return Ok(());
+    }
+
+    if let Some([domain, name]) = arguments.ddns.as_deref() {
+        let families = match (arguments.ipv4, arguments.ipv6) {
+            (false, true) => vec![Family::V6],
+            (true, true) => vec![Family::V4, Family::V6],
+            _ => vec![Family::V4],
+        };
+        let settings = ddns::Settings {
+            domain: domain.clone(),
+            name: name.clone(),
+            families,
+            source: arguments.source.clone().unwrap_or_default(),
+            interval: arguments
+                .daemon
+                .then(|| Duration::from_secs(arguments.interval.unwrap_or(ddns::INTERVAL))),
+        };
+        Ddns::new(&mut client, policy.as_ref(), &audit, settings).run()?;
+        tracing::info!("Ending {} {}", VERSION, arguments.source());
+        return Ok(());
+    }
+
+    if arguments.rfc2136 {
+        let keys = arguments
+            .tsig_keys
+            .as_ref()
+            .map(Keys::from_file)
+            .transpose()?
+            .unwrap_or_default();
+        let address = arguments.listen.unwrap_or(rfc2136::LISTEN);
+        eprintln!("Listening for rfc 2136 updates on {address}");
+        Rfc2136::new(&mut client, policy.as_ref(), &audit, &keys).serve(address)?;
+        tracing::info!("Ending {} {}", VERSION, arguments.source());
+        return Ok(());
+    }
+
+    let lines = arguments
+        .script
+        .clone()
         .map(|f| FileLinesBuilder::file(f).replace_variables().build())
         .unwrap_or(
             TTYLinesBuilder::prompt(PROMPT)
@@ -95,44 +461,39 @@ fn main() -> Result<()> {
                 .build(),
         )?;
 
-    tracing::info!(
-        "Running {} {}",
-        VERSION,
-        args().nth(1).as_ref().unwrap_or(&"tty".to_owned())
-    );
-
-    let output_format = Out::Json;
-    let mut client = configuration_from_environment().and_then(Client::try_from)?;
-    tracing::info!("Cliënt initialized");
-
-    for (line_number, line_result) in lines.enumerate() {
+    let mut lines = lines.enumerate();
+    while let Some((line_number, line_result)) = lines.next() {
         let line = line_result?;
         if !line.trim().is_empty() {
             tracing::info!("Processing non empty line: {line}");
             match line.parse::<TransipCommand>() {
+                Ok(TransipCommand {
+                    command: SubCommand::Parallel { jobs },
+                    ..
+                }) => execute_parallel(&mut client, policy.as_ref(), &audit, jobs, &mut lines),
+                Ok(
+                    ref command @ TransipCommand {
+                        command: SubCommand::Audit(AuditCommand::Show { ref since }),
+                        ..
+                    },
+                ) => execute_audit(&client, &audit, command, since.as_deref()),
+                Ok(command)
+                    if !allowed(
+                        &mut client,
+                        policy.as_ref(),
+                        &audit,
+                        line_number,
+                        &command.command,
+                    ) => {}
                 Ok(command) => {
                     tracing::info!("Processing command {:?}", command);
-                    output_format.execute(&mut client, &command.command)
-                }
-                Err(error) => {
-                    if error.kind() == ErrorKind::DisplayHelp {
-                        handle_error(error.to_string(), client.exit_on_error())
-                    } else {
-                        tracing::error!("Failed to proccess command: {error}");
-                        handle_error(
-                            format!("Error {} parsing line {}", error, line_number + 1),
-                            client.exit_on_error(),
-                        )
-                    }
+                    Out::from(&command.output).execute(&mut client, &audit, line_number, &command)
                 }
+                Err(error) => parse_error(error, line_number, &client),
             }
         }
     }
 
-    tracing::info!(
-        "Ending {} {}",
-        VERSION,
-        args().nth(1).as_ref().unwrap_or(&"tty".to_owned())
-    );
+    tracing::info!("Ending {} {}", VERSION, arguments.source());
     Ok(())
 }
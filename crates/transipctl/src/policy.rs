@@ -0,0 +1,184 @@
+use std::path::Path;
+
+use serde::Deserialize;
+use transip_execute::{Client, Resource, SubCommand};
+
+use crate::{Result, error::Error};
+
+/// Allow-lists loaded from a toml file, checked before a command is executed
+///
+/// A list that is left out does not restrict anything.
+/// Patterns can contain `*`, which matches any sequence of characters.
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Policy {
+    /// Command names, like `dns acme-validation-set` or `dns *`
+    commands: Option<Vec<String>>,
+    /// Domains of commands on domains, dns records, mailboxes and mail forwards
+    domains: Option<Vec<String>>,
+    /// Names of dns records, like `_acme-challenge`
+    record_names: Option<Vec<String>>,
+}
+
+/// True if text matches pattern, where `*` matches any sequence of characters
+pub(crate) fn matches(pattern: &str, text: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == text,
+        Some((prefix, rest)) => {
+            text.starts_with(prefix)
+                && (0..=text.len() - prefix.len())
+                    .filter(|index| text.is_char_boundary(prefix.len() + index))
+                    .any(|index| matches(rest, &text[prefix.len() + index..]))
+        }
+    }
+}
+
+fn allowed(patterns: &Option<Vec<String>>, text: &str) -> bool {
+    patterns
+        .as_ref()
+        .is_none_or(|patterns| patterns.iter().any(|pattern| matches(pattern, text)))
+}
+
+impl Policy {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        text.parse()
+    }
+
+    /// Fails if the command is not allowed, commands that only change settings are always allowed
+    pub fn check(&self, command: &SubCommand) -> Result<()> {
+        self.check_resource(command.name(), command.resource())
+    }
+
+    /// Fails if the command is not allowed, or the challenge record it follows a cname to
+    pub fn check_delegated(&self, client: &mut Client, command: &SubCommand) -> Result<()> {
+        self.check(command)?;
+        match client.delegated_record(command)? {
+            Some((domain, name)) => self.check_resource(
+                command.name(),
+                Resource::DnsRecord {
+                    domain: &domain,
+                    name: &name,
+                },
+            ),
+            None => Ok(()),
+        }
+    }
+
+    fn check_resource(&self, name: &str, resource: Resource<'_>) -> Result<()> {
+        if resource == Resource::Settings {
+            return Ok(());
+        }
+        if !allowed(&self.commands, name) {
+            return Err(Error::Policy(format!("command {name} not allowed")));
+        }
+        if let Some(domain) = resource.domain()
+            && !allowed(&self.domains, domain)
+        {
+            return Err(Error::Policy(format!("domain {domain} not allowed")));
+        }
+        if let Resource::DnsRecord { name, .. } = resource
+            && !allowed(&self.record_names, name)
+        {
+            return Err(Error::Policy(format!("record name {name} not allowed")));
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for Policy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        toml::from_str(s).map_err(|error| Error::Policy(error.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+    use transip_execute::{Client, Fixture, Memory, TransipCommand};
+    use transip_test_support::{domain, fixture};
+
+    use super::{Policy, matches};
+
+    const CERTBOT: &str = r#"
+commands = ["dns acme-validation-*", "dns list"]
+domains = ["example.nl", "*.example.nl"]
+record-names = ["_acme-challenge"]
+"#;
+
+    fn check(policy: &Policy, line: &str) -> bool {
+        let command = line.parse::<TransipCommand>().unwrap().command;
+        policy.check(&command).is_ok()
+    }
+
+    #[test]
+    fn pattern() {
+        assert!(matches("*", ""));
+        assert!(matches("*.example.nl", "www.example.nl"));
+        assert!(!matches("*.example.nl", "example.nl"));
+        assert!(matches("dns *", "dns list"));
+        assert!(matches("a*b*c", "aXXbYYc"));
+        assert!(!matches("a*b*c", "aXXcYYb"));
+    }
+
+    #[test]
+    fn certbot() {
+        let policy = CERTBOT.parse::<Policy>().unwrap();
+        assert!(check(&policy, "dns acme-validation-set example.nl abc"));
+        assert!(check(&policy, "dns acme-validation-delete sub.example.nl"));
+        assert!(check(&policy, "onerror exit"));
+        assert!(!check(&policy, "dns acme-validation-set other.nl abc"));
+        assert!(!check(&policy, "dns insert example.nl www 300 A 1.2.3.4"));
+        assert!(!check(&policy, "vps reset vps1"));
+    }
+
+    #[test]
+    fn record_names() {
+        let policy = "record-names = [\"_acme-challenge*\"]"
+            .parse::<Policy>()
+            .unwrap();
+        assert!(check(
+            &policy,
+            "dns insert example.nl _acme-challenge.www 60 TXT abc"
+        ));
+        assert!(!check(&policy, "dns delete example.nl www 300 A 1.2.3.4"));
+        assert!(check(&policy, "vps reset vps1"));
+    }
+
+    #[test]
+    fn delegated() {
+        let mut client = Client::new(Memory::from(fixture::<Fixture>(json!({
+            "dns": {
+                "example.nl": [
+                    {"name": "_acme-challenge", "expire": 300, "type": "CNAME", "content": "_acme-challenge.example.nl.acme.example.net."},
+                ],
+                "acme.example.net": [],
+            },
+            "domains": [domain("example.nl"), domain("acme.example.net")],
+        }))));
+        let mut check = |policy: &str, line: &str| {
+            let policy = policy.parse::<Policy>().unwrap();
+            let command = line.parse::<TransipCommand>().unwrap().command;
+            policy.check_delegated(&mut client, &command).is_ok()
+        };
+        let set = "dns acme-validation-set example.nl abc --follow-cname";
+        assert!(!check(CERTBOT, set));
+        assert!(check(CERTBOT, "dns acme-validation-set example.nl abc"));
+        assert!(check(
+            "domains = [\"example.nl\", \"acme.example.net\"]\nrecord-names = [\"_acme-challenge*\"]",
+            set
+        ));
+        assert!(!check(
+            "domains = [\"example.nl\", \"acme.example.net\"]\nrecord-names = [\"_acme-challenge\"]",
+            set
+        ));
+    }
+
+    #[test]
+    fn invalid() {
+        assert!("unknown = []".parse::<Policy>().is_err());
+        assert_eq!("".parse::<Policy>().unwrap(), Policy::default());
+    }
+}
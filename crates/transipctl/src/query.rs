@@ -0,0 +1,208 @@
+use std::{
+    iter::Peekable,
+    str::{Chars, FromStr},
+};
+
+use serde_json::Value;
+
+use crate::error::Error;
+
+/// One step in a query path
+#[derive(Debug, PartialEq)]
+enum Segment {
+    Field(String),
+    Index(i64),
+    Project { flatten: bool },
+}
+
+/// Path expression applied to the json result of a command
+///
+/// Supports a JMESPath like subset: `name`, `a.b`, `[0]`, `[-1]`, `[*]` and `[]`.
+/// After a projection the rest of the path is applied to every element
+/// and null results are left out. A leading `.` is accepted for jq users.
+#[derive(Debug, PartialEq)]
+pub struct Query(Vec<Segment>);
+
+impl Query {
+    pub fn apply(&self, value: &Value) -> Value {
+        apply(&self.0, value)
+    }
+}
+
+fn apply(segments: &[Segment], value: &Value) -> Value {
+    match segments.split_first() {
+        None => value.clone(),
+        Some((Segment::Field(name), rest)) => value
+            .get(name)
+            .map_or(Value::Null, |value| apply(rest, value)),
+        Some((Segment::Index(index), rest)) => match value {
+            Value::Array(items) => {
+                let index = if *index < 0 {
+                    items.len().checked_sub(index.unsigned_abs() as usize)
+                } else {
+                    Some(*index as usize)
+                };
+                index
+                    .and_then(|index| items.get(index))
+                    .map_or(Value::Null, |value| apply(rest, value))
+            }
+            _ => Value::Null,
+        },
+        Some((Segment::Project { flatten }, rest)) => match value {
+            Value::Array(items) => Value::Array(
+                items
+                    .iter()
+                    .flat_map(|item| match item {
+                        Value::Array(inner) if *flatten => inner.clone(),
+                        _ => vec![item.clone()],
+                    })
+                    .map(|item| apply(rest, &item))
+                    .filter(|item| !item.is_null())
+                    .collect(),
+            ),
+            _ => Value::Null,
+        },
+    }
+}
+
+fn invalid(expression: &str, reason: &str) -> Error {
+    Error::Query(format!("{reason} in '{expression}'"))
+}
+
+fn field(chars: &mut Peekable<Chars<'_>>) -> String {
+    let mut name = String::new();
+    while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || *c == '_' || *c == '-') {
+        name.push(c);
+    }
+    name
+}
+
+fn quoted_field(chars: &mut Peekable<Chars<'_>>, expression: &str) -> Result<String, Error> {
+    let mut name = String::new();
+    loop {
+        match chars.next() {
+            Some('"') => return Ok(name),
+            Some(c) => name.push(c),
+            None => return Err(invalid(expression, "unterminated quote")),
+        }
+    }
+}
+
+fn bracket(chars: &mut Peekable<Chars<'_>>, expression: &str) -> Result<Segment, Error> {
+    let mut inner = String::new();
+    loop {
+        match chars.next() {
+            Some(']') => break,
+            Some(c) => inner.push(c),
+            None => return Err(invalid(expression, "missing ]")),
+        }
+    }
+    match inner.trim() {
+        "" => Ok(Segment::Project { flatten: true }),
+        "*" => Ok(Segment::Project { flatten: false }),
+        number => number
+            .parse::<i64>()
+            .map(Segment::Index)
+            .map_err(|_| invalid(expression, "invalid index")),
+    }
+}
+
+impl FromStr for Query {
+    type Err = Error;
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        let mut segments = vec![];
+        let trimmed = expression.trim();
+        let mut chars = trimmed
+            .strip_prefix('.')
+            .unwrap_or(trimmed)
+            .chars()
+            .peekable();
+        let mut expect_field = true;
+        while let Some(c) = chars.peek().copied() {
+            match c {
+                '[' => {
+                    chars.next();
+                    segments.push(bracket(&mut chars, expression)?);
+                    expect_field = false;
+                }
+                '.' if !expect_field => {
+                    chars.next();
+                    expect_field = true;
+                    if chars.peek().is_none() {
+                        return Err(invalid(expression, "field missing after ."));
+                    }
+                }
+                '"' if expect_field => {
+                    chars.next();
+                    segments.push(Segment::Field(quoted_field(&mut chars, expression)?));
+                    expect_field = false;
+                }
+                '@' if expect_field => {
+                    chars.next();
+                    expect_field = false;
+                }
+                _ if expect_field => {
+                    let name = field(&mut chars);
+                    if name.is_empty() {
+                        return Err(invalid(expression, &format!("unexpected '{c}'")));
+                    }
+                    segments.push(Segment::Field(name));
+                    expect_field = false;
+                }
+                _ => return Err(invalid(expression, &format!("unexpected '{c}'"))),
+            }
+        }
+        Ok(Self(segments))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::Query;
+
+    fn query(expression: &str) -> Query {
+        expression.parse::<Query>().unwrap()
+    }
+
+    #[test]
+    fn projection() {
+        let value = json!([{"name": "vps1"}, {"name": "vps2"}, {"other": 1}]);
+        assert_eq!(query("[].name").apply(&value), json!(["vps1", "vps2"]));
+        assert_eq!(query(".[*].name").apply(&value), json!(["vps1", "vps2"]));
+        assert_eq!(query("[0].name").apply(&value), json!("vps1"));
+        assert_eq!(query("[-1]").apply(&value), json!({"other": 1}));
+        assert_eq!(query("[5]").apply(&value), json!(null));
+    }
+
+    #[test]
+    fn fields() {
+        let value =
+            json!({"renewalDate": "2025-01-01", "nameservers": [{"hostname": "ns0"}], "a-b": 1});
+        assert_eq!(query("renewalDate").apply(&value), json!("2025-01-01"));
+        assert_eq!(
+            query("nameservers[].hostname").apply(&value),
+            json!(["ns0"])
+        );
+        assert_eq!(query("\"a-b\"").apply(&value), json!(1));
+        assert_eq!(query("@").apply(&value), value);
+        assert_eq!(query("missing.field").apply(&value), json!(null));
+    }
+
+    #[test]
+    fn flatten() {
+        let value = json!([[1, 2], [3]]);
+        assert_eq!(query("[]").apply(&value), json!([1, 2, 3]));
+        assert_eq!(query("[*]").apply(&value), json!([[1, 2], [3]]));
+    }
+
+    #[test]
+    fn invalid() {
+        assert!("[".parse::<Query>().is_err());
+        assert!("[x]".parse::<Query>().is_err());
+        assert!("name.".parse::<Query>().is_err());
+        assert!("a b".parse::<Query>().is_err());
+    }
+}
@@ -0,0 +1,946 @@
+//! Dynamic updates of rfc 2136, translated into dns insert and delete commands
+
+use std::{
+    collections::BTreeSet,
+    io::{Read, Write},
+    net::{
+        IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, TcpListener, TcpStream, UdpSocket,
+    },
+    sync::mpsc::{self, Sender},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use transip_execute::{
+    Client, CommandOutput, DnsCommand, DnsEntry, DomainCommand, RecordType, SubCommand,
+    dns::{self, read_name, u16_at},
+};
+
+use crate::{
+    Result,
+    audit::{Audit, Outcome},
+    policy::Policy,
+    tsig::{self, Keys, Signature},
+};
+
+/// Default address of `serve rfc2136`, a port that needs no privileges
+pub const LISTEN: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 5353));
+const RESPONSE: u16 = 0x8000;
+const OPCODE: u16 = 0x7800;
+const UPDATE: u16 = 5 << 11;
+const SOA: u16 = 6;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+const CLASS_NONE: u16 = 254;
+const CLASS_ANY: u16 = 255;
+const HEADER: usize = 12;
+const MAX_MESSAGE: usize = 65535;
+/// Time a tcp client gets to send the next message, or the rest of one
+const TCP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Response codes of rfc 1035 and rfc 2136
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Rcode {
+    NoError = 0,
+    FormErr = 1,
+    ServFail = 2,
+    NxDomain = 3,
+    NotImp = 4,
+    Refused = 5,
+    YxDomain = 6,
+    YxRrset = 7,
+    NxRrset = 8,
+    NotAuth = 9,
+    NotZone = 10,
+}
+
+type Reply<T> = std::result::Result<T, Rcode>;
+
+/// Resource record of an update, its rdata is read from the message when needed
+#[derive(Debug)]
+struct Record {
+    name: String,
+    record_type: u16,
+    class: u16,
+    ttl: u32,
+    /// Position and length of the rdata
+    rdata: usize,
+    length: usize,
+    /// Position of the record
+    start: usize,
+}
+
+#[derive(Debug)]
+struct Update {
+    zone: String,
+    /// End of the zone section, which is repeated in the response
+    zone_end: usize,
+    prerequisites: Vec<Record>,
+    updates: Vec<Record>,
+    additional: Vec<Record>,
+}
+
+fn read_record(message: &[u8], start: usize) -> Reply<(Record, usize)> {
+    let (name, position) = read_name(message, start).map_err(|_| Rcode::FormErr)?;
+    let number = |offset| u16_at(message, position + offset).map_err(|_| Rcode::FormErr);
+    let length = number(8)? as usize;
+    let rdata = position + 10;
+    if message.len() < rdata + length {
+        return Err(Rcode::FormErr);
+    }
+    let record = Record {
+        name,
+        record_type: number(0)?,
+        class: number(2)?,
+        ttl: u32::from(number(4)?) << 16 | u32::from(number(6)?),
+        rdata,
+        length,
+        start,
+    };
+    Ok((record, rdata + length))
+}
+
+fn parse(message: &[u8]) -> Reply<Update> {
+    let count = |offset| u16_at(message, offset).map_err(|_| Rcode::FormErr);
+    if count(4)? != 1 {
+        return Err(Rcode::FormErr);
+    }
+    let (zone, position) = read_name(message, HEADER).map_err(|_| Rcode::FormErr)?;
+    if u16_at(message, position).ok() != Some(SOA)
+        || u16_at(message, position + 2).ok() != Some(CLASS_IN)
+    {
+        return Err(Rcode::FormErr);
+    }
+    let zone_end = position + 4;
+    let mut position = zone_end;
+    let mut sections = vec![];
+    for offset in [6, 8, 10] {
+        let mut records = vec![];
+        for _ in 0..count(offset)? {
+            let (record, next) = read_record(message, position)?;
+            records.push(record);
+            position = next;
+        }
+        sections.push(records);
+    }
+    let additional = sections.pop().unwrap_or_default();
+    let updates = sections.pop().unwrap_or_default();
+    let prerequisites = sections.pop().unwrap_or_default();
+    Ok(Update {
+        zone,
+        zone_end,
+        prerequisites,
+        updates,
+        additional,
+    })
+}
+
+/// Header and zone section of the request, with rcode
+fn response(message: &[u8], zone_end: Option<usize>, rcode: Rcode) -> Vec<u8> {
+    let flags = RESPONSE | u16_at(message, 2).unwrap_or_default() & OPCODE | rcode as u16;
+    let mut response = message[..2].to_vec();
+    response.extend(flags.to_be_bytes());
+    response.extend(u16::from(zone_end.is_some()).to_be_bytes());
+    response.extend([0; 6]);
+    if let Some(zone_end) = zone_end {
+        response.extend(&message[HEADER..zone_end]);
+    }
+    response
+}
+
+fn record_type(entry_type: &str) -> Option<RecordType> {
+    match entry_type {
+        "A" => Some(RecordType::A),
+        "AAAA" => Some(RecordType::AAAA),
+        "CNAME" => Some(RecordType::CNAME),
+        "MX" => Some(RecordType::MX),
+        "NS" => Some(RecordType::NS),
+        "TXT" => Some(RecordType::TXT),
+        "SRV" => Some(RecordType::SRV),
+        _ => None,
+    }
+}
+
+fn type_code(record_type: RecordType) -> u16 {
+    match record_type {
+        RecordType::A => dns::A,
+        RecordType::AAAA => dns::AAAA,
+        RecordType::CNAME => dns::CNAME,
+        RecordType::MX => dns::MX,
+        RecordType::NS => dns::NS,
+        RecordType::TXT => dns::TXT,
+        RecordType::SRV => dns::SRV,
+    }
+}
+
+/// Record type and content of the rdata, in the form transip uses
+fn content(message: &[u8], record: &Record) -> Reply<(RecordType, String)> {
+    let data = &message[record.rdata..record.rdata + record.length];
+    let number = |offset| u16_at(data, offset).map_err(|_| Rcode::FormErr);
+    let name = |offset| {
+        read_name(message, record.rdata + offset)
+            .map(|(name, _)| format!("{name}."))
+            .map_err(|_| Rcode::FormErr)
+    };
+    match record.record_type {
+        dns::A => <[u8; 4]>::try_from(data)
+            .map(|octets| (RecordType::A, Ipv4Addr::from(octets).to_string()))
+            .map_err(|_| Rcode::FormErr),
+        dns::AAAA => <[u8; 16]>::try_from(data)
+            .map(|octets| (RecordType::AAAA, Ipv6Addr::from(octets).to_string()))
+            .map_err(|_| Rcode::FormErr),
+        dns::CNAME => Ok((RecordType::CNAME, name(0)?)),
+        dns::NS => Ok((RecordType::NS, name(0)?)),
+        dns::MX => Ok((RecordType::MX, format!("{} {}", number(0)?, name(2)?))),
+        dns::TXT => {
+            let mut text = vec![];
+            let mut rest = data;
+            while let Some((length, tail)) = rest.split_first() {
+                let chunk = tail.get(..*length as usize).ok_or(Rcode::FormErr)?;
+                text.extend(chunk);
+                rest = &tail[chunk.len()..];
+            }
+            Ok((RecordType::TXT, String::from_utf8_lossy(&text).into_owned()))
+        }
+        dns::SRV => Ok((
+            RecordType::SRV,
+            format!("{} {} {} {}", number(0)?, number(2)?, number(4)?, name(6)?),
+        )),
+        _ => Err(Rcode::NotImp),
+    }
+}
+
+/// Name relative to zone, `@` for the zone itself
+fn relative(name: &str, zone: &str) -> Reply<String> {
+    if name == zone {
+        return Ok("@".to_owned());
+    }
+    name.strip_suffix(zone)
+        .and_then(|prefix| prefix.strip_suffix('.'))
+        .filter(|prefix| !prefix.is_empty())
+        .map(str::to_owned)
+        .ok_or(Rcode::NotZone)
+}
+
+/// Content that is equal for equal records, addresses are formatted and names made absolute
+fn normalized(entry: &DnsEntry, zone: &str) -> String {
+    match entry.r#type {
+        RecordType::A | RecordType::AAAA => entry
+            .content
+            .parse::<IpAddr>()
+            .map(|address| address.to_string())
+            .unwrap_or_else(|_| entry.content.clone()),
+        RecordType::TXT => entry.content.clone(),
+        _ => {
+            let mut words = entry
+                .content
+                .split_whitespace()
+                .map(str::to_lowercase)
+                .collect::<Vec<_>>();
+            if let Some(target) = words.last_mut() {
+                *target = match target.strip_suffix('.') {
+                    Some(absolute) => absolute.to_owned(),
+                    None if target == "@" => zone.to_owned(),
+                    None => format!("{target}.{zone}"),
+                };
+            }
+            words.join(" ")
+        }
+    }
+}
+
+fn same_rrset(a: &DnsEntry, b: &DnsEntry) -> bool {
+    a.name.eq_ignore_ascii_case(&b.name) && a.r#type == b.r#type
+}
+
+fn same(a: &DnsEntry, b: &DnsEntry, zone: &str) -> bool {
+    same_rrset(a, b) && normalized(a, zone) == normalized(b, zone)
+}
+
+fn entry(zone: &str, name: String, ttl: u32, (r#type, content): (RecordType, String)) -> DnsEntry {
+    DnsEntry {
+        domain: zone.to_owned(),
+        name,
+        ttl,
+        r#type,
+        content,
+    }
+}
+
+/// Fails with the rcode of rfc 2136 section 3.2 if a prerequisite does not hold for entries
+fn check_prerequisites(update: &Update, message: &[u8], entries: &[DnsEntry]) -> Reply<()> {
+    let zone = &update.zone;
+    let mut rrsets = vec![];
+    for record in &update.prerequisites {
+        let name = relative(&record.name, zone)?;
+        if record.ttl != 0 {
+            return Err(Rcode::FormErr);
+        }
+        let mut at_name = entries
+            .iter()
+            .filter(|entry| entry.name.eq_ignore_ascii_case(&name));
+        let of_type = |entry: &DnsEntry| type_code(entry.r#type) == record.record_type;
+        match (record.class, record.record_type) {
+            (CLASS_ANY | CLASS_NONE, _) if record.length != 0 => return Err(Rcode::FormErr),
+            (CLASS_ANY, TYPE_ANY) if at_name.next().is_none() => return Err(Rcode::NxDomain),
+            (CLASS_ANY, TYPE_ANY) => {}
+            (CLASS_ANY, _) if !at_name.any(of_type) => return Err(Rcode::NxRrset),
+            (CLASS_NONE, TYPE_ANY) if at_name.next().is_some() => return Err(Rcode::YxDomain),
+            (CLASS_NONE, TYPE_ANY) => {}
+            (CLASS_NONE, _) if at_name.any(of_type) => return Err(Rcode::YxRrset),
+            (CLASS_ANY | CLASS_NONE, _) => {}
+            (CLASS_IN, _) => rrsets.push(entry(zone, name, 0, content(message, record)?)),
+            _ => return Err(Rcode::FormErr),
+        }
+    }
+    for rrset in &rrsets {
+        let contents = |entries: &[DnsEntry]| {
+            entries
+                .iter()
+                .filter(|entry| same_rrset(entry, rrset))
+                .map(|entry| normalized(entry, zone))
+                .collect::<BTreeSet<_>>()
+        };
+        if contents(entries) != contents(&rrsets) {
+            return Err(Rcode::NxRrset);
+        }
+    }
+    Ok(())
+}
+
+/// Commands that make the changes of the update section to entries
+///
+/// Adding a record that exists is ignored, as are deletes of the nameservers of the zone.
+fn plan(update: &Update, message: &[u8], mut entries: Vec<DnsEntry>) -> Reply<Vec<SubCommand>> {
+    let zone = &update.zone;
+    let mut commands = vec![];
+    for record in &update.updates {
+        let name = relative(&record.name, zone)?;
+        let deleted = match record.class {
+            CLASS_IN => {
+                let entry = entry(zone, name, record.ttl, content(message, record)?);
+                if !entries.iter().any(|existing| same(existing, &entry, zone)) {
+                    entries.push(entry.clone());
+                    commands.push(SubCommand::Dns(DnsCommand::Insert(entry)));
+                }
+                continue;
+            }
+            CLASS_ANY | CLASS_NONE if record.ttl != 0 => return Err(Rcode::FormErr),
+            CLASS_ANY if record.length != 0 => return Err(Rcode::FormErr),
+            CLASS_ANY => entries
+                .extract_if(.., |entry| {
+                    entry.name.eq_ignore_ascii_case(&name)
+                        && (record.record_type == TYPE_ANY
+                            || type_code(entry.r#type) == record.record_type)
+                        && !(name == "@" && entry.r#type == RecordType::NS)
+                })
+                .collect::<Vec<_>>(),
+            CLASS_NONE => {
+                let target = entry(zone, name, 0, content(message, record)?);
+                entries
+                    .extract_if(.., |entry| {
+                        same(entry, &target, zone)
+                            && !(target.name == "@" && entry.r#type == RecordType::NS)
+                    })
+                    .collect()
+            }
+            _ => return Err(Rcode::FormErr),
+        };
+        commands.extend(
+            deleted
+                .into_iter()
+                .map(|entry| SubCommand::Dns(DnsCommand::Delete(entry))),
+        );
+    }
+    Ok(commands)
+}
+
+/// Command that reverts an executed command of an update
+fn undo(command: &SubCommand) -> Option<SubCommand> {
+    match command {
+        SubCommand::Dns(DnsCommand::Insert(entry)) => {
+            Some(SubCommand::Dns(DnsCommand::Delete(entry.clone())))
+        }
+        SubCommand::Dns(DnsCommand::Delete(entry)) => {
+            Some(SubCommand::Dns(DnsCommand::Insert(entry.clone())))
+        }
+        _ => None,
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Answers updates signed with one of the keys, with the policy and audit log of the other commands
+pub struct Rfc2136<'a> {
+    client: &'a mut Client,
+    policy: Option<&'a Policy>,
+    audit: &'a Audit,
+    keys: &'a Keys,
+}
+
+impl Rfc2136<'_> {
+    pub fn new<'a>(
+        client: &'a mut Client,
+        policy: Option<&'a Policy>,
+        audit: &'a Audit,
+        keys: &'a Keys,
+    ) -> Rfc2136<'a> {
+        Rfc2136 {
+            client,
+            policy,
+            audit,
+            keys,
+        }
+    }
+
+    fn read(&mut self, command: SubCommand) -> Reply<CommandOutput> {
+        self.client.execute_output(&command).map_err(|error| {
+            tracing::error!("Failed to execute {}: {error}", command.name());
+            Rcode::ServFail
+        })
+    }
+
+    fn entries(&mut self, zone: &str) -> Reply<Vec<DnsEntry>> {
+        match self.read(SubCommand::Dns(DnsCommand::List {
+            domain: zone.to_owned(),
+        }))? {
+            CommandOutput::DnsEntries(entries) => Ok(entries
+                .into_iter()
+                .filter_map(|entry| {
+                    let r#type = record_type(&entry.entry_type)?;
+                    Some(self::entry(
+                        zone,
+                        entry.name,
+                        entry.expire,
+                        (r#type, entry.content),
+                    ))
+                })
+                .collect()),
+            _ => Err(Rcode::ServFail),
+        }
+    }
+
+    /// Fails with NOTAUTH if the zone is not a domain of the account
+    fn check_zone(&mut self, zone: &str) -> Reply<()> {
+        match self.read(SubCommand::Domain(DomainCommand::List))? {
+            CommandOutput::Domains(domains) if domains.iter().any(|domain| domain.name == zone) => {
+                Ok(())
+            }
+            _ => Err(Rcode::NotAuth),
+        }
+    }
+
+    /// Executes command and writes it to the audit log
+    fn execute(&mut self, command: &SubCommand) -> transip_execute::Result<CommandOutput> {
+        let output = self.client.execute_output(command);
+        if let Err(error) = self.audit.executed(None, command, &output) {
+            tracing::error!("Failed to write audit log: {error}");
+        }
+        output
+    }
+
+    /// Reverts the executed commands in reverse order, the api has no transactions
+    fn revert(&mut self, executed: &[SubCommand]) {
+        for command in executed.iter().rev().filter_map(undo) {
+            match self.execute(&command) {
+                Ok(_) => tracing::info!("Reverted with {}", command.name()),
+                Err(error) => tracing::error!("Failed to revert with {}: {error}", command.name()),
+            }
+        }
+    }
+
+    /// Executes the commands of the update, none of them if the policy refuses one
+    ///
+    /// All commands are planned before the first is executed,
+    /// if one fails the ones executed before it are reverted.
+    fn apply(&mut self, update: &Update, message: &[u8]) -> Reply<()> {
+        self.check_zone(&update.zone)?;
+        let entries = self.entries(&update.zone)?;
+        check_prerequisites(update, message, &entries)?;
+        let commands = plan(update, message, entries)?;
+        if let Some(policy) = self.policy {
+            for command in &commands {
+                if let Err(error) = policy.check(command) {
+                    tracing::warn!("Refused {}: {error}", command.name());
+                    let reason = Some(error.to_string());
+                    if let Err(error) = self.audit.skipped(None, command, Outcome::Refused, reason)
+                    {
+                        tracing::error!("Failed to write audit log: {error}");
+                    }
+                    return Err(Rcode::Refused);
+                }
+            }
+        }
+        for (index, command) in commands.iter().enumerate() {
+            if let Err(error) = self.execute(command) {
+                tracing::error!("Failed to execute {}: {error}", command.name());
+                self.revert(&commands[..index]);
+                return Err(Rcode::ServFail);
+            }
+        }
+        tracing::info!("Updated {} with {} changes", update.zone, commands.len());
+        Ok(())
+    }
+
+    /// Response to a request, none if the request is not worth answering
+    pub fn handle(&mut self, message: &[u8]) -> Option<Vec<u8>> {
+        let flags = u16_at(message, 2).ok()?;
+        if message.len() < HEADER || flags & RESPONSE != 0 {
+            return None;
+        }
+        if flags & OPCODE != UPDATE {
+            return Some(response(message, None, Rcode::NotImp));
+        }
+        let update = match parse(message) {
+            Ok(update) => update,
+            Err(rcode) => return Some(response(message, None, rcode)),
+        };
+        let reply = |rcode| response(message, Some(update.zone_end), rcode);
+        let Some(signature) = update
+            .additional
+            .last()
+            .filter(|record| record.record_type == tsig::TSIG)
+            .and_then(|record| Signature::parse(message, record.start))
+        else {
+            tracing::warn!("Refused unsigned update of {}", update.zone);
+            return Some(reply(Rcode::Refused));
+        };
+        let (keys, now) = (self.keys, now());
+        let key = match signature.verify(keys, message) {
+            Ok(key) => key,
+            Err(error) => {
+                tracing::warn!(
+                    "Update of {} with key {}: tsig error {error}",
+                    update.zone,
+                    signature.key()
+                );
+                let mut response = reply(Rcode::NotAuth);
+                signature.unsigned(&mut response, error);
+                return Some(response);
+            }
+        };
+        let (rcode, error) = if !signature.in_time(now) {
+            tracing::warn!("Update of {} signed at a bad time", update.zone);
+            (Rcode::NotAuth, tsig::BADTIME)
+        } else if !key.allows(&update.zone) {
+            tracing::warn!("Key {} can not update {}", key.name, update.zone);
+            (Rcode::Refused, 0)
+        } else {
+            tracing::info!("Update of {} signed with key {}", update.zone, key.name);
+            (
+                self.apply(&update, message).err().unwrap_or(Rcode::NoError),
+                0,
+            )
+        };
+        let mut response = reply(rcode);
+        signature.sign(key, &mut response, now, error);
+        Some(response)
+    }
+
+    /// Answers updates on udp and tcp at address until the process is stopped
+    ///
+    /// Requests are received on other threads, but handled one at a time by the client.
+    pub fn serve(&mut self, address: SocketAddr) -> Result<()> {
+        let udp = UdpSocket::bind(address)?;
+        let tcp = TcpListener::bind(address)?;
+        let (requests, received) = mpsc::channel();
+        let udp_requests = requests.clone();
+        std::thread::spawn(move || serve_udp(udp, udp_requests));
+        std::thread::spawn(move || serve_tcp(tcp, requests));
+        tracing::info!("Listening for rfc 2136 updates on {address}");
+        for (message, reply) in received {
+            if let Some(response) = self.handle(&message) {
+                let _ = reply.send(response);
+            }
+        }
+        Ok(())
+    }
+}
+
+type Request = (Vec<u8>, Sender<Vec<u8>>);
+
+/// Passes message to the thread that handles requests and waits for the response
+fn exchange(requests: &Sender<Request>, message: Vec<u8>) -> Option<Vec<u8>> {
+    let (reply, response) = mpsc::channel();
+    requests.send((message, reply)).ok()?;
+    response.recv().ok()
+}
+
+fn serve_udp(socket: UdpSocket, requests: Sender<Request>) {
+    let mut buffer = vec![0u8; MAX_MESSAGE];
+    while let Ok((length, peer)) = socket.recv_from(&mut buffer) {
+        if let Some(response) = exchange(&requests, buffer[..length].to_vec()) {
+            let _ = socket.send_to(&response, peer);
+        }
+    }
+}
+
+fn serve_tcp(listener: TcpListener, requests: Sender<Request>) {
+    for stream in listener.incoming().flatten() {
+        let requests = requests.clone();
+        std::thread::spawn(move || serve_connection(stream, requests));
+    }
+}
+
+/// Messages on tcp are preceded by their length, an idle or slow client is disconnected
+fn serve_connection(mut stream: TcpStream, requests: Sender<Request>) {
+    if stream.set_read_timeout(Some(TCP_TIMEOUT)).is_err()
+        || stream.set_write_timeout(Some(TCP_TIMEOUT)).is_err()
+    {
+        return;
+    }
+    let mut length = [0u8; 2];
+    while stream.read_exact(&mut length).is_ok() {
+        let mut message = vec![0u8; u16::from_be_bytes(length) as usize];
+        if stream.read_exact(&mut message).is_err() {
+            return;
+        }
+        let Some(response) = exchange(&requests, message) else {
+            return;
+        };
+        let mut framed = (response.len() as u16).to_be_bytes().to_vec();
+        framed.extend(response);
+        if stream.write_all(&framed).is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+    use transip_execute::{Client, CommandOutput, Fixture, Memory, Replay, TransipCommand, dns};
+    use transip_test_support::{TempDir, domain, fixture};
+
+    use super::{CLASS_ANY, CLASS_IN, CLASS_NONE, Rcode, Rfc2136, TYPE_ANY, now};
+    use crate::{audit::Audit, policy::Policy, tsig::Keys};
+
+    const KEYS: &str = r#"
+[[keys]]
+name = "update"
+secret = "dXBkYXRlLWtleQ=="
+
+[[keys]]
+name = "other"
+secret = "b3RoZXI="
+zones = ["example.com"]
+"#;
+
+    /// Record of the prerequisite or update section
+    struct Rr {
+        name: &'static str,
+        class: u16,
+        record_type: u16,
+        ttl: u32,
+        rdata: Vec<u8>,
+    }
+
+    fn rr(name: &'static str, class: u16, record_type: u16, ttl: u32, rdata: &[u8]) -> Rr {
+        Rr {
+            name,
+            class,
+            record_type,
+            ttl,
+            rdata: rdata.to_vec(),
+        }
+    }
+
+    fn txt(text: &str) -> Vec<u8> {
+        std::iter::once(text.len() as u8)
+            .chain(text.bytes())
+            .collect()
+    }
+
+    fn message(zone: &str, prerequisites: &[Rr], updates: &[Rr]) -> Vec<u8> {
+        let mut message = vec![0xAB, 0xCD, 0x28, 0, 0, 1];
+        message.extend((prerequisites.len() as u16).to_be_bytes());
+        message.extend((updates.len() as u16).to_be_bytes());
+        message.extend([0, 0]);
+        dns::push_name(&mut message, zone);
+        message.extend([0, 6, 0, 1]);
+        for rr in prerequisites.iter().chain(updates) {
+            dns::push_name(&mut message, rr.name);
+            message.extend(rr.record_type.to_be_bytes());
+            message.extend(rr.class.to_be_bytes());
+            message.extend(rr.ttl.to_be_bytes());
+            message.extend((rr.rdata.len() as u16).to_be_bytes());
+            message.extend(&rr.rdata);
+        }
+        message
+    }
+
+    fn client() -> Client {
+        Client::new(Memory::from(fixture::<Fixture>(json!({
+            "dns": {
+                "example.nl": [
+                    {"name": "@", "expire": 86400, "type": "NS", "content": "ns0.transip.net."},
+                    {"name": "www", "expire": 300, "type": "A", "content": "1.2.3.4"},
+                    {"name": "www", "expire": 300, "type": "TXT", "content": "old"},
+                ],
+            },
+            "domains": [domain("example.nl")],
+        }))))
+    }
+
+    fn audit(dir: &TempDir) -> Audit {
+        Audit::new(
+            dir.to_path_buf(),
+            "fixture".to_owned(),
+            "serve rfc2136".to_owned(),
+        )
+    }
+
+    /// Rcode and number of additional records of the response to message signed with key
+    fn send(server: &mut Rfc2136<'_>, key: Option<&str>, mut message: Vec<u8>) -> (u8, u8) {
+        if let Some(key) = key {
+            KEYS.parse::<Keys>()
+                .unwrap()
+                .sign_request(key, &mut message, now());
+        }
+        let response = server.handle(&message).unwrap();
+        assert_eq!(response[..2], message[..2]);
+        (response[3] & 0x0F, response[11])
+    }
+
+    fn entries(client: &mut Client) -> Vec<String> {
+        let command = "dns list example.nl".parse::<TransipCommand>().unwrap();
+        match client.execute_output(&command.command).unwrap() {
+            CommandOutput::DnsEntries(entries) => entries
+                .into_iter()
+                .map(|entry| format!("{} {} {}", entry.name, entry.entry_type, entry.content))
+                .collect(),
+            _ => panic!("no dns entries"),
+        }
+    }
+
+    #[test]
+    fn updates() {
+        let dir = TempDir::new("transipctl-rfc2136-updates");
+        let (mut client, audit) = (client(), audit(&dir));
+        let keys = KEYS.parse::<Keys>().unwrap();
+        let mut server = Rfc2136::new(&mut client, None, &audit, &keys);
+        let add = message(
+            "example.nl",
+            &[],
+            &[
+                rr("www.example.nl", CLASS_IN, dns::A, 300, &[5, 6, 7, 8]),
+                rr("www.example.nl", CLASS_IN, dns::A, 300, &[1, 2, 3, 4]),
+                rr(
+                    "_acme-challenge.example.nl",
+                    CLASS_IN,
+                    dns::TXT,
+                    60,
+                    &txt("token"),
+                ),
+                rr("www.example.nl", CLASS_ANY, dns::TXT, 0, &[]),
+            ],
+        );
+        assert_eq!(
+            send(&mut server, Some("update"), add),
+            (Rcode::NoError as u8, 1)
+        );
+        assert_eq!(
+            entries(&mut client),
+            vec![
+                "@ NS ns0.transip.net.",
+                "www A 1.2.3.4",
+                "www A 5.6.7.8",
+                "_acme-challenge TXT token",
+            ]
+        );
+
+        let mut server = Rfc2136::new(&mut client, None, &audit, &keys);
+        let delete = message(
+            "example.nl",
+            &[],
+            &[
+                rr("www.example.nl", CLASS_NONE, dns::A, 0, &[1, 2, 3, 4]),
+                rr("_acme-challenge.example.nl", CLASS_ANY, TYPE_ANY, 0, &[]),
+                rr("example.nl", CLASS_ANY, TYPE_ANY, 0, &[]),
+            ],
+        );
+        assert_eq!(
+            send(&mut server, Some("update"), delete).0,
+            Rcode::NoError as u8
+        );
+        assert_eq!(
+            entries(&mut client),
+            vec!["@ NS ns0.transip.net.", "www A 5.6.7.8"]
+        );
+        assert_eq!(audit.show(None).unwrap().len(), 5);
+    }
+
+    #[test]
+    fn reverted() {
+        let dir = TempDir::new("transipctl-rfc2136-reverted");
+        let audit = audit(&dir);
+        let keys = KEYS.parse::<Keys>().unwrap();
+        // recorded from the fixture: the second insert gets the response of a list, so it fails
+        let mut recording = client();
+        recording.record(dir.join("cassette")).unwrap();
+        for line in [
+            "domain list",
+            "dns list example.nl",
+            "dns insert example.nl new 300 A 9.9.9.9",
+            "dns list example.nl",
+            "dns delete example.nl new 300 A 9.9.9.9",
+        ] {
+            let command = line.parse::<TransipCommand>().unwrap();
+            recording.execute_output(&command.command).unwrap();
+        }
+        drop(recording);
+        let mut client = Client::new(Replay::from_dir(dir.join("cassette")).unwrap());
+        let mut server = Rfc2136::new(&mut client, None, &audit, &keys);
+        let update = message(
+            "example.nl",
+            &[],
+            &[
+                rr("new.example.nl", CLASS_IN, dns::A, 300, &[9, 9, 9, 9]),
+                rr("new.example.nl", CLASS_IN, dns::A, 300, &[8, 8, 8, 8]),
+            ],
+        );
+        assert_eq!(
+            send(&mut server, Some("update"), update).0,
+            Rcode::ServFail as u8
+        );
+        let executed = audit
+            .show(None)
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.command)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            executed,
+            vec![
+                "dns insert example.nl new 300 A 9.9.9.9",
+                "dns insert example.nl new 300 A 8.8.8.8",
+                "dns delete example.nl new 300 A 9.9.9.9",
+            ]
+        );
+    }
+
+    #[test]
+    fn prerequisites() {
+        let dir = TempDir::new("transipctl-rfc2136-prerequisites");
+        let (mut client, audit) = (client(), audit(&dir));
+        let keys = KEYS.parse::<Keys>().unwrap();
+        let mut server = Rfc2136::new(&mut client, None, &audit, &keys);
+        let mut check = |prerequisite: Rr| {
+            let update = rr("new.example.nl", CLASS_IN, dns::A, 300, &[9, 9, 9, 9]);
+            let message = message("example.nl", &[prerequisite], &[update]);
+            send(&mut server, Some("update"), message).0
+        };
+        assert_eq!(
+            check(rr("www.example.nl", CLASS_NONE, TYPE_ANY, 0, &[])),
+            Rcode::YxDomain as u8
+        );
+        assert_eq!(
+            check(rr("www.example.nl", CLASS_ANY, dns::MX, 0, &[])),
+            Rcode::NxRrset as u8
+        );
+        assert_eq!(
+            check(rr("www.example.nl", CLASS_NONE, dns::TXT, 0, &[])),
+            Rcode::YxRrset as u8
+        );
+        assert_eq!(
+            check(rr("gone.example.nl", CLASS_ANY, TYPE_ANY, 0, &[])),
+            Rcode::NxDomain as u8
+        );
+        assert_eq!(
+            check(rr("www.example.nl", CLASS_IN, dns::A, 0, &[9, 9, 9, 9])),
+            Rcode::NxRrset as u8
+        );
+        assert_eq!(
+            check(rr("www.example.nl", CLASS_IN, dns::A, 0, &[1, 2, 3, 4])),
+            Rcode::NoError as u8
+        );
+        assert_eq!(entries(&mut client).len(), 4);
+    }
+
+    #[test]
+    fn refused() {
+        let dir = TempDir::new("transipctl-rfc2136-refused");
+        let (mut client, audit) = (client(), audit(&dir));
+        let keys = KEYS.parse::<Keys>().unwrap();
+        let policy = "record-names = [\"_acme-challenge*\"]"
+            .parse::<Policy>()
+            .unwrap();
+        let mut server = Rfc2136::new(&mut client, Some(&policy), &audit, &keys);
+        let insert = |zone, name| {
+            message(
+                zone,
+                &[],
+                &[rr(name, CLASS_IN, dns::TXT, 60, &txt("token"))],
+            )
+        };
+        let acme = || insert("example.nl", "_acme-challenge.example.nl");
+        assert_eq!(send(&mut server, None, acme()), (Rcode::Refused as u8, 0));
+        assert_eq!(
+            send(&mut server, Some("other"), acme()),
+            (Rcode::Refused as u8, 1)
+        );
+        let mut unknown = acme();
+        KEYS.replace("update", "unknown")
+            .parse::<Keys>()
+            .unwrap()
+            .sign_request("unknown", &mut unknown, now());
+        assert_eq!(send(&mut server, None, unknown), (Rcode::NotAuth as u8, 1));
+        let mut late = acme();
+        keys.sign_request("update", &mut late, now() - 3600);
+        assert_eq!(send(&mut server, None, late), (Rcode::NotAuth as u8, 1));
+        assert_eq!(
+            send(
+                &mut server,
+                Some("update"),
+                insert("example.com", "example.com")
+            )
+            .0,
+            Rcode::NotAuth as u8
+        );
+        assert_eq!(
+            send(
+                &mut server,
+                Some("update"),
+                insert("example.nl", "www.example.com")
+            )
+            .0,
+            Rcode::NotZone as u8
+        );
+        assert_eq!(
+            send(
+                &mut server,
+                Some("update"),
+                insert("example.nl", "www.example.nl")
+            )
+            .0,
+            Rcode::Refused as u8
+        );
+        let caa = message(
+            "example.nl",
+            &[],
+            &[rr(
+                "example.nl",
+                CLASS_IN,
+                257,
+                300,
+                &[0, 5, b'i', b's', b's', b'u', b'e'],
+            )],
+        );
+        assert_eq!(
+            send(&mut server, Some("update"), caa).0,
+            Rcode::NotImp as u8
+        );
+        let mut query = acme();
+        query[2] = 0;
+        assert_eq!(send(&mut server, None, query).0, Rcode::NotImp as u8);
+        assert_eq!(
+            send(&mut server, Some("update"), acme()).0,
+            Rcode::NoError as u8
+        );
+        assert_eq!(entries(&mut client).len(), 4);
+    }
+}
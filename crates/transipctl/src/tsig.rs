@@ -0,0 +1,394 @@
+//! Transaction signatures of rfc 8945, shared keys that sign dynamic updates and their responses
+
+use std::{path::Path, str::FromStr};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use hmac::{Hmac, Mac, digest::KeyInit};
+use serde::{Deserialize, Deserializer};
+use sha2::{Sha256, Sha512};
+use transip_execute::dns::{push_name, read_name, u16_at};
+
+use crate::{Result, error::Error, policy::matches};
+
+pub const TSIG: u16 = 250;
+const CLASS_ANY: u16 = 255;
+pub const BADSIG: u16 = 16;
+pub const BADKEY: u16 = 17;
+pub const BADTIME: u16 = 18;
+const MIN_MAC: usize = 10;
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+pub enum Algorithm {
+    #[default]
+    #[serde(rename = "hmac-sha256")]
+    HmacSha256,
+    #[serde(rename = "hmac-sha512")]
+    HmacSha512,
+}
+
+impl Algorithm {
+    fn name(self) -> &'static str {
+        match self {
+            Algorithm::HmacSha256 => "hmac-sha256",
+            Algorithm::HmacSha512 => "hmac-sha512",
+        }
+    }
+
+    fn mac(self, secret: &[u8], data: &[u8]) -> Vec<u8> {
+        fn mac<M: Mac + KeyInit>(secret: &[u8], data: &[u8]) -> Vec<u8> {
+            let mut mac =
+                <M as KeyInit>::new_from_slice(secret).expect("hmac takes keys of any size");
+            mac.update(data);
+            mac.finalize().into_bytes().to_vec()
+        }
+        match self {
+            Algorithm::HmacSha256 => mac::<Hmac<Sha256>>(secret, data),
+            Algorithm::HmacSha512 => mac::<Hmac<Sha512>>(secret, data),
+        }
+    }
+
+    /// True if expected is the mac of data, or a truncation of it that rfc 8945 accepts
+    fn verify(self, secret: &[u8], data: &[u8], expected: &[u8]) -> bool {
+        let mac = self.mac(secret, data);
+        expected.len() <= mac.len()
+            && expected.len() >= MIN_MAC.max(mac.len() / 2)
+            && mac
+                .iter()
+                .zip(expected)
+                .fold(0, |difference, (a, b)| difference | (a ^ b))
+                == 0
+    }
+}
+
+fn secret<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Vec<u8>, D::Error> {
+    let secret = String::deserialize(deserializer)?;
+    STANDARD
+        .decode(secret.trim())
+        .map_err(|error| serde::de::Error::custom(format!("secret is not base64: {error}")))
+}
+
+fn name(name: &str) -> String {
+    name.trim_end_matches('.').to_lowercase()
+}
+
+/// Shared key, a `[[keys]]` table in the tsig keys file
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Key {
+    pub name: String,
+    #[serde(default)]
+    pub algorithm: Algorithm,
+    /// Base64 encoded secret, like the one tsig-keygen generates
+    #[serde(deserialize_with = "secret")]
+    secret: Vec<u8>,
+    /// Zones the key can update, like `example.nl` or `*.example.nl`, all zones if left out
+    zones: Option<Vec<String>>,
+}
+
+impl Key {
+    pub fn allows(&self, zone: &str) -> bool {
+        self.zones
+            .as_ref()
+            .is_none_or(|zones| zones.iter().any(|pattern| matches(pattern, zone)))
+    }
+}
+
+/// Keys loaded from a toml file, updates signed with any of them are accepted
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Keys {
+    #[serde(default)]
+    keys: Vec<Key>,
+}
+
+impl Keys {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        text.parse()
+    }
+
+    fn find(&self, key_name: &str, algorithm: &str) -> Option<&Key> {
+        self.keys
+            .iter()
+            .find(|key| name(&key.name) == key_name && key.algorithm.name() == algorithm)
+    }
+}
+
+impl FromStr for Keys {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        toml::from_str(s).map_err(|error| Error::Tsig(error.to_string()))
+    }
+}
+
+fn push_u48(buffer: &mut Vec<u8>, value: u64) {
+    buffer.extend(&value.to_be_bytes()[2..]);
+}
+
+/// Tsig record at the end of a message
+#[derive(Clone, Debug, PartialEq)]
+pub struct Signature {
+    key: String,
+    algorithm: String,
+    /// Seconds since the epoch
+    time: u64,
+    fudge: u16,
+    mac: Vec<u8>,
+    original_id: u16,
+    error: u16,
+    other: Vec<u8>,
+    /// Position of the record in the message
+    start: usize,
+}
+
+impl Signature {
+    /// Reads the tsig record that starts at position start of message
+    pub fn parse(message: &[u8], start: usize) -> Option<Self> {
+        let (key, position) = read_name(message, start).ok()?;
+        let length = u16_at(message, position + 8).ok()? as usize;
+        let rdata = position + 10;
+        let end = rdata + length;
+        if u16_at(message, position).ok()? != TSIG || message.len() < end {
+            return None;
+        }
+        let (algorithm, position) = read_name(message, rdata).ok()?;
+        let time = message
+            .get(position..position + 6)?
+            .iter()
+            .fold(0u64, |time, byte| time << 8 | u64::from(*byte));
+        let fudge = u16_at(message, position + 6).ok()?;
+        let mac_size = u16_at(message, position + 8).ok()? as usize;
+        let mac = message
+            .get(position + 10..position + 10 + mac_size)?
+            .to_vec();
+        let position = position + 10 + mac_size;
+        let other_length = u16_at(message, position + 4).ok()? as usize;
+        let other = message
+            .get(position + 6..position + 6 + other_length)?
+            .to_vec();
+        (position + 6 + other_length == end).then(|| Self {
+            key: name(&key),
+            algorithm: name(&algorithm),
+            time,
+            fudge,
+            mac,
+            original_id: u16_at(message, position).unwrap_or_default(),
+            error: u16_at(message, position + 2).unwrap_or_default(),
+            other,
+            start,
+        })
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Variables of rfc 8945 section 4.3.3 that are signed after the message
+    fn push_variables(&self, buffer: &mut Vec<u8>, time: u64, error: u16, other: &[u8]) {
+        push_name(buffer, &self.key);
+        buffer.extend(CLASS_ANY.to_be_bytes());
+        buffer.extend(0u32.to_be_bytes());
+        push_name(buffer, &self.algorithm);
+        push_u48(buffer, time);
+        buffer.extend(self.fudge.to_be_bytes());
+        buffer.extend(error.to_be_bytes());
+        buffer.extend((other.len() as u16).to_be_bytes());
+        buffer.extend(other);
+    }
+
+    /// Key that signed message, or the tsig error to respond with unsigned
+    pub fn verify<'a>(&self, keys: &'a Keys, message: &[u8]) -> std::result::Result<&'a Key, u16> {
+        let key = keys.find(&self.key, &self.algorithm).ok_or(BADKEY)?;
+        let mut data = message[..self.start].to_vec();
+        data[..2].copy_from_slice(&self.original_id.to_be_bytes());
+        let additional = u16_at(&data, 10).map_err(|_| BADSIG)?;
+        data[10..12].copy_from_slice(&additional.saturating_sub(1).to_be_bytes());
+        self.push_variables(&mut data, self.time, self.error, &self.other);
+        if key.algorithm.verify(&key.secret, &data, &self.mac) {
+            Ok(key)
+        } else {
+            Err(BADSIG)
+        }
+    }
+
+    /// True if the message was signed within fudge seconds of now
+    pub fn in_time(&self, now: u64) -> bool {
+        now.abs_diff(self.time) <= u64::from(self.fudge)
+    }
+
+    fn push_record(&self, response: &mut Vec<u8>, time: u64, mac: &[u8], error: u16, other: &[u8]) {
+        let mut rdata = vec![];
+        push_name(&mut rdata, &self.algorithm);
+        push_u48(&mut rdata, time);
+        rdata.extend(self.fudge.to_be_bytes());
+        rdata.extend((mac.len() as u16).to_be_bytes());
+        rdata.extend(mac);
+        rdata.extend(self.original_id.to_be_bytes());
+        rdata.extend(error.to_be_bytes());
+        rdata.extend((other.len() as u16).to_be_bytes());
+        rdata.extend(other);
+
+        push_name(response, &self.key);
+        response.extend(TSIG.to_be_bytes());
+        response.extend(CLASS_ANY.to_be_bytes());
+        response.extend(0u32.to_be_bytes());
+        response.extend((rdata.len() as u16).to_be_bytes());
+        response.extend(rdata);
+        let additional = u16_at(response, 10).unwrap_or_default() + 1;
+        response[10..12].copy_from_slice(&additional.to_be_bytes());
+    }
+
+    /// Signs response to the request with this signature, with error BADTIME the time of the server is added
+    pub fn sign(&self, key: &Key, response: &mut Vec<u8>, now: u64, error: u16) {
+        let (time, other) = if error == BADTIME {
+            let mut other = vec![];
+            push_u48(&mut other, now);
+            (self.time, other)
+        } else {
+            (now, vec![])
+        };
+        let mut data = (self.mac.len() as u16).to_be_bytes().to_vec();
+        data.extend(&self.mac);
+        data.extend(response.iter());
+        self.push_variables(&mut data, time, error, &other);
+        let mac = key.algorithm.mac(&key.secret, &data);
+        self.push_record(response, time, &mac, error, &other);
+    }
+
+    /// Adds a tsig record without mac to a response to a request with an unknown key or bad signature
+    pub fn unsigned(&self, response: &mut Vec<u8>, error: u16) {
+        self.push_record(response, self.time, &[], error, &[]);
+    }
+}
+
+#[cfg(test)]
+impl Keys {
+    /// Signs request with the key named key_name, like a client would
+    pub fn sign_request(&self, key_name: &str, request: &mut Vec<u8>, time: u64) {
+        let key = self
+            .keys
+            .iter()
+            .find(|key| name(&key.name) == key_name)
+            .unwrap();
+        let signature = Signature {
+            key: key_name.to_owned(),
+            algorithm: key.algorithm.name().to_owned(),
+            time,
+            fudge: 300,
+            mac: vec![],
+            original_id: u16_at(request, 0).unwrap(),
+            error: 0,
+            other: vec![],
+            start: request.len(),
+        };
+        let mut data = request.clone();
+        signature.push_variables(&mut data, time, 0, &[]);
+        let mac = key.algorithm.mac(&key.secret, &data);
+        signature.push_record(request, time, &mac, 0, &[]);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{BADKEY, BADSIG, BADTIME, Keys, Signature};
+
+    const KEYS: &str = r#"
+[[keys]]
+name = "certbot."
+algorithm = "hmac-sha256"
+secret = "c2VjcmV0LWtleS1mb3ItdGVzdHM="
+zones = ["example.nl"]
+
+[[keys]]
+name = "other"
+algorithm = "hmac-sha512"
+secret = "b3RoZXI="
+"#;
+
+    #[test]
+    fn keys() {
+        let keys = KEYS.parse::<Keys>().unwrap();
+        let certbot = keys.find("certbot", "hmac-sha256").unwrap();
+        assert!(certbot.allows("example.nl"));
+        assert!(!certbot.allows("example.com"));
+        assert!(
+            keys.find("other", "hmac-sha512")
+                .unwrap()
+                .allows("example.com")
+        );
+        assert!(keys.find("other", "hmac-sha256").is_none());
+        assert!(
+            "[[keys]]\nname = \"a\"\nsecret = \"not base64!\""
+                .parse::<Keys>()
+                .is_err()
+        );
+        assert!("unknown = 1".parse::<Keys>().is_err());
+    }
+
+    #[test]
+    fn verify_and_sign() {
+        let keys = KEYS.parse::<Keys>().unwrap();
+        let header = [0x12, 0x34, 0x28, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        let mut request = header.to_vec();
+        keys.sign_request("certbot", &mut request, 1_000_000);
+        let signature = Signature::parse(&request, 12).unwrap();
+        assert_eq!(signature.key(), "certbot");
+        let key = signature.verify(&keys, &request).unwrap();
+        assert_eq!(key.name, "certbot.");
+        assert!(signature.in_time(1_000_300));
+        assert!(!signature.in_time(1_000_301));
+
+        let mut tampered = request.clone();
+        tampered[3] = 1;
+        assert_eq!(signature.verify(&keys, &tampered).unwrap_err(), BADSIG);
+        let mut other = header.to_vec();
+        keys.sign_request("other", &mut other, 1_000_000);
+        let signature = Signature::parse(&other, 12).unwrap();
+        assert!(signature.verify(&keys, &other).is_ok());
+        let position = other.iter().position(|byte| *byte == b'o').unwrap();
+        other[position] = b'x';
+        let signature = Signature::parse(&other, 12).unwrap();
+        assert_eq!(signature.verify(&keys, &other).unwrap_err(), BADKEY);
+
+        let signature = Signature::parse(&request, 12).unwrap();
+        let mut response = vec![0x12, 0x34, 0xA8, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        signature.sign(key, &mut response, 1_000_001, 0);
+        let signed = Signature::parse(&response, 12).unwrap();
+        assert_eq!(signed.time, 1_000_001);
+        assert_eq!(signed.mac.len(), 32);
+        assert_eq!(response[11], 1);
+
+        let mut response = response[..12].to_vec();
+        response[11] = 0;
+        signature.sign(key, &mut response, 2_000_000, BADTIME);
+        let signed = Signature::parse(&response, 12).unwrap();
+        assert_eq!(signed.time, 1_000_000);
+        assert_eq!(signed.error, BADTIME);
+        assert_eq!(signed.other, vec![0, 0, 0, 0x1E, 0x84, 0x80]);
+    }
+
+    /// Update adding `_acme-challenge.example.nl. 60 TXT "abc"` with compressed names,
+    /// signed with the key certbot at 1700000000 by a signer written apart from this module
+    const SIGNED_UPDATE: &str = "4d2b28000001000000010001076578616d706c65026e6c00000600010f5f61636d652d\
+        6368616c6c656e6765c00c001000010000003c0004036162630763657274626f740000fa00ff00000000003d0b\
+        686d61632d7368613235360000006553f100012c0020e9d1aa76baf346dad520a4c8e07d01fdaa479422df1acb\
+        c1d32619973dceef824d2b00000000";
+
+    #[test]
+    fn known_answer() {
+        let keys = KEYS.parse::<Keys>().unwrap();
+        let message = (0..SIGNED_UPDATE.len())
+            .step_by(2)
+            .map(|index| u8::from_str_radix(&SIGNED_UPDATE[index..index + 2], 16).unwrap())
+            .collect::<Vec<_>>();
+        let signature = Signature::parse(&message, 60).unwrap();
+        assert_eq!(signature.key(), "certbot");
+        assert_eq!(signature.time, 1_700_000_000);
+        assert_eq!(signature.verify(&keys, &message).unwrap().name, "certbot.");
+
+        let mut tampered = message.clone();
+        tampered[57] = b'x';
+        assert_eq!(signature.verify(&keys, &tampered).unwrap_err(), BADSIG);
+    }
+}